@@ -0,0 +1,345 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rusty_handlebars_language_server::{project_diagnostics, ProjectIndex};
+use rusty_handlebars_parser::{
+    add_builtins, parse_partial, parse_template, BlockMap, BlockSpec, Compiler, Diagnostic, Node,
+    NodeKind, Options, ParseError, Severity, Span,
+};
+
+/// A diagnostic reported for one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub region: Option<Region>,
+}
+
+/// A 1-based line and column, counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a finding points, with the text of its first line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub start: Position,
+    pub end: Position,
+    pub text: String,
+}
+
+impl Region {
+    fn new(source: &str, span: Span) -> Self {
+        let start = span.start.min(source.len());
+        let end = span.end.clamp(start, source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |index| start + index);
+        Self {
+            start: position(source, start),
+            end: position(source, end),
+            text: source[line_start..line_end].to_owned(),
+        }
+    }
+}
+
+fn position(source: &str, offset: usize) -> Position {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// Checks templates against the parser, the compiler, and the project index.
+pub struct Checker {
+    root: PathBuf,
+    project: Option<ProjectIndex>,
+    partials: HashSet<PathBuf>,
+}
+
+impl Checker {
+    /// Creates a checker for `templates`, resolving partials from `root`.
+    ///
+    /// Templates included by another with `{{> "path"}}` are checked as
+    /// partials: their private variables and compiled output depend on where
+    /// they are included, so the including template covers them. Without a
+    /// `project`, field and helper references are not checked.
+    pub fn new(root: PathBuf, project: Option<ProjectIndex>, templates: &[PathBuf]) -> Self {
+        let mut partials = HashSet::new();
+        for template in templates {
+            if let Ok(source) = fs::read_to_string(template) {
+                collect_partials(&parse_template(&source).nodes, &root, &mut partials);
+            }
+        }
+        Self {
+            root,
+            project,
+            partials,
+        }
+    }
+
+    pub fn check_file(&self, path: &Path) -> Vec<Finding> {
+        match fs::read_to_string(path) {
+            Ok(source) => self.check_source(path, &source),
+            Err(error) => vec![Finding {
+                path: path.to_path_buf(),
+                severity: Severity::Error,
+                code: "unreadable-file".to_owned(),
+                message: error.to_string(),
+                region: None,
+            }],
+        }
+    }
+
+    fn check_source(&self, path: &Path, source: &str) -> Vec<Finding> {
+        let contexts = self
+            .project
+            .as_ref()
+            .map_or(&[][..], |project| project.contexts_for(path));
+        let partial = self.partials.contains(&normalize_path(path));
+        let parsed = if partial {
+            parse_partial(source)
+        } else {
+            parse_template(source)
+        };
+        let mut findings = parsed
+            .diagnostics
+            .iter()
+            .map(|diagnostic| finding(path, source, diagnostic))
+            .collect::<Vec<_>>();
+        // The compiler stops at the first syntax error the parser already
+        // reported, so only compile templates that parse cleanly.
+        if !partial
+            && !findings
+                .iter()
+                .any(|finding| finding.severity == Severity::Error)
+        {
+            let mut blocks = BlockMap::new();
+            add_builtins(&mut blocks);
+            for spec in contexts.iter().flat_map(|context| &context.blocks) {
+                // Invalid specs are reported by the derive itself.
+                if let Ok(spec) = BlockSpec::intern(spec) {
+                    blocks.insert(spec.name(), spec);
+                }
+            }
+            let helpers = contexts.iter().flat_map(|context| &context.helpers);
+            let compiled = Compiler::new(
+                Options {
+                    root_var_name: Some("self"),
+                    write_var_name: "f",
+                },
+                blocks,
+            )
+            .with_helper_paths(
+                helpers
+                    .clone()
+                    .map(|helper| (helper.name.clone(), helper.path.clone()))
+                    .collect(),
+            )
+            .with_helper_signatures(
+                helpers
+                    .filter_map(|helper| Some((helper.name.clone(), helper.signature.clone()?)))
+                    .collect(),
+            )
+            .with_partial_loader(self.root.clone())
+            .compile(source);
+            if let Err(error) = compiled {
+                findings.extend(self.compile_findings(path, source, &error));
+            }
+        }
+        findings.extend(
+            project_diagnostics(&parsed, contexts)
+                .into_iter()
+                .map(|diagnostic| Finding {
+                    path: path.to_path_buf(),
+                    severity: Severity::Error,
+                    code: diagnostic.code.to_owned(),
+                    message: diagnostic.message,
+                    region: Some(Region::new(source, diagnostic.span)),
+                }),
+        );
+        findings
+    }
+
+    fn compile_findings(&self, path: &Path, source: &str, error: &ParseError) -> Vec<Finding> {
+        if error.diagnostics().is_empty() {
+            return vec![Finding {
+                path: path.to_path_buf(),
+                severity: Severity::Error,
+                code: "compile-error".to_owned(),
+                message: error.to_string(),
+                region: None,
+            }];
+        }
+        let partial = error.partial().map(|partial| self.root.join(partial));
+        let partial_source = partial
+            .as_deref()
+            .and_then(|partial| fs::read_to_string(partial).ok());
+        let (path, source) = match (&partial, &partial_source) {
+            (Some(partial), Some(partial_source)) => (partial.as_path(), partial_source.as_str()),
+            _ => (path, source),
+        };
+        error
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| finding(path, source, diagnostic))
+            .collect()
+    }
+}
+
+fn collect_partials(nodes: &[Node<'_>], root: &Path, partials: &mut HashSet<PathBuf>) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Partial { path, .. } => {
+                partials.insert(normalize_path(&root.join(path)));
+            }
+            NodeKind::Block(block) => {
+                for body in block.bodies() {
+                    collect_partials(body, root, partials);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn normalize_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn finding(path: &Path, source: &str, diagnostic: &Diagnostic) -> Finding {
+    Finding {
+        path: path.to_path_buf(),
+        severity: diagnostic.severity,
+        code: diagnostic.code.as_str().to_owned(),
+        message: diagnostic.message.clone(),
+        region: Some(Region::new(source, diagnostic.span)),
+    }
+}
+
+/// Returns whether `path` names a `.rhbs` or `.hbs` template.
+pub fn is_template(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "rhbs" || extension == "hbs")
+}
+
+/// Collects the templates under `path` in a stable order.
+///
+/// Hidden directories and `target` are skipped. A file path is checked even
+/// when its extension is not a template extension.
+pub fn collect_templates(path: &Path, output: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        output.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            let skipped = entry
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with('.') || name == "target");
+            if !skipped {
+                collect_templates(&entry, output)?;
+            }
+        } else if is_template(&entry) {
+            output.push(entry);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_parse_and_compile_errors() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("ok.rhbs"), "<p>{{name}}</p>").unwrap();
+        fs::write(root.path().join("broken.rhbs"), "{{#if a}}\n  {{/each}}").unwrap();
+        fs::write(
+            root.path().join("missing.hbs"),
+            "{{#each items}}{{> \"row.rhbs\"}}{{/each}}{{> \"nope.rhbs\"}}",
+        )
+        .unwrap();
+        fs::write(root.path().join("row.rhbs"), "{{@index}}").unwrap();
+        fs::create_dir(root.path().join("target")).unwrap();
+        fs::write(root.path().join("target/skipped.rhbs"), "{{").unwrap();
+        let mut templates = Vec::new();
+        collect_templates(root.path(), &mut templates).unwrap();
+        assert_eq!(
+            templates
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["broken.rhbs", "missing.hbs", "ok.rhbs", "row.rhbs"]
+        );
+        let checker = Checker::new(root.path().to_path_buf(), None, &templates);
+        let broken = checker.check_file(&templates[0]);
+        assert_eq!(broken[0].code, "mismatched-block");
+        assert_eq!(
+            broken[0].region.as_ref().unwrap().start,
+            Position { line: 2, column: 6 }
+        );
+        let missing = checker.check_file(&templates[1]);
+        assert_eq!(missing[0].code, "unresolved-partial");
+        assert!(checker.check_file(&templates[2]).is_empty());
+        assert!(checker.check_file(&templates[3]).is_empty());
+    }
+
+    #[test]
+    fn compiles_with_declared_helpers() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(
+            root.path().join("Cargo.toml"),
+            "[package]\nname = \"site\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        fs::create_dir(root.path().join("src")).unwrap();
+        fs::write(
+            root.path().join("src/lib.rs"),
+            r#"
+            #[derive(WithRustyHandlebars)]
+            #[template(path = "post.rhbs", helpers = ["helpers::truncate", "crate::pad(text, width = 8)"])]
+            struct Post { body: String }
+            "#,
+        )
+        .unwrap();
+        let template = root.path().join("post.rhbs");
+        fs::write(
+            &template,
+            "{{truncate body length=40}} {{pad body width=4}} {{pad body size=4}}",
+        )
+        .unwrap();
+        let project = ProjectIndex::discover(root.path()).unwrap();
+        let checker = Checker::new(root.path().to_path_buf(), Some(project), &[]);
+        let findings = checker.check_file(&template);
+        assert_eq!(findings.len(), 1, "{findings:?}");
+        assert!(
+            findings[0].message.contains("pad has no parameter size"),
+            "{findings:?}"
+        );
+    }
+
+    #[test]
+    fn counts_columns_in_characters() {
+        let region = Region::new("é\nab{{x}}", Span::new(5, 10));
+        assert_eq!(region.start, Position { line: 2, column: 3 });
+        assert_eq!(region.end, Position { line: 2, column: 8 });
+        assert_eq!(region.text, "ab{{x}}");
+    }
+}
@@ -0,0 +1,76 @@
+use std::{fs, io, path::PathBuf};
+
+use rusty_handlebars_parser::{format_template, is_plain_text, FormatOptions};
+
+use crate::check::collect_templates;
+
+pub struct FmtArgs {
+    pub check: bool,
+    pub options: FormatOptions,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Formats the templates under `args.paths`, or lists the unformatted ones
+/// with `--check`.
+///
+/// Plain-text templates are skipped, since their indentation is part of what
+/// they render. Returns whether every template could be formatted and, with
+/// `--check`, already was.
+pub fn fmt(args: FmtArgs) -> io::Result<bool> {
+    let mut templates = Vec::new();
+    for path in &args.paths {
+        collect_templates(path, &mut templates)?;
+    }
+    let mut ok = true;
+    for template in templates.iter().filter(|template| !is_plain_text(template)) {
+        let source = fs::read_to_string(template)?;
+        let label = template.display().to_string();
+        match format_template(&source, &args.options) {
+            Ok(formatted) if formatted == source => {}
+            Ok(_) if args.check => {
+                println!("{label}");
+                ok = false;
+            }
+            Ok(formatted) => fs::write(template, formatted)?,
+            Err(error) => {
+                eprintln!("{}", error.render(&label, &source));
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_or_checks_templates() {
+        let root = tempfile::tempdir().unwrap();
+        let page = root.path().join("page.rhbs");
+        let broken = root.path().join("broken.rhbs");
+        let text = root.path().join("mail.txt.rhbs");
+        fs::write(&text, "{{#if a}}\n  {{ b }}\n{{/if}}\n").unwrap();
+        fs::write(&page, "{{#if a}}\n{{ b }}\n{{/if}}\n").unwrap();
+        let args = |check| FmtArgs {
+            check,
+            options: FormatOptions::default(),
+            paths: vec![root.path().to_path_buf()],
+        };
+        assert!(!fmt(args(true)).unwrap());
+        assert!(fmt(args(false)).unwrap());
+        assert_eq!(
+            fs::read_to_string(&page).unwrap(),
+            "{{#if a}}\n    {{b}}\n{{/if}}\n"
+        );
+        assert!(fmt(args(true)).unwrap());
+        assert_eq!(
+            fs::read_to_string(&text).unwrap(),
+            "{{#if a}}\n  {{ b }}\n{{/if}}\n"
+        );
+        fs::write(&broken, "{{#if a}}").unwrap();
+        assert!(!fmt(args(false)).unwrap());
+        assert_eq!(fs::read_to_string(&broken).unwrap(), "{{#if a}}");
+    }
+}
@@ -0,0 +1,186 @@
+mod check;
+mod fmt;
+mod report;
+
+use std::{io, path::PathBuf, process::ExitCode};
+
+use rusty_handlebars_language_server::ProjectIndex;
+use rusty_handlebars_parser::{FormatOptions, Severity};
+
+use check::{collect_templates, Checker};
+use fmt::FmtArgs;
+use report::Format;
+
+const USAGE: &str = "usage: rhbs check [--format human|json|sarif] [--root DIR] [PATH...]
+       rhbs fmt [--check] [--indent N] [--tabs] [PATH...]
+
+rhbs check checks .rhbs and .hbs templates under each PATH (default: the
+current directory). Partials resolve from --root (default: the current
+directory), and fields and helpers are checked against the structs deriving
+WithRustyHandlebars in the Cargo workspace containing it.
+
+rhbs fmt re-indents block bodies and normalizes spacing inside {{ }},
+rewriting templates in place. With --check, it lists the templates that would
+change instead.
+
+Exits with 1 if any error is reported or, with fmt --check, any template needs
+formatting, and 2 if the command could not run.";
+
+enum Command {
+    Check(CheckArgs),
+    Fmt(FmtArgs),
+}
+
+struct CheckArgs {
+    format: Format,
+    root: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args(command: &str, mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut format = Format::Human;
+    let mut root = PathBuf::from(".");
+    let mut fmt = FmtArgs {
+        check: false,
+        options: FormatOptions::default(),
+        paths: Vec::new(),
+    };
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{name} requires a value"))
+        };
+        match (command, arg.as_str()) {
+            ("check", "--format") => format = value("--format")?.parse()?,
+            ("check", "--root") => root = value("--root")?.into(),
+            ("fmt", "--check") => fmt.check = true,
+            ("fmt", "--indent") => {
+                fmt.options.indent_width = value("--indent")?
+                    .parse()
+                    .map_err(|_| "--indent expects a number".to_owned())?;
+            }
+            ("fmt", "--tabs") => fmt.options.use_tabs = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+            _ => paths.push(arg.into()),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+    match command {
+        "check" => Ok(Command::Check(CheckArgs {
+            format,
+            root,
+            paths,
+        })),
+        _ => Ok(Command::Fmt(FmtArgs { paths, ..fmt })),
+    }
+}
+
+fn check(args: CheckArgs) -> io::Result<bool> {
+    let project = match ProjectIndex::discover(&args.root) {
+        Ok(project) => Some(project),
+        Err(error) => {
+            eprintln!("rhbs: skipping field and helper checks: {error}");
+            None
+        }
+    };
+    let mut templates = Vec::new();
+    for path in &args.paths {
+        collect_templates(path, &mut templates).map_err(|error| {
+            io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+        })?;
+    }
+    let checker = Checker::new(args.root, project, &templates);
+    let findings = templates
+        .iter()
+        .flat_map(|template| checker.check_file(template))
+        .collect::<Vec<_>>();
+    report::write(
+        args.format,
+        &findings,
+        templates.len(),
+        &mut io::stdout().lock(),
+    )?;
+    Ok(findings
+        .iter()
+        .all(|finding| finding.severity != Severity::Error))
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let command = match args.next() {
+        Some(command) if command == "check" || command == "fmt" => command,
+        Some(flag) if flag == "--version" => {
+            println!("rhbs {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Some(flag) if flag == "--help" || flag == "-h" => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let result = match parse_args(&command, args) {
+        Ok(Command::Check(args)) => check(args),
+        Ok(Command::Fmt(args)) => fmt::fmt(args),
+        Err(error) => {
+            eprintln!("rhbs: {error}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("rhbs: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &str, args: &[&str]) -> Result<Command, String> {
+        parse_args(command, args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_check_arguments() {
+        let Ok(Command::Check(parsed)) = args(
+            "check",
+            &["--format", "sarif", "templates", "--root", "site"],
+        ) else {
+            panic!("expected check arguments");
+        };
+        assert_eq!(parsed.format, Format::Sarif);
+        assert_eq!(parsed.root, PathBuf::from("site"));
+        assert_eq!(parsed.paths, [PathBuf::from("templates")]);
+        assert!(args("check", &["--format"]).is_err());
+        assert!(args("check", &["--tabs"]).is_err());
+    }
+
+    #[test]
+    fn parses_fmt_arguments() {
+        let Ok(Command::Fmt(parsed)) = args("fmt", &["--check", "--indent", "2", "--tabs"]) else {
+            panic!("expected fmt arguments");
+        };
+        assert!(parsed.check);
+        assert_eq!(
+            parsed.options,
+            FormatOptions {
+                indent_width: 2,
+                use_tabs: true
+            }
+        );
+        assert_eq!(parsed.paths, [PathBuf::from(".")]);
+        assert!(args("fmt", &["--indent", "two"]).is_err());
+        assert!(args("fmt", &["--root", "site"]).is_err());
+    }
+}
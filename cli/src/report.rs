@@ -0,0 +1,236 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use rusty_handlebars_parser::Severity;
+use serde_json::{json, Value};
+
+use crate::check::{Finding, Region};
+
+/// How `rhbs check` prints its findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Compiler-style messages with the source line and a caret.
+    Human,
+    /// A JSON array with one object per finding.
+    Json,
+    /// A SARIF 2.1.0 log for code-scanning tools.
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, String> {
+        match format {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            _ => Err(format!(
+                "unknown format `{format}`, expected human, json, or sarif"
+            )),
+        }
+    }
+}
+
+/// Writes `findings` for `files` checked templates.
+pub fn write(
+    format: Format,
+    findings: &[Finding],
+    files: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        Format::Human => write_human(findings, files, out),
+        Format::Json => {
+            let findings = findings.iter().map(json_finding).collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut *out, &findings)?;
+            writeln!(out)
+        }
+        Format::Sarif => {
+            serde_json::to_writer_pretty(&mut *out, &sarif(findings))?;
+            writeln!(out)
+        }
+    }
+}
+
+fn display_path(path: &Path) -> String {
+    path.strip_prefix(".")
+        .unwrap_or(path)
+        .display()
+        .to_string()
+        .replace('\\', "/")
+}
+
+fn severity(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    }
+}
+
+fn write_human(findings: &[Finding], files: usize, out: &mut impl Write) -> io::Result<()> {
+    for finding in findings {
+        writeln!(
+            out,
+            "{}[{}]: {}",
+            severity(finding.severity),
+            finding.code,
+            finding.message
+        )?;
+        let path = display_path(&finding.path);
+        let Some(region) = &finding.region else {
+            writeln!(out, " --> {path}\n")?;
+            continue;
+        };
+        let line = region.start.line.to_string();
+        let gutter = " ".repeat(line.len());
+        let pad = region
+            .text
+            .chars()
+            .take(region.start.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let width = if region.end.line == region.start.line {
+            region.end.column.saturating_sub(region.start.column)
+        } else {
+            region.text.chars().count() + 1 - region.start.column
+        };
+        writeln!(
+            out,
+            "{gutter}--> {path}:{line}:{column}\n{gutter} |\n{line} | {text}\n{gutter} | {pad}{carets}\n",
+            column = region.start.column,
+            text = region.text,
+            carets = "^".repeat(width.max(1)),
+        )?;
+    }
+    let count = |wanted| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == wanted)
+            .count()
+    };
+    let plural =
+        |count: usize, noun: &str| format!("{count} {noun}{}", if count == 1 { "" } else { "s" });
+    writeln!(
+        out,
+        "{}, {} in {}",
+        plural(count(Severity::Error), "error"),
+        plural(count(Severity::Warning), "warning"),
+        plural(files, "template")
+    )
+}
+
+fn json_finding(finding: &Finding) -> Value {
+    json!({
+        "path": display_path(&finding.path),
+        "severity": severity(finding.severity),
+        "code": finding.code,
+        "message": finding.message,
+        "range": finding.region.as_ref().map(|region| json!({
+            "start": { "line": region.start.line, "column": region.start.column },
+            "end": { "line": region.end.line, "column": region.end.column },
+        })),
+    })
+}
+
+fn sarif(findings: &[Finding]) -> Value {
+    let rules = findings
+        .iter()
+        .map(|finding| finding.code.as_str())
+        .collect::<BTreeSet<_>>();
+    let results = findings
+        .iter()
+        .map(|finding| {
+            let mut location = json!({
+                "artifactLocation": { "uri": display_path(&finding.path) },
+            });
+            if let Some(region) = &finding.region {
+                location["region"] = sarif_region(region);
+            }
+            json!({
+                "ruleId": finding.code,
+                "level": severity(finding.severity),
+                "message": { "text": finding.message },
+                "locations": [{ "physicalLocation": location }],
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rhbs",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules.into_iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+fn sarif_region(region: &Region) -> Value {
+    json!({
+        "startLine": region.start.line,
+        "startColumn": region.start.column,
+        "endLine": region.end.line,
+        "endColumn": region.end.column,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::Position;
+
+    fn findings() -> Vec<Finding> {
+        vec![Finding {
+            path: "./templates/page.rhbs".into(),
+            severity: Severity::Error,
+            code: "unknown-field".to_owned(),
+            message: "field `nme` does not exist".to_owned(),
+            region: Some(Region {
+                start: Position { line: 3, column: 5 },
+                end: Position { line: 3, column: 8 },
+                text: "  <{{nme}}>".to_owned(),
+            }),
+        }]
+    }
+
+    fn written(format: Format) -> String {
+        let mut out = Vec::new();
+        write(format, &findings(), 2, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats_human_output() {
+        assert_eq!(
+            written(Format::Human),
+            "error[unknown-field]: field `nme` does not exist\n --> templates/page.rhbs:3:5\n  |\n3 |   <{{nme}}>\n  |     ^^^\n\n1 error, 0 warnings in 2 templates\n"
+        );
+    }
+
+    #[test]
+    fn formats_machine_output() {
+        let json: Value = serde_json::from_str(&written(Format::Json)).unwrap();
+        assert_eq!(json[0]["path"], "templates/page.rhbs");
+        assert_eq!(json[0]["range"]["end"]["column"], 8);
+        let sarif: Value = serde_json::from_str(&written(Format::Sarif)).unwrap();
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "unknown-field");
+        let location = &run["results"][0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "templates/page.rhbs");
+        assert_eq!(location["region"]["startColumn"], 5);
+        assert_eq!("human".parse(), Ok(Format::Human));
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
@@ -3,19 +3,26 @@
 //! Applications normally use the macro re-exported by the
 //! `rusty-handlebars` facade crate.
 
-#[cfg(feature = "minify-html")]
-use minify_html::minify;
 use proc_macro::TokenStream;
 use quote::quote;
-use rusty_handlebars_parser::{add_builtins, BlockMap, Compiler, Options};
-use std::collections::HashMap;
+use rusty_handlebars_parser::{
+    add_builtins, is_plain_text, BlockMap, BlockSpec, Compiler, HelperDeclaration, HelperSignature,
+    Options, PartialLoader, AS_DISPLAY, AS_DISPLAY_HTML,
+};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::OnceLock;
 use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
-use syn::{parse_macro_input, DeriveInput, Generics, Ident, LitBool, LitStr, Result, Token};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, Generics, Ident, Index, LitBool, LitStr, Result,
+    Token,
+};
 use toml::Table;
 
 fn discover_path() -> PathBuf {
@@ -62,11 +69,7 @@ fn find_path() -> &'static Path {
 #[cfg(feature = "minify-html")]
 fn minify_template(src: String, enabled: bool) -> String {
     if enabled {
-        String::from_utf8(minify(
-            src.as_bytes(),
-            &rusty_handlebars_parser::build_helper::COMPRESS_CONFIG,
-        ))
-        .expect("minify-html returned invalid UTF-8 for a UTF-8 template")
+        rusty_handlebars_parser::build_helper::minify_template(&src)
     } else {
         src
     }
@@ -77,59 +80,88 @@ fn minify_template(src: String, _enabled: bool) -> String {
     src
 }
 
+/// Reads partials from the template root and records each file read.
+struct TemplateFiles {
+    minify: bool,
+    loaded: Rc<RefCell<BTreeSet<PathBuf>>>,
+}
+
+impl PartialLoader for TemplateFiles {
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        let path = find_path().join(path);
+        let src = std::fs::read_to_string(&path)?;
+        self.loaded.borrow_mut().insert(path);
+        Ok(minify_template(src, self.minify))
+    }
+}
+
+/// Emits an `include_bytes!` for each template file so Cargo rebuilds the
+/// deriving crate when one changes.
+fn track_files(files: &BTreeSet<PathBuf>) -> proc_macro2::TokenStream {
+    let files = files.iter().filter_map(|path| path.to_str());
+    quote! {
+        #(const _: &[u8] = include_bytes!(#files);)*
+    }
+}
+
 struct TemplateArgs {
     src: Option<String>,
-    helpers: Vec<String>,
-    minify: bool,
+    source: Option<LitStr>,
+    escape: Option<LitStr>,
+    helpers: Vec<LitStr>,
+    blocks: Vec<LitStr>,
+    minify: Option<bool>,
 }
 
-fn parse_helpers(input: ParseStream, helpers: &mut Vec<String>) -> Result<()> {
-    let content;
-    syn::bracketed!(content in input);
-    helpers.extend(
-        syn::punctuated::Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?
-            .into_iter()
-            .map(|helper| helper.value()),
-    );
-    Ok(())
+/// Qualified Rust paths and declared parameters for `helpers = [...]`.
+struct Helpers {
+    paths: HashMap<String, String>,
+    signatures: HashMap<String, HelperSignature>,
 }
 
-fn helper_paths(helpers: Vec<String>) -> HashMap<String, String> {
-    helpers
-        .into_iter()
-        .map(|helper| {
-            let name = helper
-                .rsplit("::")
-                .next()
-                .unwrap_or(helper.as_str())
-                .to_string();
-            let path = if helper.starts_with("::")
-                || helper.starts_with("crate::")
-                || helper.starts_with("self::")
-                || helper.starts_with("super::")
-            {
-                helper
-            } else {
-                format!("::rusty_handlebars::{}", helper)
-            };
-            (name, path)
-        })
-        .collect()
+/// Maps each helper's final path segment to its qualified path and, for
+/// declarations like `crate::truncate(text, length = 80)`, its signature.
+fn helper_paths(helpers: &[LitStr]) -> Result<Helpers> {
+    let mut paths = HashMap::new();
+    let mut signatures = HashMap::new();
+    for helper in helpers {
+        let declaration = HelperDeclaration::parse(&helper.value())
+            .map_err(|err| syn::Error::new(helper.span(), err.to_string()))?;
+        if let Some(signature) = declaration.signature {
+            signatures.insert(declaration.name.clone(), signature);
+        }
+        paths.insert(declaration.name, declaration.path);
+    }
+    Ok(Helpers { paths, signatures })
 }
 
 impl Parse for TemplateArgs {
     fn parse(input: ParseStream) -> Result<Self> {
         let mut src: Option<String> = None;
-        let mut minify = true;
-        let mut helpers = Vec::<String>::new();
+        let mut source: Option<LitStr> = None;
+        let mut escape: Option<LitStr> = None;
+        let mut minify = None;
+        let mut helpers = Vec::<LitStr>::new();
+        let mut blocks = Vec::<LitStr>::new();
         loop {
             let ident = input.parse::<Ident>()?;
             let label = ident.to_string();
             input.parse::<Token!(=)>()?;
             match label.as_str() {
-                "minify" => minify = input.parse::<LitBool>()?.value(),
+                "minify" => minify = Some(input.parse::<LitBool>()?.value()),
                 "path" => src = Some(input.parse::<LitStr>()?.value()),
-                "helpers" => parse_helpers(input, &mut helpers)?,
+                "source" => source = Some(input.parse::<LitStr>()?),
+                "escape" => escape = Some(input.parse::<LitStr>()?),
+                "helpers" => {
+                    let content;
+                    syn::bracketed!(content in input);
+                    helpers.extend(Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?);
+                }
+                "blocks" => {
+                    let content;
+                    syn::bracketed!(content in input);
+                    blocks.extend(Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?);
+                }
                 _ => {
                     return Err(syn::Error::new(
                         ident.span(),
@@ -144,16 +176,170 @@ impl Parse for TemplateArgs {
         }
         Ok(TemplateArgs {
             src,
+            source,
+            escape,
             helpers,
+            blocks,
             minify,
         })
     }
 }
 
+/// Parses `blocks = [...]` specs into block factories.
+///
+/// [`BlockMap`] holds `'static` references, so the specs are interned to keep
+/// repeated expansions from leaking a copy each.
+fn block_specs(blocks: &[LitStr]) -> Result<Vec<&'static BlockSpec>> {
+    let mut builtins = BlockMap::new();
+    add_builtins(&mut builtins);
+    let mut specs: Vec<&'static BlockSpec> = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let spec = BlockSpec::intern(&block.value())
+            .map_err(|err| syn::Error::new(block.span(), err.to_string()))?;
+        if builtins.contains_key(spec.name()) || specs.iter().any(|s| s.name() == spec.name()) {
+            return Err(syn::Error::new(
+                block.span(),
+                format!("block `{}` is already defined", spec.name()),
+            ));
+        }
+        specs.push(spec);
+    }
+    Ok(specs)
+}
+
+/// Returns the `Content-Type` of responses rendered from a template path.
+///
+/// The inner extension names the type, as in `feed.xml.rhbs`. Templates
+/// without one, and inline sources, are HTML.
+fn content_type(path: Option<&str>) -> &'static str {
+    let stem = path.and_then(|path| Path::new(path).file_stem().map(Path::new));
+    match stem.and_then(Path::extension).and_then(|ext| ext.to_str()) {
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        _ => "text/html; charset=utf-8",
+    }
+}
+
+/// Returns the function used for `{{value}}`.
+///
+/// An explicit `escape` is `"html"`, `"none"`, or the path of a function that
+/// takes a reference and returns a `Display`. Otherwise templates whose name
+/// has an inner `.txt` or `.md` extension, such as `email.txt.rhbs`, are not
+/// escaped.
+fn escape_path(escape: Option<&LitStr>, path: Option<&str>) -> Result<String> {
+    Ok(match escape.map(LitStr::value).as_deref() {
+        Some("html") => AS_DISPLAY_HTML.to_string(),
+        Some("none") => AS_DISPLAY.to_string(),
+        Some(custom) => {
+            if syn::parse_str::<syn::Path>(custom).is_err() {
+                return Err(syn::Error::new(
+                    escape.unwrap().span(),
+                    "escape must be \"html\", \"none\", or a function path",
+                ));
+            }
+            custom.to_string()
+        }
+        None if path.is_some_and(is_plain_text) => AS_DISPLAY.to_string(),
+        None => AS_DISPLAY_HTML.to_string(),
+    })
+}
+
+/// Implements `Reflect` for a struct, looking its fields up by name.
+///
+/// Tuple struct fields are named by index. Each field goes through a
+/// `Probe`, so fields whose types do not implement `Reflect` read as missing.
+/// Templates also pass `display`, so that a template nested in another one is
+/// written with its `Display` implementation; they may be enums, which expose
+/// no fields.
+fn reflect_impl(
+    name: &Ident,
+    generics: &Generics,
+    data: &Data,
+    display: bool,
+) -> Result<proc_macro2::TokenStream> {
+    let fields = match data {
+        Data::Struct(data) => &data.fields,
+        _ if display => &Fields::Unit,
+        _ => {
+            return Err(syn::Error::new(
+                name.span(),
+                "Reflect can only be derived for structs",
+            ))
+        }
+    };
+    let write = if display {
+        quote! {
+            fn write(
+                &self,
+                out: &mut dyn ::std::fmt::Write,
+                _escape: bool,
+            ) -> ::std::option::Option<::std::fmt::Result> {
+                ::std::option::Option::Some(::std::write!(out, "{}", self))
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+    let arms = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let ident = field.ident.as_ref().unwrap();
+                let key = ident.to_string().trim_start_matches("r#").to_string();
+                quote! { #key => (&::rusty_handlebars::reflect::Probe(&self.#ident)).reflect(), }
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|i| {
+                let index = Index::from(i);
+                let key = i.to_string();
+                quote! { #key => (&::rusty_handlebars::reflect::Probe(&self.#index)).reflect(), }
+            })
+            .collect(),
+        Fields::Unit => proc_macro2::TokenStream::new(),
+    };
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rusty_handlebars::Reflect for #name #type_generics #where_clause {
+            fn field(&self, name: &str) -> ::std::option::Option<&dyn ::rusty_handlebars::Reflect> {
+                #[allow(unused_imports)]
+                use ::rusty_handlebars::reflect::{FallbackProbe as _, ReflectProbe as _};
+                match name {
+                    #arms
+                    _ => ::std::option::Option::None,
+                }
+            }
+            #write
+        }
+    })
+}
+
+/// The template file read at runtime by the `hot-reload` feature.
+struct HotReload {
+    path: String,
+    root: String,
+    escape: bool,
+    minify: bool,
+}
+
 struct DisplayParts {
     name: Ident,
     generics: Generics,
     content: proc_macro2::TokenStream,
+    io_content: proc_macro2::TokenStream,
+    string_content: proc_macro2::TokenStream,
+    async_content: proc_macro2::TokenStream,
+    content_type: &'static str,
+    static_len: usize,
+    tracked: proc_macro2::TokenStream,
+    reflect: proc_macro2::TokenStream,
+    hot_reload: Option<HotReload>,
 }
 
 impl Parse for DisplayParts {
@@ -170,100 +356,455 @@ impl Parse for DisplayParts {
             Some(attr) => attr,
         };
         let args = attr.parse_args::<TemplateArgs>()?;
-        let src = match args.src {
-            None => {
+        let escape = escape_path(args.escape.as_ref(), args.src.as_deref())?;
+        let content_type = content_type(args.src.as_deref());
+        let specs = block_specs(&args.blocks)?;
+        let minify = args
+            .minify
+            .unwrap_or(!args.src.as_deref().is_some_and(is_plain_text));
+        // The interpreter only knows the built-in escaping, so templates with a
+        // custom `escape` function always render compiled.
+        let hot_reload = match (&args.src, escape.as_str()) {
+            (Some(src), AS_DISPLAY_HTML | AS_DISPLAY) if cfg!(feature = "hot-reload") => {
+                Some(HotReload {
+                    path: find_path().join(src).display().to_string(),
+                    root: find_path().display().to_string(),
+                    escape: escape == AS_DISPLAY_HTML,
+                    minify: cfg!(feature = "minify-html") && minify,
+                })
+            }
+            _ => None,
+        };
+        let reflect = if cfg!(feature = "hot-reload") {
+            reflect_impl(&name, &generics, &input.data, true)?
+        } else {
+            proc_macro2::TokenStream::new()
+        };
+        let loaded = Rc::new(RefCell::new(BTreeSet::new()));
+        let (template, span, label) = match (args.src, args.source) {
+            (Some(_), Some(source)) => {
                 return Err(syn::Error::new(
-                    attr.span(),
-                    "missing path attribute in template macro",
+                    source.span(),
+                    "path and source cannot both be set in template macro",
                 ))
             }
-            Some(src) => src,
-        };
-        let path = find_path().join(src);
-        let buf = match std::fs::read_to_string(&path) {
-            Ok(src) => src,
-            Err(err) => {
+            (None, Some(source)) => (source.value(), source.span(), "<source>".to_string()),
+            (Some(src), None) => {
+                let path = find_path().join(&src);
+                match std::fs::read_to_string(&path) {
+                    Ok(template) => {
+                        loaded.borrow_mut().insert(path);
+                        (template, attr.span(), src)
+                    }
+                    Err(err) => {
+                        return Err(syn::Error::new(
+                            attr.span(),
+                            format!("unable to read {path:?}, {err}"),
+                        ))
+                    }
+                }
+            }
+            (None, None) => {
                 return Err(syn::Error::new(
                     attr.span(),
-                    format!("unable to read {path:?}, {err}"),
+                    "missing path or source attribute in template macro",
                 ))
             }
         };
-        let buf = minify_template(buf, args.minify);
-        let mut factories = BlockMap::new();
-        add_builtins(&mut factories);
-        let rust = match Compiler::new(
-            Options {
-                write_var_name: "f",
-                root_var_name: Some("self"),
-            },
-            factories,
-        )
-        .with_helper_paths(helper_paths(args.helpers))
-        .compile(&buf)
-        {
-            Ok(rust) => rust,
-            Err(err) => return Err(syn::Error::new(attr.span(), err.to_string())),
+        let buf = minify_template(template.clone(), minify);
+        let helpers = helper_paths(&args.helpers)?;
+        let compiler = |write_var_name, minify, streaming: bool| {
+            let mut factories = BlockMap::new();
+            add_builtins(&mut factories);
+            for spec in &specs {
+                factories.insert(spec.name(), *spec);
+            }
+            let compiler = Compiler::new(
+                Options {
+                    write_var_name,
+                    root_var_name: Some("self"),
+                },
+                factories,
+            )
+            .with_helper_paths(helpers.paths.clone())
+            .with_helper_signatures(helpers.signatures.clone())
+            .with_escape_path(escape.as_str())
+            .with_partial_loader(TemplateFiles {
+                minify,
+                loaded: loaded.clone(),
+            });
+            if streaming {
+                compiler
+                    .with_write_macro("::rusty_handlebars::write_async!")
+                    .with_block_end(format!("{write_var_name}.flush().await?;"))
+            } else {
+                compiler
+            }
+        };
+        let compile = |write_var_name, streaming| {
+            compiler(write_var_name, minify, streaming)
+                .compile(&buf)
+                .map_err(|err| {
+                    // Minified offsets do not match the file, so report from
+                    // the original source when it fails the same way.
+                    let original = if minify {
+                        compiler(write_var_name, false, streaming)
+                            .compile(&template)
+                            .err()
+                    } else {
+                        None
+                    };
+                    let message = match original {
+                        Some(original) => original.render(&label, &template),
+                        None => err.render(&label, &buf),
+                    };
+                    syn::Error::new(span, message)
+                })
         };
+        let rust = compile("f", false)?;
+        let io_rust = compile("w", false)?;
+        let string_rust = compile("buf", false)?;
+        let async_content = if cfg!(feature = "async") {
+            proc_macro2::token_stream::TokenStream::from_str(&compile("w", true)?.code)?
+        } else {
+            proc_macro2::TokenStream::new()
+        };
+        let tracked = track_files(&loaded.borrow());
         Ok(Self {
             name,
             generics,
             content: proc_macro2::token_stream::TokenStream::from_str(&rust.code)?,
+            io_content: proc_macro2::token_stream::TokenStream::from_str(&io_rust.code)?,
+            string_content: proc_macro2::token_stream::TokenStream::from_str(&string_rust.code)?,
+            async_content,
+            content_type,
+            static_len: rust.static_len,
+            tracked,
+            reflect,
+            hot_reload,
         })
     }
 }
 
 /// Implements template rendering for a struct.
 ///
-/// `#[template(path = "...")]` names the template file. `minify = false`
+/// `#[template(path = "...")]` names the template file, or
+/// `#[template(source = "...")]` supplies the template inline. `escape` selects
+/// how `{{value}}` is written: `"html"`, `"none"`, or a function path. It
+/// defaults to `"none"` for `.txt.rhbs` and `.md.rhbs` files, which are also
+/// not minified unless `minify = true`. `minify = false`
 /// disables the default HTML minification. `helpers = ["crate::helper"]`
-/// maps an inline helper's final path segment to that Rust function path.
+/// maps an inline helper's final path segment to that Rust function path. A
+/// declaration such as `"crate::truncate(text, length = 80)"` also names the
+/// parameters, so calls may pass `length=40` or leave it to the default.
+/// `blocks = ["name: code"]` declares block helpers as Rust code with `{arg}`,
+/// `{this}`, `{body}`, and `{else}` placeholders; see `BlockSpec`.
+/// Partials included with `{{> "path"}}` are resolved from the same root as
+/// the template path. The template and every partial it reads are included
+/// with `include_bytes!`, so editing them rebuilds the deriving crate.
 ///
 /// The generated implementations are `std::fmt::Display`,
 /// `rusty_handlebars::WithRustyHandlebars`, and
-/// `rusty_handlebars::AsDisplay`.
+/// `rusty_handlebars::AsDisplay`. The template is compiled once for each
+/// writer, so `render_to` and `render_into` write directly to an
+/// `io::Write` or `String` instead of going through a `fmt::Formatter`.
+/// With the `async` feature it also implements `rusty_handlebars::AsyncRender`,
+/// which writes to a `tokio::io::AsyncWrite` and flushes after each top-level
+/// block, and requires the template to be `Sync`. The `axum`, `actix-web`,
+/// and `warp` features implement that framework's response trait, with a
+/// `Content-Type` chosen from the template's inner extension. With `hot-reload`, templates also implement
+/// `rusty_handlebars::Reflect`, and debug builds render a `path` template's file at
+/// runtime on every call, falling back to the compiled code for anything the
+/// interpreter does not support.
 #[proc_macro_derive(WithRustyHandlebars, attributes(template))]
 pub fn make_renderable(raw: TokenStream) -> TokenStream {
     let DisplayParts {
         name,
         generics,
         content,
+        io_content,
+        string_content,
+        async_content,
+        content_type,
+        static_len,
+        tracked,
+        reflect,
+        hot_reload,
     } = parse_macro_input!(raw as DisplayParts);
 
     let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    // In debug builds with `hot-reload`, each method first tries rendering the
+    // template file at runtime and runs `write` with the result.
+    let reload = |write: proc_macro2::TokenStream| match &hot_reload {
+        Some(HotReload {
+            path,
+            root,
+            escape,
+            minify,
+        }) => quote! {
+            #[cfg(debug_assertions)]
+            if let ::std::option::Option::Some(rendered) =
+                ::rusty_handlebars::hot_reload::render(#path, #root, #escape, #minify, self)
+            {
+                #write
+            }
+        },
+        None => proc_macro2::TokenStream::new(),
+    };
+    let reload_fmt = reload(quote! { return f.write_str(&rendered); });
+    let reload_io = reload(quote! { return ::std::io::Write::write_all(w, rendered.as_bytes()); });
+    let reload_string = reload(quote! {
+        buf.push_str(&rendered);
+        return Ok(());
+    });
+    let reload_async = reload(quote! { return w.write_rendered(&rendered).await; });
+    let async_impl = if cfg!(feature = "async") {
+        // `AsyncRender` requires `Sync`, so generic templates implement it when
+        // their type parameters are `Sync`. A `Self: Sync` bound, as for warp
+        // below, trips over the lifetimes of the `async fn`.
+        let mut generics = generics.clone();
+        let params = generics
+            .type_params()
+            .map(|param| param.ident.clone())
+            .collect::<Vec<_>>();
+        let predicates = &mut generics.make_where_clause().predicates;
+        for param in params {
+            predicates.push(syn::parse_quote!(#param: ::std::marker::Sync));
+        }
+        let where_clause = &generics.where_clause;
+        quote! {
+            impl #impl_generics ::rusty_handlebars::AsyncRender for #name #type_generics #where_clause {
+                async fn render_async<W: ::rusty_handlebars::AsyncWrite + ::std::marker::Unpin + ::std::marker::Send + ?::std::marker::Sized>(
+                    &self,
+                    w: &mut W,
+                ) -> ::std::io::Result<()> {
+                    let mut w = ::rusty_handlebars::AsyncWriter::new(w);
+                    #reload_async
+                    #async_content
+                    w.flush().await
+                }
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+    let type_name = name.to_string();
+    let mut responses = proc_macro2::TokenStream::new();
+    if cfg!(feature = "axum") {
+        responses.extend(quote! {
+            impl #impl_generics ::rusty_handlebars::web::axum_core::response::IntoResponse for #name #type_generics #where_clause {
+                fn into_response(self) -> ::rusty_handlebars::web::axum_core::response::Response {
+                    ::rusty_handlebars::web::axum_response(&self, #type_name, #content_type)
+                }
+            }
+        });
+    }
+    if cfg!(feature = "actix-web") {
+        responses.extend(quote! {
+            impl #impl_generics ::rusty_handlebars::web::actix_web::Responder for #name #type_generics #where_clause {
+                type Body = ::rusty_handlebars::web::actix_web::body::BoxBody;
+
+                fn respond_to(
+                    self,
+                    _req: &::rusty_handlebars::web::actix_web::HttpRequest,
+                ) -> ::rusty_handlebars::web::actix_web::HttpResponse<Self::Body> {
+                    ::rusty_handlebars::web::actix_response(&self, #type_name, #content_type)
+                }
+            }
+        });
+    }
+    if cfg!(feature = "warp") {
+        // `Reply` requires `Send`. The bound makes generic templates reply when
+        // their parameters allow it, and reports templates that are never `Send`.
+        let mut generics = generics.clone();
+        generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote!(Self: ::std::marker::Send));
+        let where_clause = &generics.where_clause;
+        responses.extend(quote! {
+            impl #impl_generics ::rusty_handlebars::web::warp::Reply for #name #type_generics #where_clause {
+                fn into_response(self) -> ::rusty_handlebars::web::warp::reply::Response {
+                    ::rusty_handlebars::web::warp_response(&self, #type_name, #content_type)
+                }
+            }
+        });
+    }
     TokenStream::from(quote! {
+        #tracked
         impl #impl_generics ::std::fmt::Display for #name #type_generics #where_clause {
             fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                #reload_fmt
                 #content
                 Ok(())
             }
         }
-        impl #impl_generics ::rusty_handlebars::WithRustyHandlebars for #name #type_generics #where_clause {}
+        impl #impl_generics ::rusty_handlebars::WithRustyHandlebars for #name #type_generics #where_clause {
+            fn render_to<W: ::std::io::Write>(&self, w: &mut W) -> ::std::io::Result<()>
+            where
+                Self: ::std::marker::Sized,
+            {
+                #reload_io
+                #io_content
+                Ok(())
+            }
+            fn render_into(&self, buf: &mut ::std::string::String) -> ::std::fmt::Result {
+                #reload_string
+                use ::std::fmt::Write as _;
+                #string_content
+                Ok(())
+            }
+            fn size_hint(&self) -> usize {
+                #static_len
+            }
+        }
         impl #impl_generics ::rusty_handlebars::AsDisplay for #name #type_generics #where_clause {
             fn as_display(&self) -> impl ::std::fmt::Display {
                 self
             }
         }
+        #async_impl
+        #responses
+        #reflect
     })
 }
 
+/// Implements `rusty_handlebars::Reflect` for a struct.
+///
+/// Hot-reloaded templates read fields through `Reflect`. Derive it for the
+/// structs that templates reach through fields of the template struct, which
+/// gets its own implementation from `WithRustyHandlebars`. Fields whose types
+/// do not implement `Reflect` are skipped, and a template that reads one is
+/// rendered by its compiled code instead.
+#[proc_macro_derive(Reflect)]
+pub fn derive_reflect(raw: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(raw as DeriveInput);
+    match reflect_impl(&input.ident, &input.generics, &input.data, false) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{find_path, helper_paths, TemplateArgs};
+    use std::collections::BTreeSet;
+
+    use rusty_handlebars_parser::{AS_DISPLAY, AS_DISPLAY_HTML};
+
+    use crate::{
+        block_specs, content_type, escape_path, find_path, helper_paths, track_files, TemplateArgs,
+    };
 
     #[test]
     fn test_find() {
         println!("{:?}", find_path());
     }
 
+    #[test]
+    fn tracks_each_template_file() {
+        let files = BTreeSet::from([find_path().join("a.rhbs"), find_path().join("b.rhbs")]);
+        let tracked = track_files(&files).to_string();
+        assert_eq!(tracked.matches("include_bytes !").count(), 2, "{tracked}");
+        assert!(tracked.contains("b.rhbs"), "{tracked}");
+    }
+
+    #[test]
+    fn parses_block_specs() {
+        let args: TemplateArgs = syn::parse_str(
+            r#"path = "t.rhbs", blocks = ["admin: if {arg} { {body} }", "if: {body}"]"#,
+        )
+        .unwrap();
+        assert_eq!(block_specs(&args.blocks[..1]).unwrap()[0].name(), "admin");
+        let err = block_specs(&args.blocks).err().unwrap();
+        assert_eq!(err.to_string(), "block `if` is already defined");
+    }
+
+    #[test]
+    fn selects_escaping() {
+        let escape = |args: &str| {
+            let args: TemplateArgs = syn::parse_str(args).unwrap();
+            escape_path(args.escape.as_ref(), args.src.as_deref()).unwrap()
+        };
+        assert_eq!(escape(r#"path = "page.rhbs""#), AS_DISPLAY_HTML);
+        assert_eq!(escape(r#"path = "email.txt.rhbs""#), AS_DISPLAY);
+        assert_eq!(escape(r#"path = "notes.md.hbs""#), AS_DISPLAY);
+        assert_eq!(
+            escape(r#"path = "email.txt.rhbs", escape = "html""#),
+            AS_DISPLAY_HTML
+        );
+        assert_eq!(escape(r#"source = "x", escape = "none""#), AS_DISPLAY);
+        assert_eq!(
+            escape(r#"source = "x", escape = "crate::tex""#),
+            "crate::tex"
+        );
+        for invalid in ["", "crate::", "tex(", "crate::tex x"] {
+            let args: TemplateArgs =
+                syn::parse_str(&format!(r#"source = "x", escape = "{invalid}""#)).unwrap();
+            let err = escape_path(args.escape.as_ref(), None).err().unwrap();
+            assert!(err.to_string().contains("function path"), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn selects_content_type() {
+        assert_eq!(content_type(Some("page.rhbs")), "text/html; charset=utf-8");
+        assert_eq!(
+            content_type(Some("page.html.rhbs")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Some("email.txt.rhbs")),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(content_type(Some("data.json.rhbs")), "application/json");
+        assert_eq!(content_type(None), "text/html; charset=utf-8");
+    }
+
+    #[test]
+    fn parses_inline_source() {
+        let args: TemplateArgs =
+            syn::parse_str(r#"source = "<a href=\"{{url}}\">{{label}}</a>", minify = false"#)
+                .unwrap();
+        assert!(args.src.is_none());
+        assert_eq!(
+            args.source.unwrap().value(),
+            r#"<a href="{{url}}">{{label}}</a>"#
+        );
+        assert_eq!(args.minify, Some(false));
+    }
+
     #[test]
     fn parses_and_qualifies_helpers() {
         let args: TemplateArgs = syn::parse_str(
             r#"path = "template.rhbs", helpers = ["format_date", "crate::capitalize"]"#,
         )
         .unwrap();
-        let paths = helper_paths(args.helpers);
-        assert_eq!(paths["format_date"], "::rusty_handlebars::format_date");
-        assert_eq!(paths["capitalize"], "crate::capitalize");
+        let helpers = helper_paths(&args.helpers).unwrap();
+        assert_eq!(
+            helpers.paths["format_date"],
+            "::rusty_handlebars::format_date"
+        );
+        assert_eq!(helpers.paths["capitalize"], "crate::capitalize");
+        assert!(helpers.signatures.is_empty());
+    }
+
+    #[test]
+    fn parses_helper_signatures() {
+        let args: TemplateArgs = syn::parse_str(
+            r#"path = "template.rhbs", helpers = ["crate::truncate(text, length = 80)", "pad(text"]"#,
+        )
+        .unwrap();
+        let helpers = helper_paths(&args.helpers[..1]).unwrap();
+        assert_eq!(helpers.paths["truncate"], "crate::truncate");
+        assert_eq!(helpers.signatures["truncate"].params()[1].name, "length");
+        let standard: TemplateArgs =
+            syn::parse_str(r#"path = "template.rhbs", helpers = ["helpers::join"]"#).unwrap();
+        let helpers = helper_paths(&standard.helpers).unwrap();
+        assert_eq!(helpers.paths["join"], "::rusty_handlebars::helpers::join");
+        assert_eq!(
+            helpers.signatures["join"].params()[1].default.as_deref(),
+            Some("\", \"")
+        );
+        assert!(helper_paths(&args.helpers).is_err());
     }
 }
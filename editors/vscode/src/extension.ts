@@ -4,6 +4,7 @@ import {
   Executable,
   LanguageClient,
   LanguageClientOptions,
+  ReferencesRequest,
   ServerOptions,
   TransportKind
 } from "vscode-languageclient/node";
@@ -30,6 +31,10 @@ export async function activate(context: vscode.ExtensionContext): Promise<void>
 
   context.subscriptions.push(
     vscode.commands.registerCommand("rustyHandlebars.showGeneratedRust", showGeneratedRust),
+    vscode.languages.registerReferenceProvider(
+      { scheme: "file", language: "rust" },
+      { provideReferences: templateReferences }
+    ),
     vscode.commands.registerCommand("rustyHandlebars.restartServer", async () => {
       await stopClient();
       await startClient(context, output);
@@ -104,6 +109,33 @@ async function stopClient(): Promise<void> {
   }
 }
 
+/**
+ * Lists template uses of a context struct field from a Rust source. Rust files
+ * are not synchronized with the server, which reads them from disk, so only
+ * saved files deriving `WithRustyHandlebars` are sent.
+ */
+async function templateReferences(
+  document: vscode.TextDocument,
+  position: vscode.Position,
+  context: vscode.ReferenceContext,
+  token: vscode.CancellationToken
+): Promise<vscode.Location[] | undefined> {
+  const active = client;
+  if (
+    active === undefined ||
+    document.isDirty ||
+    !document.getText().includes("WithRustyHandlebars")
+  ) {
+    return undefined;
+  }
+  const locations = await active.sendRequest(
+    ReferencesRequest.type,
+    active.code2ProtocolConverter.asReferenceParams(document, position, context),
+    token
+  );
+  return active.protocol2CodeConverter.asReferences(locations, token);
+}
+
 async function showGeneratedRust(): Promise<void> {
   const editor = vscode.window.activeTextEditor;
   if (editor === undefined || client === undefined) {
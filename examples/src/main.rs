@@ -55,7 +55,7 @@ struct Lookups<'a>{
 }
 
 struct Holder<'a>{
-    held: &'a dyn Display
+    held: &'a (dyn Display + Sync)
 }
 
 impl<'a> AsDisplay for &Holder<'a>{
@@ -1,14 +1,77 @@
 use std::collections::HashMap;
 
-use lsp_types::{Position, Range, Uri};
-use rusty_handlebars_parser::Span;
+use lsp_types::{Position, Range, TextDocumentContentChangeEvent, Uri};
+use rusty_handlebars_parser::{
+    parse_template, reparse_template, ParsedTemplate, Span, TemplateEdit,
+};
+use self_cell::self_cell;
 
-#[derive(Debug, Clone)]
+self_cell!(
+    /// A document's text and the template parsed from it.
+    struct Parse {
+        owner: String,
+        #[covariant]
+        dependent: ParsedTemplate,
+    }
+
+    impl {Debug}
+);
+
+#[derive(Debug)]
 pub struct Document {
-    pub text: String,
+    parse: Parse,
     pub version: i32,
 }
 
+impl Document {
+    fn new(text: String, version: i32) -> Self {
+        Self {
+            parse: Parse::new(text, |text| parse_template(text)),
+            version,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        self.parse.borrow_owner()
+    }
+
+    /// The template parsed from the current text, kept up to date as the
+    /// document changes.
+    pub fn parsed(&self) -> &ParsedTemplate<'_> {
+        self.parse.borrow_dependent()
+    }
+
+    /// Applies incremental or full-text changes in order, then reparses only
+    /// the part of the template they touched.
+    fn apply(&mut self, changes: Vec<TextDocumentContentChangeEvent>) {
+        let mut text = self.text().to_owned();
+        let mut edit = None;
+        let mut replaced = false;
+        for change in changes {
+            let Some(range) = change.range else {
+                text = change.text;
+                replaced = true;
+                continue;
+            };
+            let start = position_to_byte(&text, range.start);
+            let end = position_to_byte(&text, range.end).max(start);
+            text.replace_range(start..end, &change.text);
+            let next = TemplateEdit {
+                span: Span::new(start, end),
+                len: change.text.len(),
+            };
+            edit = Some(edit.map_or(next, |edit: TemplateEdit| edit.then(next)));
+        }
+        self.parse = match edit {
+            Some(edit) if !replaced => Parse::new(text, |text| {
+                reparse_template(self.parse.borrow_dependent(), edit, text)
+            }),
+            None if !replaced => return,
+            _ => Parse::new(text, |text| parse_template(text)),
+        };
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Documents {
     open: HashMap<Uri, Document>,
@@ -16,11 +79,19 @@ pub struct Documents {
 
 impl Documents {
     pub fn open(&mut self, uri: Uri, text: String, version: i32) {
-        self.open.insert(uri, Document { text, version });
+        self.open.insert(uri, Document::new(text, version));
     }
 
-    pub fn change(&mut self, uri: &Uri, text: String, version: i32) {
-        self.open.insert(uri.clone(), Document { text, version });
+    pub fn change(
+        &mut self,
+        uri: &Uri,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        version: i32,
+    ) {
+        if let Some(document) = self.open.get_mut(uri) {
+            document.apply(changes);
+            document.version = version;
+        }
     }
 
     pub fn close(&mut self, uri: &Uri) -> Option<Document> {
@@ -87,4 +158,38 @@ mod tests {
             "a🦀b\né".len()
         );
     }
+
+    #[test]
+    fn applies_incremental_changes() {
+        let uri = "file:///page.rhbs".parse::<Uri>().unwrap();
+        let mut documents = Documents::default();
+        documents.open(uri.clone(), "{{#if a}}\n  é{{b}}\n{{/if}}".to_owned(), 1);
+        let change = |range: Option<Range>, text: &str| TextDocumentContentChangeEvent {
+            range,
+            range_length: None,
+            text: text.to_owned(),
+        };
+        documents.change(
+            &uri,
+            vec![
+                change(
+                    Some(Range::new(Position::new(1, 6), Position::new(1, 6))),
+                    ".c",
+                ),
+                change(
+                    Some(Range::new(Position::new(0, 6), Position::new(0, 7))),
+                    "ok",
+                ),
+            ],
+            2,
+        );
+        let document = documents.get(&uri).unwrap();
+        assert_eq!(document.text(), "{{#if ok}}\n  é{{b.c}}\n{{/if}}");
+        assert_eq!(document.version, 2);
+        assert_eq!(document.parsed(), &parse_template(document.text()));
+
+        documents.change(&uri, vec![change(None, "{{x}}")], 3);
+        let document = documents.get(&uri).unwrap();
+        assert_eq!(document.parsed(), &parse_template("{{x}}"));
+    }
 }
@@ -1,15 +1,18 @@
 use lsp_types::{
     CompletionItem, CompletionItemKind, CompletionResponse, DocumentHighlight,
-    DocumentHighlightKind, DocumentSymbol, Documentation, FoldingRange, FoldingRangeKind, Hover,
-    HoverContents, MarkupContent, MarkupKind, Position, SelectionRange, SignatureHelp,
-    SignatureInformation, SymbolKind,
+    DocumentHighlightKind, DocumentSymbol, Documentation, FoldingRange, FoldingRangeKind,
+    FormattingOptions, Hover, HoverContents, MarkupContent, MarkupKind, Position, Range,
+    SelectionRange, SignatureHelp, SignatureInformation, SymbolKind, TextEdit,
 };
 use rusty_handlebars_parser::{
-    parse_template, BlockNode, Node, NodeKind, Span, SyntaxToken, SyntaxTokenKind,
+    format_template, parse_template, BlockNode, FormatOptions, Node, NodeKind, ParsedTemplate,
+    Span, SyntaxToken, SyntaxTokenKind,
 };
 
 use crate::documents::{position_to_byte, span_to_range};
-use crate::project::{FieldInfo, TemplateContext};
+use crate::project::{FieldInfo, ProjectIndex, TemplateContext};
+use crate::scope::{self, Binding, Declaration, Reference, ResolvedFile};
+use crate::types::{type_name, Types};
 
 const BLOCKS: &[(&str, &str)] = &[
     ("if", "Render a body when a value is truthy."),
@@ -29,6 +32,16 @@ const BLOCKS: &[(&str, &str)] = &[
         "Iterate over a value, with an optional empty branch.",
     ),
     ("each_ref", "Borrow and iterate over a value."),
+    ("match", "Match an enum against `case` arms."),
+    (
+        "case",
+        "Render a body for one enum variant and bind its fields.",
+    ),
+    (
+        "extends",
+        "Render this template's `block` regions inside a layout.",
+    ),
+    ("block", "Declare or override a named layout region."),
 ];
 
 const HELPERS: &[(&str, &str, &str)] = &[
@@ -47,8 +60,74 @@ const HELPERS: &[(&str, &str, &str)] = &[
         "format \"{specifier}\" value",
         "Render a value with a Rust format specifier.",
     ),
+    (
+        "eq",
+        "eq left right",
+        "Test whether `left` is equal to `right` with `==`.",
+    ),
+    (
+        "ne",
+        "ne left right",
+        "Test whether `left` is not equal to `right` with `!=`.",
+    ),
+    (
+        "lt",
+        "lt left right",
+        "Test whether `left` is less than `right` with `<`.",
+    ),
+    (
+        "gt",
+        "gt left right",
+        "Test whether `left` is greater than `right` with `>`.",
+    ),
+    (
+        "le",
+        "le left right",
+        "Test whether `left` is less than or equal to `right` with `<=`.",
+    ),
+    (
+        "ge",
+        "ge left right",
+        "Test whether `left` is greater than or equal to `right` with `>=`.",
+    ),
+    (
+        "and",
+        "and value value…",
+        "Test whether every value is truthy with `&&`.",
+    ),
+    (
+        "or",
+        "or value value…",
+        "Test whether any value is truthy with `||`.",
+    ),
+    (
+        "not",
+        "not value",
+        "Negate the truthiness of a value with `!`.",
+    ),
+    (
+        "url",
+        "url value",
+        "Percent-encode a value for a URL with `AsDisplayUrl`.",
+    ),
+    (
+        "js",
+        "js value",
+        "Escape a value for a quoted JavaScript string with `AsDisplayJs`.",
+    ),
+    (
+        "attr",
+        "attr value",
+        "Escape a value for an HTML attribute with `AsDisplayAttr`.",
+    ),
+    (
+        "css",
+        "css value",
+        "Escape a value for CSS with `AsDisplayCss`.",
+    ),
 ];
 
+/// A field or helper reference that no template context provides.
 pub struct ProjectDiagnostic {
     pub span: Span,
     pub code: &'static str,
@@ -85,37 +164,75 @@ pub fn completions(source: &str, position: Position) -> CompletionResponse {
     CompletionResponse::Array(items)
 }
 
-pub fn add_project_completions(response: &mut CompletionResponse, contexts: &[TemplateContext]) {
+/// Adds the fields the variable at `position` can read, and configured
+/// helpers.
+///
+/// After `path.`, the completions are replaced by the fields of the value
+/// `path` reads when its type resolves.
+pub fn add_project_completions(
+    response: &mut CompletionResponse,
+    source: &str,
+    position: Position,
+    project: &ProjectIndex,
+    contexts: &[TemplateContext],
+) {
     let CompletionResponse::Array(items) = response else {
         return;
     };
-    for context in contexts {
-        for field in &context.fields {
-            if items.iter().any(|item| item.label == field.name) {
-                continue;
-            }
-            items.push(CompletionItem {
-                label: field.name.clone(),
-                kind: Some(CompletionItemKind::FIELD),
-                detail: Some(format!("{} field: {}", context.name, field.ty)),
-                ..Default::default()
-            });
+    let offset = position_to_byte(source, position);
+    let parsed = parse_template(source);
+    let scope = scope::scope_at(&parsed, offset);
+    let types = Types {
+        project,
+        contexts,
+        files: &scope.files,
+    };
+    let before = &source[..offset];
+    let path = &before[before
+        .trim_end_matches(|c: char| c.is_alphanumeric() || matches!(c, '_' | '.' | '/'))
+        .len()..];
+    if let Some((owner, _)) = path.rsplit_once('.') {
+        let Some(ty) = types.path_type(&scope.frames, owner) else {
+            return;
+        };
+        *items = types
+            .fields(&ty)
+            .unwrap_or_default()
+            .iter()
+            .map(|field| field_completion(type_name(&ty), field))
+            .collect();
+        return;
+    }
+    for (owner, field) in types.scope_fields(&scope.frames) {
+        if items.iter().any(|item| item.label == field.name) {
+            continue;
         }
+        items.push(field_completion(&owner, field));
+    }
+    for context in contexts {
         for helper in &context.helpers {
-            let name = helper.rsplit("::").next().unwrap_or(helper);
-            if items.iter().any(|item| item.label == name) {
+            if items.iter().any(|item| item.label == helper.name) {
                 continue;
             }
             items.push(CompletionItem {
-                label: name.to_owned(),
+                label: helper.name.clone(),
                 kind: Some(CompletionItemKind::FUNCTION),
-                detail: Some(format!("Configured helper `{helper}`")),
+                detail: Some(format!("Configured helper `{}`", helper.path)),
                 ..Default::default()
             });
         }
     }
 }
 
+fn field_completion(owner: &str, field: &FieldInfo) -> CompletionItem {
+    CompletionItem {
+        label: field.name.clone(),
+        kind: Some(CompletionItemKind::FIELD),
+        detail: Some(format!("{owner} field: {}", field.ty)),
+        ..Default::default()
+    }
+}
+
 fn collect_scope_completions(nodes: &[Node<'_>], offset: usize, items: &mut Vec<CompletionItem>) {
     for node in nodes {
         let NodeKind::Block(block) = &node.kind else {
@@ -136,21 +253,28 @@ fn collect_scope_completions(nodes: &[Node<'_>], offset: usize, items: &mut Vec<
             items.push(variable_completion("this", "Current block value"));
         }
         if matches!(block.name, "each" | "each_ref")
-            && block.else_span.is_none_or(|span| offset < span.start)
+            && block
+                .else_branches
+                .first()
+                .is_none_or(|branch| offset < branch.span.start)
         {
             for (name, detail) in [
                 ("@index", "Zero-based iteration index"),
+                ("@first", "Whether this is the first item"),
+                ("@last", "Whether this is the last item"),
+                ("@length", "Number of items being iterated"),
                 ("@key", "Pair key (when the item is pair-like)"),
                 ("@value", "Pair value (when the item is pair-like)"),
             ] {
                 items.push(variable_completion(name, detail));
             }
         }
-        let active = if block.else_span.is_some_and(|span| offset > span.end) {
-            &block.else_body
-        } else {
-            &block.body
-        };
+        let active = block
+            .else_branches
+            .iter()
+            .rev()
+            .find(|branch| offset > branch.span.end)
+            .map_or(&block.body, |branch| &branch.body);
         collect_scope_completions(active, offset, items);
     }
 }
@@ -181,6 +305,11 @@ pub fn hover(source: &str, position: Position) -> Option<Hover> {
         })
         .or(match name {
             "@index" => Some("Zero-based index in the nearest `each` block."),
+            "@first" => Some("`true` for the first item in the nearest `each` block."),
+            "@last" => Some("`true` for the last item in the nearest `each` block."),
+            "@length" => Some(
+                "Number of items in the nearest `each` block; the iterator must be `ExactSizeIterator`.",
+            ),
             "@key" => Some("First member of a pair-like item in an `each` block."),
             "@value" => Some("Second member of a pair-like item in an `each` block."),
             "this" => Some("The current block context."),
@@ -195,50 +324,66 @@ pub fn hover(source: &str, position: Position) -> Option<Hover> {
     })
 }
 
-pub fn project_field_at<'context>(
-    source: &str,
-    position: Position,
-    contexts: &'context [TemplateContext],
-) -> Option<&'context FieldInfo> {
-    let token = token_at(source, position)?;
-    let root = token
-        .text
-        .trim_start_matches("../")
-        .split('.')
-        .next()
-        .unwrap_or(token.text);
-    contexts
-        .iter()
-        .flat_map(|context| &context.fields)
-        .find(|field| field.name == root)
-}
-
+/// Describes the Rust type of the variable path segment at `position`.
 pub fn project_hover(
     source: &str,
     position: Position,
+    project: &ProjectIndex,
     contexts: &[TemplateContext],
 ) -> Option<Hover> {
-    let token = token_at(source, position)?;
-    let field = project_field_at(source, position, contexts)?;
+    let token =
+        token_at(source, position).filter(|token| token.kind == SyntaxTokenKind::Variable)?;
+    let parsed = parse_template(source);
+    let scope = scope::scope_at(&parsed, token.span.start);
+    let types = Types {
+        project,
+        contexts,
+        files: &scope.files,
+    };
+    // The path up to the end of the segment under the cursor.
+    let (_, rest) = scope::strip_parents(token.text);
+    let within = (position_to_byte(source, position) - token.span.start)
+        .max(token.text.len() - rest.len())
+        .min(token.text.len());
+    let end = token.text[within..]
+        .find('.')
+        .map_or(token.text.len(), |end| within + end);
+    let path = &token.text[..end];
+    let ty = types.path_type(&scope.frames, path)?;
+    let (_, rest) = scope::strip_parents(path);
+    let label = match path.rsplit_once('.') {
+        Some((owner, member)) if rest.contains('.') => format!(
+            "{}.{member}",
+            type_name(&types.path_type(&scope.frames, owner)?)
+        ),
+        _ if rest == "this" => rest.to_owned(),
+        _ => match scope::lookup(&scope.frames, path)? {
+            Binding::Root => format!("{}.{rest}", contexts.first()?.name),
+            Binding::This(declaration) => {
+                format!("{}.{rest}", type_name(&types.value_type(declaration)?))
+            }
+            Binding::Local(_) => rest.to_owned(),
+        },
+    };
     Some(Hover {
         contents: HoverContents::Markup(MarkupContent {
             kind: MarkupKind::Markdown,
-            value: format!(
-                "**`{}.{}`**\n\nRust type: `{}`",
-                contexts.first()?.name,
-                field.name,
-                field.ty
-            ),
+            value: format!("**`{label}`**\n\nRust type: `{ty}`"),
         }),
         range: Some(span_to_range(source, token.span)),
     })
 }
 
-pub fn project_diagnostics(source: &str, contexts: &[TemplateContext]) -> Vec<ProjectDiagnostic> {
+/// Checks `source` against the contexts associated with its template file.
+///
+/// Returns nothing when `contexts` is empty.
+pub fn project_diagnostics(
+    parsed: &ParsedTemplate<'_>,
+    contexts: &[TemplateContext],
+) -> Vec<ProjectDiagnostic> {
     if contexts.is_empty() {
         return Vec::new();
     }
-    let parsed = parse_template(source);
     let mut diagnostics = Vec::new();
     collect_project_diagnostics(&parsed.nodes, contexts, true, &mut diagnostics);
     diagnostics
@@ -267,19 +412,26 @@ fn collect_project_diagnostics(
                 }
             }
             NodeKind::Block(block) => {
-                if root_context {
+                let layout = matches!(block.name, "extends" | "block");
+                if root_context && !layout {
                     if let Some(argument) = block.arguments.first() {
                         check_root_field(*argument, contexts, diagnostics);
                     }
                 }
-                let preserves_root = root_context && matches!(block.name, "if" | "unless");
-                collect_project_diagnostics(&block.body, contexts, preserves_root, diagnostics);
-                collect_project_diagnostics(
-                    &block.else_body,
-                    contexts,
-                    preserves_root,
-                    diagnostics,
-                );
+                let preserves_root =
+                    root_context && (layout || matches!(block.name, "if" | "unless"));
+                if preserves_root {
+                    for argument in block
+                        .else_branches
+                        .iter()
+                        .filter_map(|branch| branch.arguments.get(1))
+                    {
+                        check_root_field(*argument, contexts, diagnostics);
+                    }
+                }
+                for body in block.bodies() {
+                    collect_project_diagnostics(body, contexts, preserves_root, diagnostics);
+                }
             }
             _ => {}
         }
@@ -329,7 +481,7 @@ fn check_helper(
             context
                 .helpers
                 .iter()
-                .any(|helper| helper.rsplit("::").next() == Some(token.text))
+                .any(|helper| helper.name == token.text)
         })
     {
         return;
@@ -368,9 +520,9 @@ fn symbols_for_nodes(source: &str, nodes: &[Node<'_>]) -> Vec<DocumentSymbol> {
                 range: span_to_range(source, node.span),
                 selection_range: span_to_range(source, block.name_span),
                 children: Some(
-                    symbols_for_nodes(source, &block.body)
-                        .into_iter()
-                        .chain(symbols_for_nodes(source, &block.else_body))
+                    block
+                        .bodies()
+                        .flat_map(|body| symbols_for_nodes(source, body))
                         .collect(),
                 ),
             })
@@ -403,8 +555,9 @@ fn collect_folding(source: &str, nodes: &[Node<'_>], ranges: &mut Vec<FoldingRan
                 collapsed_text: Some(format!("{} …", block.name)),
             });
         }
-        collect_folding(source, &block.body, ranges);
-        collect_folding(source, &block.else_body, ranges);
+        for body in block.bodies() {
+            collect_folding(source, body, ranges);
+        }
     }
 }
 
@@ -449,8 +602,9 @@ fn collect_containing_spans(nodes: &[Node<'_>], offset: usize, spans: &mut Vec<S
             }
             NodeKind::Block(block) => {
                 spans.push(block.expression_span);
-                collect_containing_spans(&block.body, offset, spans);
-                collect_containing_spans(&block.else_body, offset, spans);
+                for body in block.bodies() {
+                    collect_containing_spans(body, offset, spans);
+                }
             }
             _ => {}
         }
@@ -499,6 +653,123 @@ pub fn signature_help(source: &str, position: Position) -> Option<SignatureHelp>
     })
 }
 
+/// A name that rename and references resolve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Symbol {
+    /// An `as |name|` binding, identified by its declaration.
+    Local(Declaration),
+    /// A field of the template's context struct.
+    Field(String),
+    /// A field of the value a `with`, `each`, or `if_some` block binds as
+    /// `this`, identified by the block's argument.
+    Member { scope: Declaration, name: String },
+}
+
+/// Returns the symbol at `position`, and the range of its name.
+pub fn symbol_at(source: &str, position: Position) -> Option<(Symbol, Range)> {
+    let offset = position_to_byte(source, position);
+    let reference = scope::references(&parse_template(source))
+        .into_iter()
+        .find(|reference| reference.span.contains(offset))?;
+    let symbol = match reference.binding {
+        Binding::Root => Symbol::Field(reference.name),
+        Binding::Local(declaration) => Symbol::Local(declaration),
+        Binding::This(scope) => Symbol::Member {
+            scope,
+            name: reference.name,
+        },
+    };
+    Some((symbol, span_to_range(source, reference.span)))
+}
+
+/// Returns what renaming the name at `position` changes, and the name's range.
+///
+/// Only block locals and fields of `contexts` can be renamed: helpers,
+/// private variables, and fields of an implicit `this` are left alone.
+pub fn rename_target(
+    source: &str,
+    position: Position,
+    contexts: &[TemplateContext],
+) -> Option<(Symbol, Range)> {
+    symbol_at(source, position).filter(|(symbol, _)| match symbol {
+        Symbol::Local(_) => true,
+        Symbol::Field(name) => contexts
+            .iter()
+            .flat_map(|context| &context.fields)
+            .any(|field| field.name == *name),
+        Symbol::Member { .. } => false,
+    })
+}
+
+/// Returns the references in `file` that read or declare `symbol`.
+pub fn symbol_references<'file>(
+    file: &'file ResolvedFile,
+    symbol: &'file Symbol,
+) -> impl Iterator<Item = &'file Reference> {
+    file.references
+        .iter()
+        .filter(move |reference| match symbol {
+            Symbol::Local(declaration) => reference.binding == Binding::Local(*declaration),
+            Symbol::Field(name) => reference.binding == Binding::Root && reference.name == *name,
+            Symbol::Member { scope, name } => {
+                reference.binding == Binding::This(*scope) && reference.name == *name
+            }
+        })
+}
+
+/// Returns the edits renaming `symbol` to `new_name` within `file`.
+pub fn rename_edits(file: &ResolvedFile, symbol: &Symbol, new_name: &str) -> Vec<TextEdit> {
+    symbol_references(file, symbol)
+        .map(|reference| TextEdit {
+            range: span_to_range(&file.source, reference.span),
+            new_text: new_name.to_owned(),
+        })
+        .collect()
+}
+
+/// Formats the document, or only the lines `range` touches.
+///
+/// Returns `None` when the template has syntax errors. The formatter keeps
+/// every line, so each changed line becomes its own edit.
+pub fn formatting(
+    source: &str,
+    options: &FormattingOptions,
+    range: Option<Range>,
+) -> Option<Vec<TextEdit>> {
+    let formatted = format_template(
+        source,
+        &FormatOptions {
+            indent_width: options.tab_size as usize,
+            use_tabs: !options.insert_spaces,
+        },
+    )
+    .ok()?;
+    let lines = range.map_or(0..=u32::MAX, |range| {
+        let end = if range.end.line > range.start.line && range.end.character == 0 {
+            range.end.line - 1
+        } else {
+            range.end.line
+        };
+        range.start.line..=end
+    });
+    Some(
+        source
+            .split('\n')
+            .zip(formatted.split('\n'))
+            .zip(0u32..)
+            .filter(|(_, line)| lines.contains(line))
+            .filter(|((before, after), _)| before != after)
+            .map(|((before, after), line)| TextEdit {
+                range: Range::new(
+                    Position::new(line, 0),
+                    Position::new(line, before.encode_utf16().count() as u32),
+                ),
+                new_text: after.to_owned(),
+            })
+            .collect(),
+    )
+}
+
 fn find_token<'source>(nodes: &[Node<'source>], offset: usize) -> Option<SyntaxToken<'source>> {
     for node in nodes.iter().filter(|node| node.span.contains(offset)) {
         match &node.kind {
@@ -516,9 +787,20 @@ fn find_token<'source>(nodes: &[Node<'source>], offset: usize) -> Option<SyntaxT
                         span: block.name_span,
                     });
                 }
-                if let Some(found) =
-                    find_token(&block.body, offset).or_else(|| find_token(&block.else_body, offset))
+                if let Some(found) = block
+                    .arguments
+                    .iter()
+                    .chain(
+                        block
+                            .else_branches
+                            .iter()
+                            .flat_map(|branch| branch.arguments.iter().skip(1)),
+                    )
+                    .find(|token| token.span.contains(offset))
                 {
+                    return Some(*found);
+                }
+                if let Some(found) = block.bodies().find_map(|body| find_token(body, offset)) {
                     return Some(found);
                 }
             }
@@ -547,9 +829,7 @@ fn find_block_at<'source>(
         {
             return Some(block);
         }
-        if let Some(nested) =
-            find_block_at(&block.body, offset).or_else(|| find_block_at(&block.else_body, offset))
-        {
+        if let Some(nested) = block.bodies().find_map(|body| find_block_at(body, offset)) {
             return Some(nested);
         }
     }
@@ -571,7 +851,109 @@ mod tests {
             }
             _ => Vec::new(),
         };
-        assert!(labels(body).contains(&"@index".to_owned()));
+        let body = labels(body);
+        assert!(body.contains(&"@index".to_owned()));
+        assert!(body.contains(&"@last".to_owned()));
         assert!(!labels(else_branch).contains(&"@index".to_owned()));
     }
+
+    #[test]
+    fn renames_locals_and_context_fields() {
+        let contexts = [TemplateContext {
+            name: "Page".to_owned(),
+            template: "page.rhbs".into(),
+            helpers: Vec::new(),
+            blocks: Vec::new(),
+            fields: vec![FieldInfo {
+                name: "items".to_owned(),
+                ty: "Vec<Item>".to_owned(),
+                source: "src/lib.rs".into(),
+            }],
+        }];
+        let source =
+            "{{#each items as |item|}}{{item.name}}{{/each}}{{#with items}}{{len}}{{/with}}";
+        let (target, range) = rename_target(source, Position::new(0, 30), &contexts).unwrap();
+        assert_eq!(
+            range,
+            Range::new(Position::new(0, 27), Position::new(0, 31))
+        );
+        let file = &scope::resolve(source, |_| None)[0];
+        let edits = rename_edits(file, &target, "entry");
+        assert_eq!(
+            edits
+                .iter()
+                .map(|edit| edit.range.start.character)
+                .collect::<Vec<_>>(),
+            [18, 27]
+        );
+        let (target, _) = rename_target(source, Position::new(0, 9), &contexts).unwrap();
+        assert_eq!(target, Symbol::Field("items".to_owned()));
+        assert_eq!(rename_edits(file, &target, "rows").len(), 2);
+        assert_eq!(rename_target(source, Position::new(0, 65), &contexts), None);
+        let (member, _) = symbol_at(source, Position::new(0, 65)).unwrap();
+        assert_eq!(
+            symbol_references(file, &member)
+                .map(|reference| reference.span.start)
+                .collect::<Vec<_>>(),
+            [64]
+        );
+    }
+
+    #[test]
+    fn formats_whole_documents_and_ranges() {
+        let source = "{{#if a}}\n{{ b }}\n{{#if c}}\nd\n{{/if}}\n{{/if}}";
+        let options = FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            ..Default::default()
+        };
+        let edits = formatting(source, &options, None).unwrap();
+        assert_eq!(
+            edits
+                .iter()
+                .map(|edit| (edit.range.start.line, edit.new_text.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (1, "  {{b}}"),
+                (2, "  {{#if c}}"),
+                (4, "  {{/if}}")
+            ]
+        );
+        let range = Range::new(Position::new(3, 0), Position::new(5, 0));
+        let edits = formatting(source, &options, Some(range)).unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(4, 0), Position::new(4, 7))
+        );
+        assert!(formatting("{{#if a}}", &options, None).is_none());
+    }
+
+    #[test]
+    fn layout_regions_keep_the_root_context() {
+        let contexts = [TemplateContext {
+            name: "Page".to_owned(),
+            template: "page.rhbs".into(),
+            helpers: Vec::new(),
+            blocks: Vec::new(),
+            fields: vec![FieldInfo {
+                name: "title".to_owned(),
+                ty: "String".to_owned(),
+                source: "page.rs".into(),
+            }],
+        }];
+        let diagnostics = project_diagnostics(
+            &parse_template(
+                "{{#extends base}}{{#block head}}{{title}}{{missing}}{{/block}}{{/extends}}",
+            ),
+            &contexts,
+        );
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>(),
+            ["unknown-field"]
+        );
+    }
 }
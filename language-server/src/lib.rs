@@ -1,6 +1,11 @@
 mod documents;
 mod features;
 mod project;
+mod scope;
+mod semantic;
 mod server;
+mod types;
 
+pub use features::{project_diagnostics, ProjectDiagnostic};
+pub use project::{FieldInfo, ProjectIndex, TemplateContext};
 pub use server::run;
@@ -5,9 +5,11 @@ use std::{
 };
 
 use cargo_metadata::{Metadata, MetadataCommand};
-use serde::Serialize;
-use syn::{punctuated::Punctuated, Expr, Fields, Item, Lit, Meta, Token};
+use rusty_handlebars_parser::{HelperDeclaration, Span};
+use serde::{Serialize, Serializer};
+use syn::{punctuated::Punctuated, Expr, Field, Fields, Ident, Item, ItemStruct, Lit, Meta, Token};
 
+/// A named field of a template context struct.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct FieldInfo {
     pub name: String,
@@ -15,20 +17,31 @@ pub struct FieldInfo {
     pub source: PathBuf,
 }
 
+/// A struct deriving `WithRustyHandlebars` for a template file.
+///
+/// `helpers` holds the parsed `helpers = [...]` declarations and `blocks` the
+/// raw `blocks = [...]` specs.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct TemplateContext {
     pub name: String,
     pub template: PathBuf,
-    pub helpers: Vec<String>,
+    #[serde(serialize_with = "serialize_helper_paths")]
+    pub helpers: Vec<HelperDeclaration>,
+    pub blocks: Vec<String>,
     pub fields: Vec<FieldInfo>,
 }
 
+/// Template contexts found in the local packages of a Cargo workspace.
 #[derive(Debug, Default)]
 pub struct ProjectIndex {
     contexts: HashMap<PathBuf, Vec<TemplateContext>>,
+    /// The named fields of every local struct by name, or `None` when
+    /// several structs share the name.
+    structs: HashMap<String, Option<Vec<FieldInfo>>>,
 }
 
 impl ProjectIndex {
+    /// Indexes the workspace containing `root` using `cargo metadata`.
     pub fn discover(root: &Path) -> Result<Self, String> {
         let metadata = MetadataCommand::new()
             .current_dir(root)
@@ -60,8 +73,16 @@ impl ProjectIndex {
                 index.index_source(&source, &template_root)?;
             }
         }
+        // A root package's sources were indexed above.
         let root_source = workspace_root.join("src");
-        if root_source.exists() {
+        let root_package = metadata.packages.iter().any(|package| {
+            package
+                .manifest_path
+                .parent()
+                .map(|path| path.as_std_path())
+                == Some(workspace_root)
+        });
+        if root_source.exists() && !root_package {
             let mut source_files = Vec::new();
             collect_rust_files(&root_source, &mut source_files)?;
             for source in source_files {
@@ -71,20 +92,32 @@ impl ProjectIndex {
         Ok(index)
     }
 
+    /// Returns the contexts rendering `template`, if any.
     pub fn contexts_for(&self, template: &Path) -> &[TemplateContext] {
         self.contexts
             .get(&normalize_path(template))
             .map_or(&[], Vec::as_slice)
     }
 
+    /// Iterates over every indexed context.
+    pub fn contexts(&self) -> impl Iterator<Item = &TemplateContext> {
+        self.contexts.values().flatten()
+    }
+
+    /// Returns the fields of the local struct called `name`.
+    ///
+    /// Structs are matched by name alone, so a name that several structs
+    /// declare resolves to none of them.
+    pub fn struct_fields(&self, name: &str) -> Option<&[FieldInfo]> {
+        self.structs.get(name)?.as_deref()
+    }
+
     fn index_source(&mut self, source_path: &Path, template_root: &Path) -> Result<(), String> {
         let source = fs::read_to_string(source_path)
             .map_err(|error| format!("unable to read {}: {error}", source_path.display()))?;
-        let syntax = match syn::parse_file(&source) {
-            Ok(syntax) => syntax,
-            Err(_) => return Ok(()),
-        };
-        self.index_items(syntax.items, source_path, template_root);
+        if let Some(items) = with_rust_file(&source, |syntax| syntax.items) {
+            self.index_items(items, source_path, template_root);
+        }
         Ok(())
     }
 
@@ -100,6 +133,24 @@ impl ProjectIndex {
                 }
                 _ => continue,
             };
+            let fields = match item.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .into_iter()
+                    .filter_map(|field| {
+                        Some(FieldInfo {
+                            name: field.ident?.to_string(),
+                            ty: quote_type(&field.ty),
+                            source: source_path.to_path_buf(),
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+                _ => Vec::new(),
+            };
+            self.structs
+                .entry(item.ident.to_string())
+                .and_modify(|existing| *existing = None)
+                .or_insert_with(|| Some(fields.clone()));
             if !derives_rusty_handlebars(&item.attrs) {
                 continue;
             }
@@ -119,33 +170,71 @@ impl ProjectIndex {
                 continue;
             };
             let template = normalize_path(&template_root.join(template_path));
-            let fields = match item.fields {
-                Fields::Named(fields) => fields
-                    .named
-                    .into_iter()
-                    .filter_map(|field| {
-                        Some(FieldInfo {
-                            name: field.ident?.to_string(),
-                            ty: quote_type(&field.ty),
-                            source: source_path.to_path_buf(),
-                        })
-                    })
-                    .collect(),
-                _ => Vec::new(),
-            };
             self.contexts
                 .entry(template.clone())
                 .or_default()
                 .push(TemplateContext {
                     name: item.ident.to_string(),
                     template,
-                    helpers: helper_paths(&arguments),
+                    helpers: helper_declarations(&arguments),
+                    blocks: block_specs(&arguments),
                     fields,
                 });
         }
     }
 }
 
+/// Finds the name of `field` in the declaration of `struct_name`.
+pub fn field_span(rust_source: &str, struct_name: &str, field: &str) -> Option<Span> {
+    with_rust_file(rust_source, |syntax| {
+        find_field(&syntax.items, &mut |item, candidate| {
+            let ident = candidate.ident.as_ref()?;
+            (item.ident == struct_name && ident == field).then(|| ident_span(ident))
+        })
+    })
+    .flatten()
+}
+
+/// Returns the struct and field names of the field declared at `offset`.
+pub fn field_at(rust_source: &str, offset: usize) -> Option<(String, String)> {
+    with_rust_file(rust_source, |syntax| {
+        find_field(&syntax.items, &mut |item, field| {
+            let ident = field.ident.as_ref()?;
+            ident_span(ident)
+                .contains(offset)
+                .then(|| (item.ident.to_string(), ident.to_string()))
+        })
+    })
+    .flatten()
+}
+
+fn find_field<T>(
+    items: &[Item],
+    found: &mut impl FnMut(&ItemStruct, &Field) -> Option<T>,
+) -> Option<T> {
+    items.iter().find_map(|item| match item {
+        Item::Struct(item) => item.fields.iter().find_map(|field| found(item, field)),
+        Item::Mod(module) => module
+            .content
+            .as_ref()
+            .and_then(|(_, items)| find_field(items, found)),
+        _ => None,
+    })
+}
+
+fn ident_span(ident: &Ident) -> Span {
+    let range = ident.span().byte_range();
+    Span::new(range.start, range.end)
+}
+
+/// Parses a Rust file and releases its span information afterwards, which
+/// `proc-macro2` otherwise keeps for the life of the thread.
+fn with_rust_file<T>(source: &str, read: impl FnOnce(syn::File) -> T) -> Option<T> {
+    let result = syn::parse_file(source).ok().map(read);
+    proc_macro2::extra::invalidate_current_thread_spans();
+    result
+}
+
 fn derives_rusty_handlebars(attributes: &[syn::Attribute]) -> bool {
     attributes
         .iter()
@@ -181,14 +270,35 @@ fn template_path(arguments: &Punctuated<Meta, Token![,]>) -> Option<String> {
     })
 }
 
-fn helper_paths(arguments: &Punctuated<Meta, Token![,]>) -> Vec<String> {
+/// Serializes helpers as their paths, as `rustyHandlebars/projectContexts`
+/// reports them.
+fn serialize_helper_paths<S: Serializer>(
+    helpers: &[HelperDeclaration],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(helpers.iter().map(|helper| &helper.path))
+}
+
+/// Parses `helpers = [...]`, skipping declarations the derive rejects.
+fn helper_declarations(arguments: &Punctuated<Meta, Token![,]>) -> Vec<HelperDeclaration> {
+    string_list(arguments, "helpers")
+        .iter()
+        .filter_map(|declaration| HelperDeclaration::parse(declaration).ok())
+        .collect()
+}
+
+fn block_specs(arguments: &Punctuated<Meta, Token![,]>) -> Vec<String> {
+    string_list(arguments, "blocks")
+}
+
+fn string_list(arguments: &Punctuated<Meta, Token![,]>, name: &str) -> Vec<String> {
     arguments
         .iter()
         .find_map(|argument| {
             let Meta::NameValue(value) = argument else {
                 return None;
             };
-            if !value.path.is_ident("helpers") {
+            if !value.path.is_ident(name) {
                 return None;
             }
             let Expr::Array(values) = &value.value else {
@@ -219,7 +329,25 @@ fn quote_type(ty: &syn::Type) -> String {
             .path
             .segments
             .iter()
-            .map(|segment| segment.ident.to_string())
+            .map(|segment| {
+                let mut quoted = segment.ident.to_string();
+                if let syn::PathArguments::AngleBracketed(arguments) = &segment.arguments {
+                    let types = arguments
+                        .args
+                        .iter()
+                        .filter_map(|argument| match argument {
+                            syn::GenericArgument::Type(ty) => Some(quote_type(ty)),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    if !types.is_empty() {
+                        quoted.push('<');
+                        quoted.push_str(&types.join(", "));
+                        quoted.push('>');
+                    }
+                }
+                quoted
+            })
             .collect::<Vec<_>>()
             .join("::"),
         syn::Type::Reference(reference) => format!("&{}", quote_type(&reference.elem)),
@@ -257,7 +385,7 @@ fn collect_rust_files(directory: &Path, output: &mut Vec<PathBuf>) -> Result<(),
     Ok(())
 }
 
-fn normalize_path(path: &Path) -> PathBuf {
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
     path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
 }
 
@@ -269,14 +397,63 @@ mod tests {
     fn parses_template_attribute_values() {
         let arguments: Punctuated<Meta, Token![,]> = syn::parse::Parser::parse_str(
             Punctuated::<Meta, Token![,]>::parse_terminated,
-            r#"path = "templates/page.rhbs", helpers = ["crate::title"]"#,
+            r#"path = "templates/page.rhbs", helpers = ["crate::title", "crate::truncate(text, length = 80)"], blocks = ["admin: if {arg}.is_admin() { {body} }"]"#,
         )
         .unwrap();
         assert_eq!(
             template_path(&arguments).as_deref(),
             Some("templates/page.rhbs")
         );
-        assert_eq!(helper_paths(&arguments), ["crate::title"]);
+        let helpers = helper_declarations(&arguments);
+        assert_eq!(
+            helpers
+                .iter()
+                .map(|helper| (helper.name.as_str(), helper.path.as_str()))
+                .collect::<Vec<_>>(),
+            [("title", "crate::title"), ("truncate", "crate::truncate")]
+        );
+        assert_eq!(helpers[0].signature, None);
+        assert_eq!(
+            helpers[1].signature.as_ref().unwrap().params()[1].name,
+            "length"
+        );
+        assert_eq!(
+            block_specs(&arguments),
+            ["admin: if {arg}.is_admin() { {body} }"]
+        );
+    }
+
+    #[test]
+    fn finds_field_declarations() {
+        let source = "struct Other { name: String }\nmod pages {\n    struct Page { title: String, name: String }\n}";
+        let span = field_span(source, "Page", "name").unwrap();
+        assert_eq!(&source[span.start..span.end], "name");
+        assert!(span.start > source.find("Page").unwrap());
+        assert_eq!(field_span(source, "Page", "missing"), None);
+        assert_eq!(
+            field_at(source, span.start + 2),
+            Some(("Page".to_owned(), "name".to_owned()))
+        );
+        assert_eq!(field_at(source, 0), None);
+    }
+
+    #[test]
+    fn indexes_every_struct() {
+        let source = "struct Order { lines: &'a [Line], totals: HashMap<String, Option<f64>> }\nmod a { struct Line { id: u32 } }\nmod b { struct Line; }";
+        let mut index = ProjectIndex::default();
+        let items = with_rust_file(source, |syntax| syntax.items).unwrap();
+        index.index_items(items, Path::new("src/lib.rs"), Path::new(""));
+        assert_eq!(
+            index
+                .struct_fields("Order")
+                .unwrap()
+                .iter()
+                .map(|field| field.ty.as_str())
+                .collect::<Vec<_>>(),
+            ["&[Line]", "HashMap<String, Option<f64>>"]
+        );
+        assert_eq!(index.struct_fields("Line"), None);
+        assert_eq!(index.contexts().count(), 0);
     }
 
     #[test]
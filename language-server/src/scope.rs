@@ -0,0 +1,544 @@
+//! Resolves template variables with the compiler's scope rules.
+//!
+//! Each block opens a scope. A name resolves to the innermost `as |name|`
+//! binding that declares it, stops at the innermost block that binds `this`,
+//! and otherwise reads a field of the template's context struct. Every `../`
+//! starts the lookup one scope further out. Partials are resolved where they
+//! are included, as the compiler inlines them, and a layout named by
+//! `{{#extends "path"}}` reads the context at its root.
+
+use rusty_handlebars_parser::{
+    parse_partial, parse_template, BlockNode, Node, NodeKind, ParsedTemplate, Span, SyntaxToken,
+    SyntaxTokenKind,
+};
+
+/// How deeply partials are followed, which also stops include cycles.
+const MAX_PARTIAL_DEPTH: usize = 32;
+
+/// A position in one of the files returned by [`resolve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Declaration {
+    /// Index into the resolved files.
+    pub file: usize,
+    pub span: Span,
+}
+
+/// What the first segment of a variable path reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// A field of the template's context struct.
+    Root,
+    /// A name bound by `as |name|`.
+    Local(Declaration),
+    /// A field of the value that an unaliased `with`, `each`, or `if_some`
+    /// block binds as `this`, identified by the block's argument.
+    This(Declaration),
+}
+
+/// A variable, or an `as |name|` declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// The first path segment after any `../`, such as `customer` in
+    /// `../customer.name`.
+    pub name: String,
+    pub span: Span,
+    /// The whole variable path.
+    pub path: String,
+    pub path_span: Span,
+    pub binding: Binding,
+    pub declaration: bool,
+}
+
+/// The value an `each`, `with`, or `if_some` block binds, as `this` or to
+/// its first `as |name|` local.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockValue {
+    pub declaration: Declaration,
+    pub helper: String,
+    /// The variable path the block reads, and what its first segment reads
+    /// where the block opens.
+    pub path: String,
+    pub binding: Binding,
+}
+
+/// The references found in a template or in a partial it includes.
+#[derive(Debug, Clone)]
+pub struct ResolvedFile {
+    /// The partial or layout path as written in `{{> "path"}}` or
+    /// `{{#extends "path"}}`, or `None` for the template itself.
+    pub partial: Option<String>,
+    pub source: String,
+    pub references: Vec<Reference>,
+    pub values: Vec<BlockValue>,
+}
+
+/// The scopes open at an offset of a template, innermost last, and the files
+/// resolved to find them.
+#[derive(Debug, Clone)]
+pub struct ScopeAt {
+    pub files: Vec<ResolvedFile>,
+    pub frames: Vec<Frame>,
+}
+
+/// Resolves every variable in `source`, the first file returned.
+///
+/// `load` reads partials and layouts by the path written in the template.
+/// Each include adds a file, so a partial included twice appears twice with
+/// the bindings of each include site.
+pub fn resolve(source: &str, load: impl FnMut(&str) -> Option<String>) -> Vec<ResolvedFile> {
+    resolve_parsed(&parse_template(source), load)
+}
+
+/// Resolves every variable in an already parsed template, as [`resolve`].
+pub fn resolve_parsed(
+    parsed: &ParsedTemplate<'_>,
+    mut load: impl FnMut(&str) -> Option<String>,
+) -> Vec<ResolvedFile> {
+    Resolver::new(parsed, &mut load, None).run(parsed).files
+}
+
+/// Finds the scopes open at `offset`, without reading partials.
+pub fn scope_at(parsed: &ParsedTemplate<'_>, offset: usize) -> ScopeAt {
+    Resolver::new(parsed, &mut |_| None, Some(offset)).run(parsed)
+}
+
+/// Resolves the first segment of a variable path in `frames`.
+///
+/// `this` reads the innermost value bound as `this`, or the context at the
+/// root. Returns `None` when `../` leaves the template.
+pub fn lookup(frames: &[Frame], path: &str) -> Option<Binding> {
+    let (parents, rest) = strip_parents(path);
+    let name = rest.split('.').next().unwrap_or(rest);
+    let mut depth = frames.len().checked_sub(parents)?;
+    while depth > 0 {
+        match &frames[depth - 1] {
+            Frame::This(declaration) => return Some(Binding::This(*declaration)),
+            Frame::Names(names) if name != "this" => {
+                if let Some((_, declaration)) = names.iter().find(|(local, _)| local == name) {
+                    return Some(Binding::Local(*declaration));
+                }
+            }
+            _ => {}
+        }
+        depth -= 1;
+    }
+    Some(Binding::Root)
+}
+
+/// Splits the leading `../` segments from a variable path.
+pub fn strip_parents(path: &str) -> (usize, &str) {
+    let mut parents = 0;
+    let mut rest = path;
+    while let Some(parent) = rest.strip_prefix("../") {
+        rest = parent;
+        parents += 1;
+    }
+    (parents, rest)
+}
+
+/// Resolves the variables of a template alone, without reading partials.
+pub fn references(parsed: &ParsedTemplate<'_>) -> Vec<Reference> {
+    resolve_parsed(parsed, |_| None).swap_remove(0).references
+}
+
+/// Names visible in an open block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A block that binds nothing, such as `if`, or an `else` branch.
+    None,
+    This(Declaration),
+    Names(Vec<(String, Declaration)>),
+}
+
+struct Resolver<'a> {
+    load: &'a mut dyn FnMut(&str) -> Option<String>,
+    files: Vec<ResolvedFile>,
+    frames: Vec<Frame>,
+    depth: usize,
+    /// An offset in the template whose open scopes are kept in `open`.
+    probe: Option<usize>,
+    open: Vec<Frame>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(
+        parsed: &ParsedTemplate<'_>,
+        load: &'a mut dyn FnMut(&str) -> Option<String>,
+        probe: Option<usize>,
+    ) -> Self {
+        Self {
+            load,
+            files: vec![ResolvedFile {
+                partial: None,
+                source: parsed.source.to_owned(),
+                references: Vec::new(),
+                values: Vec::new(),
+            }],
+            frames: Vec::new(),
+            depth: 0,
+            probe,
+            open: Vec::new(),
+        }
+    }
+
+    fn run(mut self, parsed: &ParsedTemplate<'_>) -> ScopeAt {
+        self.nodes(0, &parsed.nodes);
+        ScopeAt {
+            files: self.files,
+            frames: self.open,
+        }
+    }
+
+    fn nodes(&mut self, file: usize, nodes: &[Node<'_>]) {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Interpolation { tokens, .. } => {
+                    // The first of several tokens names a helper.
+                    let arguments = if tokens.len() == 1 {
+                        &tokens[..]
+                    } else {
+                        tokens.get(1..).unwrap_or_default()
+                    };
+                    self.tokens(file, arguments);
+                }
+                NodeKind::Block(block) => self.block(file, block),
+                NodeKind::Partial { path, .. } => self.partial(path),
+                _ => {}
+            }
+        }
+    }
+
+    fn block(&mut self, file: usize, block: &BlockNode<'_>) {
+        let (arguments, names) = split_alias(&block.arguments);
+        // `case` names a variant, and layouts name a path or region.
+        if !matches!(block.name, "case" | "extends" | "block") {
+            self.tokens(file, arguments);
+        }
+        // Only the compiled template extends a layout, at its root, so the
+        // layout reads the context with no scopes open.
+        if let ("extends", Some(path)) = (block.name, block.name_argument()) {
+            self.partial(path);
+        }
+        let declared = self.declare(file, names, None);
+        let this = matches!(
+            block.name,
+            "each" | "each_ref" | "with" | "with_ref" | "if_some" | "if_some_ref"
+        );
+        let frame = if !declared.is_empty() {
+            Frame::Names(declared.clone())
+        } else if this {
+            Frame::This(Declaration {
+                file,
+                span: arguments
+                    .first()
+                    .map_or(block.name_span, |token| token.span),
+            })
+        } else {
+            Frame::None
+        };
+        if this {
+            self.bind_value(&frame, block.name, arguments);
+        }
+        // Each body runs from the end of its opening tag to the next tag
+        // of the block.
+        let end = block.full_span().end;
+        let mut starts = block.else_branches.iter().map(|branch| branch.span.start);
+        let close = block.close_span.map_or(end, |span| span.start);
+        let body_end = starts.next().unwrap_or(close);
+        self.scoped(
+            file,
+            frame,
+            &block.body,
+            Span::new(block.open_span.end, body_end),
+        );
+        for (branch, branch_end) in block
+            .else_branches
+            .iter()
+            .zip(starts.chain(std::iter::once(close)))
+        {
+            let helper = branch.arguments.first().map(|token| token.text);
+            let (arguments, names) = split_alias(branch.arguments.get(1..).unwrap_or_default());
+            self.tokens(file, arguments);
+            // A chained `else if_some` binds the same name as its block.
+            let chained = matches!(block.name, "if_some" | "if_some_ref")
+                && matches!(helper, Some("if_some" | "if_some_ref"));
+            let frame = if !chained {
+                Frame::None
+            } else if names.is_empty() {
+                Frame::This(Declaration {
+                    file,
+                    span: arguments.first().map_or(branch.span, |token| token.span),
+                })
+            } else {
+                let first = declared.first().map(|(_, declaration)| *declaration);
+                Frame::Names(self.declare(file, names, first))
+            };
+            if let (Frame::This(_), Some(helper)) = (&frame, helper) {
+                self.bind_value(&frame, helper, arguments);
+            }
+            self.scoped(
+                file,
+                frame,
+                &branch.body,
+                Span::new(branch.span.end, branch_end),
+            );
+        }
+    }
+
+    /// Records the value a block binds by `frame` from its first argument.
+    fn bind_value(&mut self, frame: &Frame, helper: &str, arguments: &[SyntaxToken<'_>]) {
+        let declaration = match frame {
+            Frame::This(declaration) => *declaration,
+            Frame::Names(names) => match names.first() {
+                Some((_, declaration)) => *declaration,
+                None => return,
+            },
+            Frame::None => return,
+        };
+        let Some(argument) = arguments
+            .first()
+            .filter(|token| token.kind == SyntaxTokenKind::Variable)
+        else {
+            return;
+        };
+        let Some(binding) = lookup(&self.frames, argument.text) else {
+            return;
+        };
+        self.files[declaration.file].values.push(BlockValue {
+            declaration,
+            helper: helper.to_owned(),
+            path: argument.text.to_owned(),
+            binding,
+        });
+    }
+
+    /// Records `as |name|` declarations, as references to `existing` when a
+    /// chained branch repeats its block's binding.
+    fn declare(
+        &mut self,
+        file: usize,
+        names: &[SyntaxToken<'_>],
+        existing: Option<Declaration>,
+    ) -> Vec<(String, Declaration)> {
+        names
+            .iter()
+            .map(|token| {
+                let declaration = existing.unwrap_or(Declaration {
+                    file,
+                    span: token.span,
+                });
+                self.files[file].references.push(Reference {
+                    name: token.text.to_owned(),
+                    span: token.span,
+                    path: token.text.to_owned(),
+                    path_span: token.span,
+                    binding: Binding::Local(declaration),
+                    declaration: existing.is_none(),
+                });
+                (token.text.to_owned(), declaration)
+            })
+            .collect()
+    }
+
+    fn scoped(&mut self, file: usize, frame: Frame, nodes: &[Node<'_>], body: Span) {
+        self.frames.push(frame);
+        if file == 0 && self.probe.is_some_and(|offset| body.contains(offset)) {
+            self.open.clone_from(&self.frames);
+        }
+        self.nodes(file, nodes);
+        self.frames.pop();
+    }
+
+    fn partial(&mut self, path: &str) {
+        if self.depth >= MAX_PARTIAL_DEPTH {
+            return;
+        }
+        let Some(source) = (self.load)(path) else {
+            return;
+        };
+        let file = self.files.len();
+        self.files.push(ResolvedFile {
+            partial: Some(path.to_owned()),
+            source: source.clone(),
+            references: Vec::new(),
+            values: Vec::new(),
+        });
+        self.depth += 1;
+        self.nodes(file, &parse_partial(&source).nodes);
+        self.depth -= 1;
+    }
+
+    fn tokens(&mut self, file: usize, tokens: &[SyntaxToken<'_>]) {
+        for token in tokens {
+            match token.kind {
+                SyntaxTokenKind::Variable => self.variable(file, token.text, token.span.start),
+                SyntaxTokenKind::Subexpression => self.subexpression(file, *token),
+                _ => {}
+            }
+        }
+    }
+
+    /// Resolves the arguments of a `(helper args)` subexpression.
+    fn subexpression(&mut self, file: usize, token: SyntaxToken<'_>) {
+        let tokens = token.subexpression_tokens();
+        self.tokens(file, tokens.get(1..).unwrap_or_default());
+    }
+
+    fn variable(&mut self, file: usize, text: &str, start: usize) {
+        let (_, rest) = strip_parents(text);
+        let name = rest.split('.').next().unwrap_or(rest);
+        if name.is_empty() || name == "this" || name.starts_with('@') {
+            return;
+        }
+        // `../` past the template's root does not compile.
+        let Some(binding) = lookup(&self.frames, text) else {
+            return;
+        };
+        let name_start = start + text.len() - rest.len();
+        self.files[file].references.push(Reference {
+            name: name.to_owned(),
+            span: Span::new(name_start, name_start + name.len()),
+            path: text.to_owned(),
+            path_span: Span::new(start, start + text.len()),
+            binding,
+            declaration: false,
+        });
+    }
+}
+
+/// Splits block arguments into the values before `as` and the names bound
+/// after it.
+fn split_alias<'a, 'source>(
+    arguments: &'a [SyntaxToken<'source>],
+) -> (&'a [SyntaxToken<'source>], &'a [SyntaxToken<'source>]) {
+    match arguments
+        .iter()
+        .position(|token| token.kind == SyntaxTokenKind::Keyword && token.text == "as")
+    {
+        Some(position) => {
+            let names = &arguments[position + 1..];
+            let start = names
+                .iter()
+                .position(|token| token.kind != SyntaxTokenKind::Punctuation)
+                .unwrap_or(names.len());
+            let end = names[start..]
+                .iter()
+                .position(|token| token.kind == SyntaxTokenKind::Punctuation)
+                .map_or(names.len(), |end| start + end);
+            (&arguments[..position], &names[start..end])
+        }
+        None => (arguments, &[]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(source: &str) -> Vec<(&str, Binding)> {
+        references(&parse_template(source))
+            .into_iter()
+            .map(|reference| {
+                (
+                    &source[reference.span.start..reference.span.end],
+                    reference.binding,
+                )
+            })
+            .collect()
+    }
+
+    /// The local declared by the first `{name}|` in `source`.
+    fn local(source: &str, name: &str) -> Binding {
+        let start = source.find(&format!("{name}|")).unwrap();
+        Binding::Local(Declaration {
+            file: 0,
+            span: Span::new(start, start + name.len()),
+        })
+    }
+
+    #[test]
+    fn resolves_like_the_compiler() {
+        let source = "{{#each orders as |order|}}{{order.id}}{{#if (eq order.id ../id)}}{{../../title}}{{/if}}{{else}}{{order}}{{/each}}{{#with customer}}{{name}}{{../name}}{{/with}}";
+        let order = local(source, "order");
+        let customer = Binding::This(Declaration {
+            file: 0,
+            span: Span::new(
+                source.find("customer").unwrap(),
+                source.find("customer").unwrap() + 8,
+            ),
+        });
+        assert_eq!(
+            bindings(source),
+            [
+                ("orders", Binding::Root),
+                ("order", order),
+                ("order", order),
+                ("order", order),
+                ("id", Binding::Root),
+                ("title", Binding::Root),
+                ("order", Binding::Root),
+                ("customer", Binding::Root),
+                ("name", customer),
+                ("name", Binding::Root),
+            ]
+        );
+    }
+
+    #[test]
+    fn chained_if_some_repeats_its_binding() {
+        let source =
+            "{{#if_some a as |v|}}{{v}}{{else if_some b as |v|}}{{v}}{{else}}{{v}}{{/if_some}}";
+        let v = local(source, "v");
+        assert_eq!(
+            bindings(source)[4..],
+            [("v", v), ("v", v), ("v", Binding::Root)]
+        );
+    }
+
+    #[test]
+    fn resolves_partials_where_they_are_included() {
+        let files = resolve(
+            "{{#each items as |item|}}{{> \"row.rhbs\"}}{{/each}}{{> row.rhbs}}",
+            |path| (path == "row.rhbs").then(|| "{{item.name}}".to_owned()),
+        );
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[1].partial.as_deref(), Some("row.rhbs"));
+        assert!(matches!(files[1].references[0].binding, Binding::Local(_)));
+        assert_eq!(files[2].references[0].binding, Binding::Root);
+    }
+
+    #[test]
+    fn resolves_extended_layouts() {
+        let files = resolve(
+            "{{#extends \"base.rhbs\"}}{{#block main}}{{title}}{{/block}}{{/extends}}",
+            |path| {
+                (path == "base.rhbs")
+                    .then(|| "<h1>{{title}}</h1>{{#block main}}{{/block}}".to_owned())
+            },
+        );
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].partial.as_deref(), Some("base.rhbs"));
+        assert_eq!(files[1].references[0].name, "title");
+        assert_eq!(files[1].references[0].binding, Binding::Root);
+    }
+
+    #[test]
+    fn finds_open_scopes_and_bound_values() {
+        let source = "{{#with a}}{{#each b as |c|}}{{else}}x{{/each}}{{/with}}";
+        let at = |needle: &str| scope_at(&parse_template(source), source.find(needle).unwrap());
+        let scope = at("{{else}}");
+        assert_eq!(scope.frames.len(), 2);
+        assert_eq!(lookup(&scope.frames, "c"), Some(local(source, "c")));
+        assert_eq!(at("x").frames[1], Frame::None);
+        assert!(at("{{#with").frames.is_empty());
+        let values = &scope.files[0].values;
+        assert_eq!(
+            values
+                .iter()
+                .map(|value| (value.helper.as_str(), value.path.as_str()))
+                .collect::<Vec<_>>(),
+            [("with", "a"), ("each", "b")]
+        );
+        assert!(matches!(values[1].binding, Binding::This(_)));
+    }
+}
@@ -0,0 +1,363 @@
+//! Semantic tokens for template expressions.
+//!
+//! Text outside `{{ }}` is left to the editor's grammar. Inside tags, names
+//! are classified by how the compiler reads them: helpers, block keywords,
+//! `as |name|` locals, context fields, and private variables such as
+//! `@index`.
+
+use std::collections::{HashMap, HashSet};
+
+use lsp_types::{
+    Range, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens,
+    SemanticTokensLegend,
+};
+use rusty_handlebars_parser::{
+    BlockNode, Node, NodeKind, ParsedTemplate, Span, SyntaxToken, SyntaxTokenKind,
+};
+
+use crate::documents::position_to_byte;
+use crate::project::TemplateContext;
+use crate::scope::{self, Binding, Reference};
+
+const HELPER: u32 = 0;
+const KEYWORD: u32 = 1;
+const LOCAL: u32 = 2;
+const FIELD: u32 = 3;
+const STRING: u32 = 4;
+const NUMBER: u32 = 5;
+const COMMENT: u32 = 6;
+const HASH_KEY: u32 = 7;
+const VARIANT: u32 = 8;
+
+const DECLARATION: u32 = 1 << 0;
+const READONLY: u32 = 1 << 1;
+const DEFAULT_LIBRARY: u32 = 1 << 2;
+const UNRESOLVED: u32 = 1 << 3;
+
+/// The token types and modifiers, indexed by the constants above.
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: vec![
+            SemanticTokenType::FUNCTION,
+            SemanticTokenType::KEYWORD,
+            SemanticTokenType::VARIABLE,
+            SemanticTokenType::PROPERTY,
+            SemanticTokenType::STRING,
+            SemanticTokenType::NUMBER,
+            SemanticTokenType::COMMENT,
+            SemanticTokenType::PARAMETER,
+            SemanticTokenType::ENUM_MEMBER,
+        ],
+        token_modifiers: vec![
+            SemanticTokenModifier::DECLARATION,
+            SemanticTokenModifier::READONLY,
+            SemanticTokenModifier::DEFAULT_LIBRARY,
+            // A context field that no struct rendering the template declares.
+            SemanticTokenModifier::new("unresolved"),
+        ],
+    }
+}
+
+/// Classifies the template's expressions, or only those within `range`.
+///
+/// Context fields are marked unresolved only when `contexts` is not empty.
+pub fn semantic_tokens(
+    parsed: &ParsedTemplate<'_>,
+    range: Option<Range>,
+    contexts: &[TemplateContext],
+) -> SemanticTokens {
+    let source = parsed.source;
+    let references = scope::references(parsed);
+    let mut highlighter = Highlighter {
+        source,
+        references: references
+            .iter()
+            .map(|reference| (reference.path_span.start, reference))
+            .collect(),
+        fields: (!contexts.is_empty()).then(|| {
+            contexts
+                .iter()
+                .flat_map(|context| &context.fields)
+                .map(|field| field.name.as_str())
+                .collect()
+        }),
+        tokens: Vec::new(),
+    };
+    highlighter.nodes(&parsed.nodes);
+    let mut tokens = highlighter.tokens;
+    tokens.sort_by_key(|(span, _, _)| span.start);
+    tokens.dedup_by_key(|(span, _, _)| span.start);
+    if let Some(range) = range {
+        let start = position_to_byte(source, range.start);
+        let end = position_to_byte(source, range.end);
+        tokens.retain(|(span, _, _)| span.end > start && span.start < end);
+    }
+    SemanticTokens {
+        result_id: None,
+        data: encode(source, &tokens),
+    }
+}
+
+struct Highlighter<'a> {
+    source: &'a str,
+    references: HashMap<usize, &'a Reference>,
+    fields: Option<HashSet<&'a str>>,
+    tokens: Vec<(Span, u32, u32)>,
+}
+
+impl Highlighter<'_> {
+    fn nodes(&mut self, nodes: &[Node<'_>]) {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Comment { .. } => self.push(node.span, COMMENT, 0),
+                NodeKind::Interpolation { tokens, .. } => self.call(tokens),
+                NodeKind::Block(block) => self.block(block),
+                NodeKind::Partial { path_span, .. } => self.push(*path_span, STRING, 0),
+                NodeKind::RawBlock {
+                    name,
+                    name_span,
+                    close_span,
+                    ..
+                } => {
+                    self.push(*name_span, KEYWORD, 0);
+                    if let Some(close) = close_span {
+                        self.close(*close, name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Highlights `helper args`, or a lone value.
+    fn call(&mut self, tokens: &[SyntaxToken<'_>]) {
+        match tokens {
+            [value] => self.tokens(&[*value]),
+            [helper, arguments @ ..] => {
+                self.push(helper.span, HELPER, 0);
+                self.tokens(arguments);
+            }
+            [] => {}
+        }
+    }
+
+    fn block(&mut self, block: &BlockNode<'_>) {
+        self.push(block.name_span, KEYWORD, 0);
+        match (block.name, block.arguments.split_first()) {
+            ("case", Some((variant, rest))) => {
+                self.push(variant.span, VARIANT, 0);
+                self.tokens(rest);
+            }
+            _ => self.tokens(&block.arguments),
+        }
+        for branch in &block.else_branches {
+            if let Some(start) = self.source[branch.span.start..branch.span.end].find("else") {
+                let start = branch.span.start + start;
+                self.push(Span::new(start, start + "else".len()), KEYWORD, 0);
+            }
+            if let Some((helper, arguments)) = branch.arguments.split_first() {
+                self.push(helper.span, KEYWORD, 0);
+                self.tokens(arguments);
+            }
+        }
+        if let Some(close) = block.close_span {
+            self.close(close, block.name);
+        }
+        for body in block.bodies() {
+            self.nodes(body);
+        }
+    }
+
+    /// Highlights the name in a `{{/name}}` tag.
+    fn close(&mut self, close: Span, name: &str) {
+        if let Some(slash) = self.source[close.start..close.end].find('/') {
+            let start = close.start + slash + 1;
+            if self.source[start..close.end].starts_with(name) {
+                self.push(Span::new(start, start + name.len()), KEYWORD, 0);
+            }
+        }
+    }
+
+    fn tokens(&mut self, tokens: &[SyntaxToken<'_>]) {
+        for token in tokens {
+            match token.kind {
+                SyntaxTokenKind::Variable => self.variable(*token),
+                SyntaxTokenKind::PrivateVariable => {
+                    self.push(token.span, LOCAL, READONLY | DEFAULT_LIBRARY);
+                }
+                SyntaxTokenKind::String => self.push(token.span, STRING, 0),
+                SyntaxTokenKind::Number => self.push(token.span, NUMBER, 0),
+                SyntaxTokenKind::Keyword => self.push(token.span, KEYWORD, 0),
+                SyntaxTokenKind::HashKey => self.push(token.span, HASH_KEY, 0),
+                SyntaxTokenKind::Subexpression => self.call(&token.subexpression_tokens()),
+                SyntaxTokenKind::Punctuation => {}
+            }
+        }
+    }
+
+    fn variable(&mut self, token: SyntaxToken<'_>) {
+        if let Some(reference) = self.references.get(&token.span.start) {
+            let (kind, modifiers) = match reference.binding {
+                Binding::Root => {
+                    let known = self
+                        .fields
+                        .as_ref()
+                        .is_none_or(|fields| fields.contains(reference.name.as_str()));
+                    (FIELD, if known { 0 } else { UNRESOLVED })
+                }
+                Binding::Local(_) if reference.declaration => (LOCAL, DECLARATION),
+                Binding::Local(_) => (LOCAL, 0),
+                Binding::This(_) => (FIELD, 0),
+            };
+            self.push(reference.path_span, kind, modifiers);
+        } else if let Some(member) = token.text.strip_prefix("this") {
+            if !(member.is_empty() || member.starts_with('.')) {
+                return;
+            }
+            let start = token.span.start;
+            self.push(Span::new(start, start + "this".len()), KEYWORD, 0);
+            if let Some(path) = member.strip_prefix('.') {
+                let path_start = token.span.end - path.len();
+                self.push(Span::new(path_start, token.span.end), FIELD, 0);
+            }
+        }
+    }
+
+    /// Adds a token, split at line breaks as the protocol requires.
+    fn push(&mut self, span: Span, kind: u32, modifiers: u32) {
+        let mut start = span.start;
+        for line in self.source[span.start..span.end].split_inclusive('\n') {
+            let text = line.trim_end_matches(['\n', '\r']);
+            if !text.is_empty() {
+                self.tokens
+                    .push((Span::new(start, start + text.len()), kind, modifiers));
+            }
+            start += line.len();
+        }
+    }
+}
+
+/// Encodes sorted tokens as UTF-16 deltas from the previous token's start.
+fn encode(source: &str, tokens: &[(Span, u32, u32)]) -> Vec<SemanticToken> {
+    let mut data = Vec::with_capacity(tokens.len());
+    let (mut offset, mut line, mut character) = (0, 0, 0);
+    let (mut previous_line, mut previous_character) = (0, 0);
+    for (span, kind, modifiers) in tokens {
+        for c in source[offset..span.start].chars() {
+            if c == '\n' {
+                line += 1;
+                character = 0;
+            } else {
+                character += c.len_utf16() as u32;
+            }
+        }
+        offset = span.start;
+        data.push(SemanticToken {
+            delta_line: line - previous_line,
+            delta_start: if line == previous_line {
+                character - previous_character
+            } else {
+                character
+            },
+            length: source[span.start..span.end].encode_utf16().count() as u32,
+            token_type: *kind,
+            token_modifiers_bitset: *modifiers,
+        });
+        (previous_line, previous_character) = (line, character);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+    use rusty_handlebars_parser::parse_template;
+
+    use super::*;
+    use crate::project::FieldInfo;
+
+    /// Decodes tokens back to `(text, type, modifiers)` for single-line sources.
+    fn decoded(source: &str, tokens: &SemanticTokens) -> Vec<(String, u32, u32)> {
+        let mut character = 0;
+        tokens
+            .data
+            .iter()
+            .map(|token| {
+                assert_eq!(token.delta_line, 0);
+                character += token.delta_start as usize;
+                let text = source
+                    .chars()
+                    .skip(character)
+                    .take(token.length as usize)
+                    .collect();
+                (text, token.token_type, token.token_modifiers_bitset)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn classifies_template_expressions() {
+        let contexts = [TemplateContext {
+            name: "Page".to_owned(),
+            template: "page.rhbs".into(),
+            helpers: Vec::new(),
+            blocks: Vec::new(),
+            fields: vec![FieldInfo {
+                name: "items".to_owned(),
+                ty: "Vec<Item>".to_owned(),
+                source: "src/lib.rs".into(),
+            }],
+        }];
+        let source = "{{!-- é --}}{{#each items as |item|}}{{@index}} {{upper item.name}}{{#if (eq this.id 2)}}{{titel}}{{/if}}{{else}}{{> \"row.rhbs\"}}{{/each}}";
+        let tokens = semantic_tokens(&parse_template(source), None, &contexts);
+        assert_eq!(
+            decoded(source, &tokens),
+            [
+                ("{{!-- é --}}".to_owned(), COMMENT, 0),
+                ("each".to_owned(), KEYWORD, 0),
+                ("items".to_owned(), FIELD, 0),
+                ("as".to_owned(), KEYWORD, 0),
+                ("item".to_owned(), LOCAL, DECLARATION),
+                ("@index".to_owned(), LOCAL, READONLY | DEFAULT_LIBRARY),
+                ("upper".to_owned(), HELPER, 0),
+                ("item.name".to_owned(), LOCAL, 0),
+                ("if".to_owned(), KEYWORD, 0),
+                ("eq".to_owned(), HELPER, 0),
+                ("this".to_owned(), KEYWORD, 0),
+                ("id".to_owned(), FIELD, 0),
+                ("2".to_owned(), NUMBER, 0),
+                ("titel".to_owned(), FIELD, UNRESOLVED),
+                ("if".to_owned(), KEYWORD, 0),
+                ("else".to_owned(), KEYWORD, 0),
+                ("row.rhbs".to_owned(), STRING, 0),
+                ("each".to_owned(), KEYWORD, 0),
+            ]
+        );
+        let unindexed = semantic_tokens(&parse_template(source), None, &[]);
+        assert_eq!(
+            decoded(source, &unindexed)[13],
+            ("titel".to_owned(), FIELD, 0)
+        );
+    }
+
+    #[test]
+    fn splits_multiline_tokens_and_filters_ranges() {
+        let source = "{{!--\n  a\n--}}\n{{x}}";
+        let tokens = semantic_tokens(&parse_template(source), None, &[]);
+        assert_eq!(
+            tokens
+                .data
+                .iter()
+                .map(|token| (token.delta_line, token.delta_start, token.length))
+                .collect::<Vec<_>>(),
+            [(0, 0, 5), (1, 0, 3), (1, 0, 4), (1, 2, 1)]
+        );
+        let range = Range::new(Position::new(3, 0), Position::new(3, 5));
+        let tokens = semantic_tokens(&parse_template(source), Some(range), &[]);
+        assert_eq!(tokens.data.len(), 1);
+        assert_eq!(
+            (tokens.data[0].delta_line, tokens.data[0].delta_start),
+            (3, 2)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use std::{
+    collections::HashMap,
     error::Error,
+    fs,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -8,24 +10,30 @@ use lsp_server::{Connection, Message, Notification, Request, RequestId, Response
 use lsp_types::{
     CompletionOptions, CompletionParams, DiagnosticSeverity, DidChangeTextDocumentParams,
     DidChangeWatchedFilesParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-    DidSaveTextDocumentParams, DocumentHighlightParams, DocumentSymbolParams, FoldingRangeParams,
+    DidSaveTextDocumentParams, DocumentFormattingParams, DocumentHighlightParams,
+    DocumentRangeFormattingParams, DocumentSymbolParams, FoldingRangeParams, FormattingOptions,
     GotoDefinitionParams, HoverParams, HoverProviderCapability, Location, OneOf,
-    PositionEncodingKind, PublishDiagnosticsParams, SelectionRangeParams,
-    SelectionRangeProviderCapability, ServerCapabilities, SignatureHelpOptions,
-    SignatureHelpParams, TextDocumentIdentifier, TextDocumentSyncCapability, TextDocumentSyncKind,
-    Uri,
+    PositionEncodingKind, PrepareRenameResponse, PublishDiagnosticsParams, Range, ReferenceParams,
+    RenameOptions, RenameParams, SelectionRangeParams, SelectionRangeProviderCapability,
+    SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelpOptions,
+    SignatureHelpParams, TextDocumentIdentifier, TextDocumentPositionParams,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Uri, WorkspaceEdit,
 };
 use rusty_handlebars_parser::{
-    add_builtins, parse_template, BlockMap, Compiler, Options, Severity,
+    add_builtins, is_plain_text, BlockMap, Compiler, Options, ParsedTemplate, Severity,
 };
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 use url::Url;
 
 use crate::{
-    documents::{byte_to_position, span_to_range, Documents},
-    features,
-    project::{ProjectIndex, TemplateContext},
+    documents::{position_to_byte, span_to_range, Documents},
+    features::{self, Symbol},
+    project::{field_at, field_span, normalize_path, ProjectIndex, TemplateContext},
+    scope::{self, ResolvedFile},
+    semantic,
 };
 
 type ServerResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
@@ -33,9 +41,16 @@ type ServerResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 pub fn capabilities() -> ServerCapabilities {
     ServerCapabilities {
         position_encoding: Some(PositionEncodingKind::UTF16),
-        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
         completion_provider: Some(CompletionOptions {
-            trigger_characters: Some(vec!["{".to_owned(), "@".to_owned(), "/".to_owned()]),
+            trigger_characters: Some(vec![
+                "{".to_owned(),
+                "@".to_owned(),
+                "/".to_owned(),
+                ".".to_owned(),
+            ]),
             ..Default::default()
         }),
         hover_provider: Some(HoverProviderCapability::Simple(true)),
@@ -48,6 +63,21 @@ pub fn capabilities() -> ServerCapabilities {
         selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
         definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: semantic::legend(),
+                range: Some(true),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
         ..Default::default()
     }
 }
@@ -127,11 +157,9 @@ fn handle_notification(
         }
         "textDocument/didChange" => {
             let params: DidChangeTextDocumentParams = from_value(notification.params)?;
-            if let Some(change) = params.content_changes.into_iter().last() {
-                let uri = params.text_document.uri;
-                documents.change(&uri, change.text, params.text_document.version);
-                publish_diagnostics(connection, documents, project, &uri)?;
-            }
+            let uri = params.text_document.uri;
+            documents.change(&uri, params.content_changes, params.text_document.version);
+            publish_diagnostics(connection, documents, project, &uri)?;
         }
         "textDocument/didClose" => {
             let params: DidCloseTextDocumentParams = from_value(notification.params)?;
@@ -170,6 +198,12 @@ fn handle_request(
     request: Request,
 ) -> ServerResult<()> {
     let id = request.id.clone();
+    // Rust sources are only opened to find references to their fields.
+    if request.method != "textDocument/references"
+        && request_path(&request).is_some_and(|path| is_rust_source(&path))
+    {
+        return send_response(connection, id, Value::Null);
+    }
     match request.method.as_str() {
         "textDocument/completion" => {
             let params: CompletionParams = from_value(request.params)?;
@@ -184,7 +218,13 @@ fn handle_request(
                 |text| {
                     let mut response =
                         features::completions(text, params.text_document_position.position);
-                    features::add_project_completions(&mut response, contexts);
+                    features::add_project_completions(
+                        &mut response,
+                        text,
+                        params.text_document_position.position,
+                        project,
+                        contexts,
+                    );
                     response
                 },
             )?;
@@ -203,6 +243,7 @@ fn handle_request(
                     features::project_hover(
                         text,
                         params.text_document_position_params.position,
+                        project,
                         contexts,
                     )
                     .or_else(|| {
@@ -219,12 +260,79 @@ fn handle_request(
                 .unwrap_or_default();
             with_document(connection, documents, id, uri, |text| {
                 definition_location(
+                    documents,
+                    uri,
                     text,
                     params.text_document_position_params.position,
                     contexts,
                 )
             })?;
         }
+        "textDocument/semanticTokens/full" => {
+            let params: SemanticTokensParams = from_value(request.params)?;
+            let contexts = uri_path(&params.text_document.uri)
+                .map(|path| project.contexts_for(&path))
+                .unwrap_or_default();
+            with_parsed(
+                connection,
+                documents,
+                id,
+                &params.text_document.uri,
+                |parsed| {
+                    SemanticTokensResult::Tokens(semantic::semantic_tokens(parsed, None, contexts))
+                },
+            )?;
+        }
+        "textDocument/semanticTokens/range" => {
+            let params: SemanticTokensRangeParams = from_value(request.params)?;
+            let contexts = uri_path(&params.text_document.uri)
+                .map(|path| project.contexts_for(&path))
+                .unwrap_or_default();
+            with_parsed(
+                connection,
+                documents,
+                id,
+                &params.text_document.uri,
+                |parsed| {
+                    SemanticTokensRangeResult::Tokens(semantic::semantic_tokens(
+                        parsed,
+                        Some(params.range),
+                        contexts,
+                    ))
+                },
+            )?;
+        }
+        "textDocument/references" => {
+            let params: ReferenceParams = from_value(request.params)?;
+            send_response(
+                connection,
+                id,
+                references(documents, root, project, &params),
+            )?;
+        }
+        "textDocument/prepareRename" => {
+            let params: TextDocumentPositionParams = from_value(request.params)?;
+            let contexts = uri_path(&params.text_document.uri)
+                .map(|path| project.contexts_for(&path))
+                .unwrap_or_default();
+            with_document(
+                connection,
+                documents,
+                id,
+                &params.text_document.uri,
+                |text| {
+                    features::rename_target(text, params.position, contexts)
+                        .map(|(_, range)| PrepareRenameResponse::Range(range))
+                },
+            )?;
+        }
+        "textDocument/rename" => {
+            let params: RenameParams = from_value(request.params)?;
+            match rename(documents, root, project, &params) {
+                Ok(edit) => send_response(connection, id, edit)?,
+                Err(message) => send_error(connection, id, -32803, &message)?,
+            }
+        }
         "textDocument/documentSymbol" => {
             let params: DocumentSymbolParams = from_value(request.params)?;
             with_document(
@@ -270,6 +378,33 @@ fn handle_request(
                 },
             )?;
         }
+        "textDocument/formatting" => {
+            let params: DocumentFormattingParams = from_value(request.params)?;
+            with_document(
+                connection,
+                documents,
+                id,
+                &params.text_document.uri,
+                |text| format_document(&params.text_document.uri, text, &params.options, None),
+            )?;
+        }
+        "textDocument/rangeFormatting" => {
+            let params: DocumentRangeFormattingParams = from_value(request.params)?;
+            with_document(
+                connection,
+                documents,
+                id,
+                &params.text_document.uri,
+                |text| {
+                    format_document(
+                        &params.text_document.uri,
+                        text,
+                        &params.options,
+                        Some(params.range),
+                    )
+                },
+            )?;
+        }
         "textDocument/signatureHelp" => {
             let params: SignatureHelpParams = from_value(request.params)?;
             with_document(
@@ -284,7 +419,9 @@ fn handle_request(
         }
         "rustyHandlebars/showGeneratedRust" => {
             let params: TextDocumentIdentifier = from_value(request.params)?;
-            with_document(connection, documents, id, &params.uri, generated_rust)?;
+            with_document(connection, documents, id, &params.uri, |text| {
+                generated_rust(text, root)
+            })?;
         }
         "rustyHandlebars/projectContexts" => {
             let params: TextDocumentIdentifier = from_value(request.params)?;
@@ -305,41 +442,260 @@ fn handle_request(
     Ok(())
 }
 
-fn generated_rust(source: &str) -> Result<String, String> {
+fn generated_rust(source: &str, root: Option<&Path>) -> Result<String, String> {
     let mut blocks = BlockMap::new();
     add_builtins(&mut blocks);
-    Compiler::new(
+    let mut compiler = Compiler::new(
         Options {
             root_var_name: Some("self"),
             write_var_name: "f",
         },
         blocks,
-    )
-    .compile(source)
-    .map(|rust| rust.code)
-    .map_err(|error| error.to_string())
+    );
+    if let Some(root) = root {
+        compiler = compiler.with_partial_loader(root.to_path_buf());
+    }
+    compiler
+        .compile(source)
+        .map(|rust| rust.code)
+        .map_err(|error| error.to_string())
 }
 
 fn definition_location(
+    documents: &Documents,
+    uri: &Uri,
     source: &str,
     position: lsp_types::Position,
     contexts: &[TemplateContext],
 ) -> Option<Location> {
-    let field = features::project_field_at(source, position, contexts)?;
-    let rust_source = std::fs::read_to_string(&field.source).ok()?;
-    let needle = format!("{}:", field.name);
-    let start = rust_source.find(&needle)?;
-    let end = start + field.name.len();
-    let uri = path_uri(&field.source)?;
+    match features::symbol_at(source, position)?.0 {
+        Symbol::Field(name) => contexts
+            .iter()
+            .find_map(|context| field_location(documents, context, &name)),
+        Symbol::Local(declaration) if declaration.file == 0 => Some(Location::new(
+            uri.clone(),
+            span_to_range(source, declaration.span),
+        )),
+        _ => None,
+    }
+}
+
+/// Lists the reads of the symbol at a position in a template, or of the field
+/// declared at a position in a Rust source, across templates and partials.
+fn references(
+    documents: &Documents,
+    root: Option<&Path>,
+    project: &ProjectIndex,
+    params: &ReferenceParams,
+) -> Option<Vec<Location>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+    let include_declaration = params.context.include_declaration;
+    let path = uri_path(uri)?;
+    let mut locations = Vec::new();
+    if is_rust_source(&path) {
+        let source = read_text(documents, &path)?;
+        let (struct_name, field) = field_at(&source, position_to_byte(&source, position))?;
+        let path = normalize_path(&path);
+        let symbol = Symbol::Field(field.clone());
+        for context in project.contexts().filter(|context| {
+            context.name == struct_name
+                && context.fields.iter().any(|candidate| {
+                    candidate.name == field && normalize_path(&candidate.source) == path
+                })
+        }) {
+            let (Some(template_uri), Some(template)) = (
+                path_uri(&context.template),
+                read_text(documents, &context.template),
+            ) else {
+                continue;
+            };
+            let files = resolve_files(documents, root, &template_uri, &template);
+            push_references(&mut locations, &files, &symbol, include_declaration);
+            if include_declaration {
+                push_location(&mut locations, field_location(documents, context, &field));
+            }
+        }
+        return Some(locations);
+    }
+    let source = documents.get(uri)?.text();
+    let (symbol, _) = features::symbol_at(source, position)?;
+    let files = resolve_files(documents, root, uri, source);
+    push_references(&mut locations, &files, &symbol, include_declaration);
+    if let (Symbol::Field(name), true) = (&symbol, include_declaration) {
+        for context in project.contexts_for(&path) {
+            push_location(&mut locations, field_location(documents, context, name));
+        }
+    }
+    Some(locations)
+}
+
+fn push_references(
+    locations: &mut Vec<Location>,
+    files: &[(Uri, ResolvedFile)],
+    symbol: &Symbol,
+    include_declaration: bool,
+) {
+    for (uri, file) in files {
+        for reference in features::symbol_references(file, symbol) {
+            if include_declaration || !reference.declaration {
+                push_location(
+                    locations,
+                    Some(Location::new(
+                        uri.clone(),
+                        span_to_range(&file.source, reference.span),
+                    )),
+                );
+            }
+        }
+    }
+}
+
+/// Adds `location` once: a partial included more than once appears once per
+/// include.
+fn push_location(locations: &mut Vec<Location>, location: Option<Location>) {
+    if let Some(location) = location {
+        if !locations.contains(&location) {
+            locations.push(location);
+        }
+    }
+}
+
+/// Resolves `source` and the partials it includes, with each file's URI.
+fn resolve_files(
+    documents: &Documents,
+    root: Option<&Path>,
+    uri: &Uri,
+    source: &str,
+) -> Vec<(Uri, ResolvedFile)> {
+    scope::resolve(source, |partial| read_text(documents, &root?.join(partial)))
+        .into_iter()
+        .filter_map(|file| {
+            let file_uri = match &file.partial {
+                None => uri.clone(),
+                Some(partial) => path_uri(&root?.join(partial))?,
+            };
+            Some((file_uri, file))
+        })
+        .collect()
+}
+
+/// Finds where the struct of `context` declares `field`.
+fn field_location(
+    documents: &Documents,
+    context: &TemplateContext,
+    field: &str,
+) -> Option<Location> {
+    let info = context.fields.iter().find(|info| info.name == field)?;
+    let source = read_text(documents, &info.source)?;
+    let span = field_span(&source, &context.name, field)?;
     Some(Location::new(
-        uri,
-        lsp_types::Range::new(
-            byte_to_position(&rust_source, start),
-            byte_to_position(&rust_source, end),
-        ),
+        path_uri(&info.source)?,
+        span_to_range(&source, span),
     ))
 }
 
+/// Renames a block local in its template, or a context field in every
+/// template rendering it and in the struct declaring it.
+///
+/// Partials are edited where they read the renamed name in the scope they are
+/// included in. A partial or layout edited here that another template also
+/// renders would break that template, so the rename is refused instead.
+// `Uri` caches its parsed form, which does not affect hashing or equality.
+#[allow(clippy::mutable_key_type)]
+fn rename(
+    documents: &Documents,
+    root: Option<&Path>,
+    project: &ProjectIndex,
+    params: &RenameParams,
+) -> Result<Option<WorkspaceEdit>, String> {
+    let uri = &params.text_document_position.text_document.uri;
+    let new_name = &params.new_name;
+    let document = documents.get(uri).ok_or("document is not open")?;
+    if syn::parse_str::<syn::Ident>(new_name).is_err() {
+        return Err(format!("`{new_name}` is not a valid Rust identifier"));
+    }
+    let contexts = uri_path(uri)
+        .map(|path| project.contexts_for(&path))
+        .unwrap_or_default();
+    let Some((target, _)) = features::rename_target(
+        document.text(),
+        params.text_document_position.position,
+        contexts,
+    ) else {
+        return Ok(None);
+    };
+    let mut changes: HashMap<Uri, Vec<TextEdit>> = HashMap::new();
+    let mut shared = Vec::new();
+    for (file_uri, file) in resolve_files(documents, root, uri, document.text()) {
+        let edits = features::rename_edits(&file, &target, new_name);
+        if edits.is_empty() {
+            continue;
+        }
+        if let Some(partial) = &file.partial {
+            if !shared.iter().any(|(shared, _)| *shared == file_uri) {
+                shared.push((file_uri.clone(), partial.clone()));
+            }
+        }
+        // A partial included more than once reads the name at each include.
+        let file_edits = changes.entry(file_uri).or_default();
+        for edit in edits {
+            if !file_edits.contains(&edit) {
+                file_edits.push(edit);
+            }
+        }
+    }
+    if !shared.is_empty() {
+        let path = uri_path(uri).map(|path| normalize_path(&path));
+        for context in project
+            .contexts()
+            .filter(|context| Some(&context.template) != path.as_ref())
+        {
+            let (Some(template_uri), Some(template)) = (
+                path_uri(&context.template),
+                read_text(documents, &context.template),
+            ) else {
+                continue;
+            };
+            let files = resolve_files(documents, root, &template_uri, &template);
+            if let Some((_, partial)) = shared
+                .iter()
+                .find(|(shared, _)| files.iter().any(|(file_uri, _)| file_uri == shared))
+            {
+                return Err(format!(
+                    "`{partial}` is also rendered by `{}`, which this rename does not update",
+                    context.name
+                ));
+            }
+        }
+    }
+    if let Symbol::Field(name) = &target {
+        for context in contexts
+            .iter()
+            .filter(|context| context.fields.iter().any(|field| field.name == *name))
+        {
+            let location = field_location(documents, context, name)
+                .ok_or_else(|| format!("cannot find field `{name}` of `{}`", context.name))?;
+            changes.entry(location.uri).or_default().push(TextEdit {
+                range: location.range,
+                new_text: new_name.clone(),
+            });
+        }
+    }
+    Ok(Some(WorkspaceEdit {
+        changes: Some(changes),
+        ..Default::default()
+    }))
+}
+
+/// Reads a file from its open document, or from disk when it is not open.
+fn read_text(documents: &Documents, path: &Path) -> Option<String> {
+    match path_uri(path).and_then(|uri| documents.get(&uri)) {
+        Some(document) => Some(document.text().to_owned()),
+        None => fs::read_to_string(path).ok(),
+    }
+}
+
 fn publish_diagnostics(
     connection: &Connection,
     documents: &Documents,
@@ -349,12 +705,15 @@ fn publish_diagnostics(
     let Some(document) = documents.get(uri) else {
         return Ok(());
     };
-    let parsed = parse_template(&document.text);
+    if uri_path(uri).is_some_and(|path| is_rust_source(&path)) {
+        return Ok(());
+    }
+    let parsed = document.parsed();
     let mut diagnostics = parsed
         .diagnostics
-        .into_iter()
+        .iter()
         .map(|diagnostic| lsp_types::Diagnostic {
-            range: span_to_range(&document.text, diagnostic.span),
+            range: span_to_range(parsed.source, diagnostic.span),
             severity: Some(match diagnostic.severity {
                 Severity::Error => DiagnosticSeverity::ERROR,
                 Severity::Warning => DiagnosticSeverity::WARNING,
@@ -363,7 +722,7 @@ fn publish_diagnostics(
                 diagnostic.code.as_str().to_owned(),
             )),
             source: Some("rusty-handlebars".to_owned()),
-            message: diagnostic.message,
+            message: diagnostic.message.clone(),
             ..Default::default()
         })
         .collect::<Vec<_>>();
@@ -371,10 +730,10 @@ fn publish_diagnostics(
         .map(|path| project.contexts_for(&path))
         .unwrap_or_default();
     diagnostics.extend(
-        features::project_diagnostics(&document.text, contexts)
+        features::project_diagnostics(parsed, contexts)
             .into_iter()
             .map(|diagnostic| lsp_types::Diagnostic {
-                range: span_to_range(&document.text, diagnostic.span),
+                range: span_to_range(parsed.source, diagnostic.span),
                 severity: Some(DiagnosticSeverity::ERROR),
                 code: Some(lsp_types::NumberOrString::String(
                     diagnostic.code.to_owned(),
@@ -399,7 +758,21 @@ fn with_document<T: serde::Serialize>(
     operation: impl FnOnce(&str) -> T,
 ) -> ServerResult<()> {
     match documents.get(uri) {
-        Some(document) => send_response(connection, id, operation(&document.text)),
+        Some(document) => send_response(connection, id, operation(document.text())),
+        None => send_error(connection, id, -32602, "document is not open"),
+    }
+}
+
+/// Like [`with_document`], with the document's cached parse.
+fn with_parsed<T: serde::Serialize>(
+    connection: &Connection,
+    documents: &Documents,
+    id: RequestId,
+    uri: &Uri,
+    operation: impl FnOnce(&ParsedTemplate<'_>) -> T,
+) -> ServerResult<()> {
+    match documents.get(uri) {
+        Some(document) => send_response(connection, id, operation(document.parsed())),
         None => send_error(connection, id, -32602, "document is not open"),
     }
 }
@@ -466,6 +839,20 @@ fn workspace_root(parameters: &Value) -> Option<PathBuf> {
         })
 }
 
+/// Formats a document, leaving plain-text templates as they are since their
+/// indentation is part of what they render.
+fn format_document(
+    uri: &Uri,
+    text: &str,
+    options: &FormattingOptions,
+    range: Option<Range>,
+) -> Option<Vec<TextEdit>> {
+    if uri_path(uri).is_some_and(is_plain_text) {
+        return Some(Vec::new());
+    }
+    features::formatting(text, options, range)
+}
+
 fn uri_path(uri: &Uri) -> Option<PathBuf> {
     Url::parse(uri.as_str()).ok()?.to_file_path().ok()
 }
@@ -479,6 +866,16 @@ fn path_uri(path: &Path) -> Option<Uri> {
     Uri::from_str(Url::from_file_path(absolute).ok()?.as_str()).ok()
 }
 
+fn is_rust_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "rs")
+}
+
+/// Returns the path of the document a request is about, if it names one.
+fn request_path(request: &Request) -> Option<PathBuf> {
+    let uri = request.params.get("textDocument")?.get("uri")?.as_str()?;
+    uri_path(&Uri::from_str(uri).ok()?)
+}
+
 fn is_project_input(path: &Path) -> bool {
     path.file_name().is_some_and(|name| {
         name == "Cargo.toml"
@@ -519,6 +916,7 @@ mod tests {
     use std::{thread, time::Duration};
 
     use lsp_server::{Connection, Message, Notification, Request, RequestId};
+    use lsp_types::{CompletionItemKind, CompletionResponse, Hover, HoverContents};
     use serde_json::json;
     use tempfile::tempdir;
 
@@ -591,6 +989,295 @@ mod tests {
         );
     }
 
+    /// A one-package workspace whose page template includes a partial from
+    /// inside and outside an `each` block, with the page open.
+    fn site() -> (tempfile::TempDir, Documents, ProjectIndex) {
+        let directory = tempdir().unwrap();
+        let root = directory.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"site\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("templates")).unwrap();
+        fs::write(
+            root.join("src/lib.rs"),
+            concat!(
+                "#[derive(WithRustyHandlebars)]\n#[template(path = \"templates/page.rhbs\")]\n",
+                "pub struct Page {\n    title: String,\n    items: Vec<String>,\n",
+                "    customer: Customer,\n    billing: Option<&'static Address>,\n",
+                "    orders: Vec<Order>,\n}\n",
+                "pub struct Customer { address: Box<Address> }\n",
+                "pub struct Address { city: String }\n",
+                "pub struct Order { id: u32, lines: BTreeMap<String, Line> }\n",
+                "pub struct Line { total: f64 }\n",
+            ),
+        )
+        .unwrap();
+        fs::write(root.join("templates/row.rhbs"), "<b>{{title}}</b>").unwrap();
+        let page = "{{title}}{{#each items as |item|}}{{> \"templates/row.rhbs\"}}{{item}}{{/each}}{{> \"templates/row.rhbs\"}}";
+        let page_path = root.join("templates/page.rhbs");
+        fs::write(&page_path, page).unwrap();
+        let mut documents = Documents::default();
+        documents.open(path_uri(&page_path).unwrap(), page.to_owned(), 1);
+        let project = ProjectIndex::discover(root).unwrap();
+        (directory, documents, project)
+    }
+
+    fn position(path: &Path, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(path_uri(path).unwrap()),
+            lsp_types::Position::new(line, character),
+        )
+    }
+
+    #[test]
+    fn renames_fields_in_partials_and_rust() {
+        let (directory, documents, project) = site();
+        let root = directory.path();
+        let page_path = root.join("templates/page.rhbs");
+        let params = |new_name: &str| RenameParams {
+            text_document_position: position(&page_path, 0, 3),
+            new_name: new_name.to_owned(),
+            work_done_progress_params: Default::default(),
+        };
+
+        let changes = rename(&documents, Some(root), &project, &params("heading"))
+            .unwrap()
+            .unwrap()
+            .changes
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let starts = |path: &Path| {
+            let uri = path_uri(path).unwrap();
+            let (_, edits) = changes.iter().find(|(edited, _)| *edited == uri).unwrap();
+            edits
+                .iter()
+                .map(|edit| (edit.range.start.line, edit.range.start.character))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(changes.len(), 3);
+        assert_eq!(starts(&page_path), [(0, 2)]);
+        assert_eq!(starts(&root.join("templates/row.rhbs")), [(0, 5)]);
+        assert_eq!(starts(&root.join("src/lib.rs")), [(3, 4)]);
+        assert!(rename(&documents, Some(root), &project, &params("two words")).is_err());
+    }
+
+    #[test]
+    fn renames_and_finds_fields_in_extended_layouts() {
+        let (directory, _, project) = site();
+        let root = directory.path();
+        fs::write(
+            root.join("templates/base.rhbs"),
+            "<h1>{{title}}</h1>{{#block main}}{{/block}}",
+        )
+        .unwrap();
+        let page =
+            "{{#extends \"templates/base.rhbs\"}}{{#block main}}{{title}}{{/block}}{{/extends}}";
+        let page_path = root.join("templates/page.rhbs");
+        fs::write(&page_path, page).unwrap();
+        let mut documents = Documents::default();
+        documents.open(path_uri(&page_path).unwrap(), page.to_owned(), 1);
+        let title = position(&page_path, 0, 52);
+
+        let params = RenameParams {
+            text_document_position: title.clone(),
+            new_name: "heading".to_owned(),
+            work_done_progress_params: Default::default(),
+        };
+        let changes = rename(&documents, Some(root), &project, &params)
+            .unwrap()
+            .unwrap()
+            .changes
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let base = path_uri(&root.join("templates/base.rhbs")).unwrap();
+        let (_, edits) = changes.iter().find(|(edited, _)| *edited == base).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(edits[0].range.start, lsp_types::Position::new(0, 6));
+
+        let params = ReferenceParams {
+            text_document_position: title,
+            context: lsp_types::ReferenceContext {
+                include_declaration: false,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let locations = references(&documents, Some(root), &project, &params).unwrap();
+        assert!(locations.iter().any(|location| location.uri == base));
+        assert_eq!(locations.len(), 2);
+    }
+
+    #[test]
+    fn refuses_to_rename_fields_in_shared_layouts() {
+        let (directory, _, _) = site();
+        let root = directory.path();
+        fs::write(
+            root.join("templates/base.rhbs"),
+            "<h1>{{title}}</h1>{{#block main}}{{/block}}",
+        )
+        .unwrap();
+        let page =
+            "{{#extends \"templates/base.rhbs\"}}{{#block main}}{{title}}{{/block}}{{/extends}}";
+        let page_path = root.join("templates/page.rhbs");
+        fs::write(&page_path, page).unwrap();
+        fs::write(root.join("templates/other.rhbs"), page).unwrap();
+        let mut lib = fs::read_to_string(root.join("src/lib.rs")).unwrap();
+        lib.push_str(concat!(
+            "#[derive(WithRustyHandlebars)]\n#[template(path = \"templates/other.rhbs\")]\n",
+            "pub struct Other {\n    title: String,\n}\n",
+        ));
+        fs::write(root.join("src/lib.rs"), lib).unwrap();
+        let project = ProjectIndex::discover(root).unwrap();
+        let mut documents = Documents::default();
+        documents.open(path_uri(&page_path).unwrap(), page.to_owned(), 1);
+
+        let params = RenameParams {
+            text_document_position: position(&page_path, 0, 52),
+            new_name: "heading".to_owned(),
+            work_done_progress_params: Default::default(),
+        };
+        let error = rename(&documents, Some(root), &project, &params).unwrap_err();
+        assert!(error.contains("templates/base.rhbs"), "{error}");
+        assert!(error.contains("Other"), "{error}");
+
+        fs::write(root.join("templates/other.rhbs"), "{{title}}").unwrap();
+        let edited = rename(&documents, Some(root), &project, &params)
+            .unwrap()
+            .unwrap()
+            .changes
+            .unwrap()
+            .len();
+        assert_eq!(edited, 3);
+    }
+
+    #[test]
+    fn finds_references_from_templates_and_rust() {
+        let (directory, documents, project) = site();
+        let root = directory.path();
+        let params = |position, include_declaration| ReferenceParams {
+            text_document_position: position,
+            context: lsp_types::ReferenceContext {
+                include_declaration,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let starts = |locations: Vec<Location>| {
+            locations
+                .into_iter()
+                .map(|location| {
+                    let path = uri_path(&location.uri).unwrap();
+                    let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+                    (
+                        name,
+                        location.range.start.line,
+                        location.range.start.character,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let page = root.join("templates/page.rhbs");
+        let lib = root.join("src/lib.rs");
+
+        let from_rust = references(
+            &documents,
+            Some(root),
+            &project,
+            &params(position(&lib, 3, 6), true),
+        );
+        assert_eq!(
+            starts(from_rust.unwrap()),
+            [
+                ("page.rhbs".to_owned(), 0, 2),
+                ("row.rhbs".to_owned(), 0, 5),
+                ("lib.rs".to_owned(), 3, 4)
+            ]
+        );
+        let local = references(
+            &documents,
+            Some(root),
+            &project,
+            &params(position(&page, 0, 63), false),
+        );
+        assert_eq!(starts(local.unwrap()), [("page.rhbs".to_owned(), 0, 62)]);
+        let local = references(
+            &documents,
+            Some(root),
+            &project,
+            &params(position(&page, 0, 63), true),
+        );
+        assert_eq!(starts(local.unwrap()).len(), 2);
+        assert_eq!(
+            definition_location(
+                &documents,
+                &path_uri(&page).unwrap(),
+                documents.get(&path_uri(&page).unwrap()).unwrap().text(),
+                lsp_types::Position::new(0, 3),
+                project.contexts_for(&page),
+            )
+            .map(|location| location.range.start),
+            Some(lsp_types::Position::new(3, 4))
+        );
+    }
+
+    #[test]
+    fn completes_and_hovers_nested_fields() {
+        let (directory, _, project) = site();
+        let contexts = project.contexts_for(&directory.path().join("templates/page.rhbs"));
+        let source = "{{customer.address.city}}{{#with_ref customer}}{{}}{{/with_ref}}{{#if_some billing}}{{city}}{{/if_some}}{{#each_ref orders as |order|}}{{order.}}{{#each order.lines}}{{this.1.}}{{/each}}{{/each_ref}}";
+        let after = |needle: &str| {
+            lsp_types::Position::new(0, (source.find(needle).unwrap() + needle.len()) as u32)
+        };
+        let labels = |position| {
+            let mut response = features::completions(source, position);
+            features::add_project_completions(&mut response, source, position, &project, contexts);
+            let CompletionResponse::Array(items) = response else {
+                unreachable!();
+            };
+            items
+                .into_iter()
+                .filter(|item| item.kind == Some(CompletionItemKind::FIELD))
+                .map(|item| item.label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(after("{{#with_ref customer}}{{")), ["address"]);
+        assert_eq!(labels(after("{{order.")), ["id", "lines"]);
+        assert_eq!(labels(after("{{this.1.")), ["total"]);
+        assert!(labels(after("{{/each_ref}}")).contains(&"title".to_owned()));
+
+        let hover = |position| {
+            let Some(Hover {
+                contents: HoverContents::Markup(markup),
+                ..
+            }) = features::project_hover(source, position, &project, contexts)
+            else {
+                return None;
+            };
+            Some(markup.value)
+        };
+        assert_eq!(
+            hover(after("{{customer.addr")).as_deref(),
+            Some("**`Customer.address`**\n\nRust type: `Box<Address>`")
+        );
+        assert_eq!(
+            hover(after("{{customer.address.ci")).as_deref(),
+            Some("**`Address.city`**\n\nRust type: `String`")
+        );
+        assert_eq!(
+            hover(after("{{ci")).as_deref(),
+            Some("**`Address.city`**\n\nRust type: `String`")
+        );
+        assert_eq!(
+            hover(after("{{#each ord")).as_deref(),
+            Some("**`order`**\n\nRust type: `Order`")
+        );
+    }
+
     #[test]
     fn handles_a_rustrover_document_lifecycle_over_lsp() {
         let (server, client) = Connection::memory();
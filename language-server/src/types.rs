@@ -0,0 +1,232 @@
+//! Resolves the Rust types of template values from declared field types.
+//!
+//! Types are compared by the names the index records, without Rust name
+//! resolution. Field access looks through references and smart pointers,
+//! `each` reads the items of sequences and the `(key, value)` pairs of maps,
+//! and `if_some` reads the value inside an `Option`.
+
+use crate::project::{FieldInfo, ProjectIndex, TemplateContext};
+use crate::scope::{lookup, strip_parents, Binding, Declaration, Frame, ResolvedFile};
+
+const POINTERS: &[&str] = &["Box", "Rc", "Arc", "Cow"];
+const SEQUENCES: &[&str] = &[
+    "Vec",
+    "VecDeque",
+    "LinkedList",
+    "BinaryHeap",
+    "HashSet",
+    "BTreeSet",
+    "IndexSet",
+    "Option",
+];
+const MAPS: &[&str] = &["HashMap", "BTreeMap", "IndexMap"];
+
+/// Removes the references and smart pointers that field access sees through.
+pub fn deref(mut ty: &str) -> &str {
+    loop {
+        if let Some(inner) = ty.strip_prefix('&') {
+            ty = inner.trim_start();
+            continue;
+        }
+        match generic(ty) {
+            Some((name, arguments)) if POINTERS.contains(&name) && arguments.len() == 1 => {
+                ty = arguments[0];
+            }
+            _ => return ty,
+        }
+    }
+}
+
+/// Returns the struct name of `ty`, without its path or generic arguments.
+pub fn type_name(ty: &str) -> &str {
+    let ty = deref(ty);
+    let path = ty.split('<').next().unwrap_or(ty);
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+/// Returns the type of the items `each` iterates in a value of type `ty`.
+pub fn each_item(ty: &str) -> Option<String> {
+    let ty = deref(ty);
+    if let Some(elements) = ty.strip_prefix('[').and_then(|ty| ty.strip_suffix(']')) {
+        // `[T]` or `[T; N]`.
+        return split_top_level(elements, ';')
+            .first()
+            .map(|element| (*element).to_owned());
+    }
+    match generic(ty)? {
+        (name, arguments) if SEQUENCES.contains(&name) && arguments.len() == 1 => {
+            Some(arguments[0].to_owned())
+        }
+        (name, arguments) if MAPS.contains(&name) && arguments.len() == 2 => {
+            Some(format!("({}, {})", arguments[0], arguments[1]))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the type `if_some` binds for a value of type `ty`.
+pub fn option_item(ty: &str) -> Option<String> {
+    match generic(deref(ty))? {
+        ("Option", arguments) if arguments.len() == 1 => Some(arguments[0].to_owned()),
+        _ => None,
+    }
+}
+
+/// Splits `path::Name<A, B>` into `Name` and its generic arguments.
+fn generic(ty: &str) -> Option<(&str, Vec<&str>)> {
+    let open = ty.find('<')?;
+    let arguments = ty[open + 1..].strip_suffix('>')?;
+    let path = &ty[..open];
+    Some((
+        path.rsplit("::").next().unwrap_or(path),
+        split_top_level(arguments, ','),
+    ))
+}
+
+/// Splits `list` at each `separator` outside brackets.
+fn split_top_level(list: &str, separator: char) -> Vec<&str> {
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (index, character) in list.char_indices() {
+        match character {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth = depth.saturating_sub(1),
+            _ if character == separator && depth == 0 => {
+                parts.push(list[start..index].trim());
+                start = index + character.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(list[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Resolves variable paths in a template rendered by `contexts`.
+pub struct Types<'a> {
+    pub project: &'a ProjectIndex,
+    pub contexts: &'a [TemplateContext],
+    pub files: &'a [ResolvedFile],
+}
+
+impl Types<'_> {
+    /// Returns the type of the value `path` reads in the scopes `frames`.
+    pub fn path_type(&self, frames: &[Frame], path: &str) -> Option<String> {
+        self.binding_type(lookup(frames, path)?, path)
+    }
+
+    /// Returns the fields of a value of type `ty`.
+    pub fn fields(&self, ty: &str) -> Option<&[FieldInfo]> {
+        self.project.struct_fields(type_name(ty))
+    }
+
+    /// Returns the fields that a name with no `.` reads in `frames`, with
+    /// the name of the struct declaring each: the fields of the innermost
+    /// value bound as `this`, or of the contexts.
+    pub fn scope_fields(&self, frames: &[Frame]) -> Vec<(String, &FieldInfo)> {
+        match lookup(frames, "this") {
+            Some(Binding::This(declaration)) => {
+                let Some(ty) = self.value_type(declaration) else {
+                    return Vec::new();
+                };
+                self.fields(&ty)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|field| (type_name(&ty).to_owned(), field))
+                    .collect()
+            }
+            _ => self
+                .contexts
+                .iter()
+                .flat_map(|context| {
+                    context
+                        .fields
+                        .iter()
+                        .map(|field| (context.name.clone(), field))
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the type of the value a block binds to `declaration`.
+    pub fn value_type(&self, declaration: Declaration) -> Option<String> {
+        let value = self
+            .files
+            .get(declaration.file)?
+            .values
+            .iter()
+            .find(|value| value.declaration == declaration)?;
+        let ty = self.binding_type(value.binding, &value.path)?;
+        match value.helper.as_str() {
+            "each" | "each_ref" => each_item(&ty),
+            "if_some" | "if_some_ref" => option_item(&ty),
+            _ => Some(ty),
+        }
+    }
+
+    fn binding_type(&self, binding: Binding, path: &str) -> Option<String> {
+        let (_, rest) = strip_parents(path);
+        let mut segments = rest.split('.');
+        let first = segments.next()?;
+        let mut ty = match (binding, first) {
+            (Binding::Root, "this") => self.contexts.first()?.name.clone(),
+            (Binding::Root, name) => self
+                .contexts
+                .iter()
+                .flat_map(|context| &context.fields)
+                .find(|field| field.name == name)?
+                .ty
+                .clone(),
+            (Binding::This(declaration), "this") => self.value_type(declaration)?,
+            (Binding::This(declaration), name) => {
+                self.member_type(&self.value_type(declaration)?, name)?
+            }
+            (Binding::Local(declaration), _) => self.value_type(declaration)?,
+        };
+        for segment in segments {
+            ty = self.member_type(&ty, segment)?;
+        }
+        Some(ty)
+    }
+
+    /// Returns the type of a field, or of a numbered tuple element.
+    fn member_type(&self, ty: &str, member: &str) -> Option<String> {
+        let ty = deref(ty);
+        if let Some(elements) = ty.strip_prefix('(').and_then(|ty| ty.strip_suffix(')')) {
+            let index = member.parse::<usize>().ok()?;
+            return split_top_level(elements, ',')
+                .get(index)
+                .map(|element| (*element).to_owned());
+        }
+        self.fields(ty)?
+            .iter()
+            .find(|field| field.name == member)
+            .map(|field| field.ty.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_through_wrappers_and_collections() {
+        assert_eq!(deref("&Box<crate::Address>"), "crate::Address");
+        assert_eq!(type_name("&Rc<models::Page<T>>"), "Page");
+        assert_eq!(
+            each_item("&Vec<Option<Order>>").as_deref(),
+            Some("Option<Order>")
+        );
+        assert_eq!(each_item("&[Order]").as_deref(), Some("Order"));
+        assert_eq!(each_item("[Vec<Order>; _]").as_deref(), Some("Vec<Order>"));
+        assert_eq!(
+            each_item("HashMap<String, Vec<Order>>").as_deref(),
+            Some("(String, Vec<Order>)")
+        );
+        assert_eq!(each_item("String"), None);
+        assert_eq!(option_item("Option<&Address>").as_deref(), Some("&Address"));
+        assert_eq!(option_item("Vec<Address>"), None);
+    }
+}
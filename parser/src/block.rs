@@ -4,7 +4,7 @@ use crate::{
     compiler::{append_with_depth, Block, BlockFactory, BlockMap, Compile, Local, Rust},
     error::{ParseError, Result},
     expression::Expression,
-    expression_tokenizer::Token,
+    expression_tokenizer::{Token, TokenType},
 };
 fn strip_pipes<'a>(token: Token<'a>, expression: &Expression<'a>) -> Result<&'a str> {
     loop {
@@ -19,7 +19,7 @@ fn strip_pipes<'a>(token: Token<'a>, expression: &Expression<'a>) -> Result<&'a
         }
     }
 }
-fn read_local<'a>(token: &Token<'a>, expression: &Expression<'a>) -> Result<Local> {
+pub(crate) fn read_local<'a>(token: &Token<'a>, expression: &Expression<'a>) -> Result<Local> {
     match token.next()? {
         Some(token) => match token.value {
             "as" => Ok(Local::As(strip_pipes(token, expression)?.to_string())),
@@ -31,6 +31,100 @@ fn read_local<'a>(token: &Token<'a>, expression: &Expression<'a>) -> Result<Loca
         None => Ok(Local::This),
     }
 }
+fn write_condition<'a>(
+    label: &str,
+    prefix: &str,
+    compile: &'a Compile<'a>,
+    token: &Token<'a>,
+    expression: &'a Expression<'a>,
+    rust: &mut Rust,
+) -> Result<()> {
+    match token.next()? {
+        Some(var) => {
+            rust.code.push_str(prefix);
+            rust.code.push_str("::rusty_handlebars::AsBool::as_bool(&");
+            compile.write_var(expression, rust, &var)?;
+            rust.code.push_str("){");
+            Ok(())
+        }
+        None => Err(ParseError::new(
+            &format!("expected variable after {}", label),
+            expression,
+        )),
+    }
+}
+
+fn write_if_some<'a>(
+    by_ref: bool,
+    compile: &'a Compile<'a>,
+    next: &Token<'a>,
+    local: &Local,
+    expression: &'a Expression<'a>,
+    rust: &mut Rust,
+) -> Result<()> {
+    rust.code.push_str("if let Some(");
+    compile.write_local(&mut rust.code, local);
+    rust.code.push_str(") = ");
+    if by_ref {
+        rust.code.push('&');
+    }
+    compile.write_var(expression, rust, next)?;
+    rust.code.push('{');
+    Ok(())
+}
+
+fn write_else<'a>(
+    compile: &'a Compile<'a>,
+    expression: &'a Expression<'a>,
+    local: Option<&Local>,
+    rust: &mut Rust,
+) -> Result<()> {
+    let token = match Token::first(expression.content)? {
+        Some(token) => token,
+        None => {
+            rust.code.push_str("}else{");
+            return Ok(());
+        }
+    };
+    rust.code.push_str("}else ");
+    match (token.value, local) {
+        ("if", _) => write_condition("else if", "if ", compile, &token, expression, rust),
+        ("unless", _) => write_condition("else unless", "if !", compile, &token, expression, rust),
+        ("if_some" | "if_some_ref", Some(local)) => {
+            let next = token.next()?.ok_or_else(|| {
+                ParseError::new(
+                    &format!("expected variable after else {}", token.value),
+                    expression,
+                )
+            })?;
+            let chained = read_local(&next, expression)?;
+            let same_local = match (&chained, local) {
+                (Local::As(chained), Local::As(local)) => chained == local,
+                (Local::As(_), _) => false,
+                _ => true,
+            };
+            if !same_local {
+                return Err(ParseError::new(
+                    "chained if_some must bind the same name as its block",
+                    expression,
+                ));
+            }
+            write_if_some(
+                token.value == "if_some_ref",
+                compile,
+                &next,
+                local,
+                expression,
+                rust,
+            )
+        }
+        (name, _) => Err(ParseError::new(
+            &format!("else {} not expected here", name),
+            expression,
+        )),
+    }
+}
+
 struct IfOrUnless {}
 
 impl IfOrUnless {
@@ -42,26 +136,19 @@ impl IfOrUnless {
         expression: &'a Expression<'a>,
         rust: &mut Rust,
     ) -> Result<IfOrUnless> {
-        match token.next()? {
-            Some(var) => {
-                rust.code.push_str(prefix);
-                rust.code.push_str("::rusty_handlebars::AsBool::as_bool(&");
-                compile.write_var(expression, rust, &var)?;
-                rust.code.push_str("){");
-                Ok(Self {})
-            }
-            None => Err(ParseError::new(
-                &format!("expected variable after {}", label),
-                expression,
-            )),
-        }
+        write_condition(label, prefix, compile, &token, expression, rust)?;
+        Ok(Self {})
     }
 }
 
 impl Block for IfOrUnless {
-    fn handle_else<'a>(&self, _expression: &'a Expression<'a>, rust: &mut Rust) -> Result<()> {
-        rust.code.push_str("}else{");
-        Ok(())
+    fn handle_else<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        write_else(compile, expression, None, rust)
     }
 }
 struct IfFty {}
@@ -96,6 +183,9 @@ impl BlockFactory for UnlessFty {
 }
 struct IfSome {
     local: Local,
+    /// Whether the current branch binds `local`, which only the body and
+    /// chained `else if_some` branches do.
+    bound: Cell<bool>,
 }
 
 impl IfSome {
@@ -116,25 +206,34 @@ impl IfSome {
             )
         })?;
         let local = read_local(&next, expression)?;
-        rust.code.push_str("if let Some(");
-        compile.write_local(&mut rust.code, &local);
-        rust.code.push_str(") = ");
-        if by_ref {
-            rust.code.push('&');
-        }
-        compile.write_var(expression, rust, &next)?;
-        rust.code.push('{');
-        Ok(Self { local })
+        write_if_some(by_ref, compile, &next, &local, expression, rust)?;
+        Ok(Self {
+            local,
+            bound: Cell::new(true),
+        })
     }
 }
 
 impl Block for IfSome {
-    fn handle_else<'a>(&self, _expression: &'a Expression<'a>, rust: &mut Rust) -> Result<()> {
-        rust.code.push_str("}else{");
+    fn handle_else<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        write_else(compile, expression, Some(&self.local), rust)?;
+        self.bound.set(matches!(
+            expression.content.split_whitespace().next(),
+            Some("if_some" | "if_some_ref")
+        ));
         Ok(())
     }
     fn local(&self) -> &Local {
-        &self.local
+        if self.bound.get() {
+            &self.local
+        } else {
+            &Local::None
+        }
     }
 }
 struct IfSomeFty {}
@@ -237,14 +336,13 @@ impl BlockFactory for WithRefFty {
 struct Each {
     local: Local,
     depth: usize,
+    pattern: String,
+    expression: String,
     code_start: usize,
-    pattern_start: usize,
-    pattern_end: usize,
-    expression_start: usize,
-    expression_end: usize,
-    body_start: Cell<usize>,
-    header_shift: Cell<usize>,
+    body_start: usize,
     uses_index: Cell<bool>,
+    uses_length: Cell<bool>,
+    uses_last: Cell<bool>,
     has_else: Cell<bool>,
 }
 
@@ -269,62 +367,105 @@ impl Each {
             }
         };
         let local = read_local(&next, expression)?;
-        let depth = compile.open_stack.len();
-        let code_start = rust.code.len();
-        rust.code.push_str("for ");
-        let pattern_start = rust.code.len();
-        compile.write_local(&mut rust.code, &local);
-        let pattern_end = rust.code.len();
-        rust.code.push_str(" in ");
-        let expression_start = rust.code.len();
+        let mut pattern = String::new();
+        compile.write_local(&mut pattern, &local);
+        let mut iterated = Rust::new();
         if by_ref {
-            rust.code.push('&');
+            iterated.code.push('&');
         }
-        compile.write_var(expression, rust, &next)?;
-        let expression_end = rust.code.len();
-        rust.code.push('{');
-        let body_start = rust.code.len();
-        Ok(Self {
+        compile.write_var(expression, &mut iterated, &next)?;
+        let each = Self {
             local,
-            depth,
-            code_start,
-            pattern_start,
-            pattern_end,
-            expression_start,
-            expression_end,
-            body_start: Cell::new(body_start),
-            header_shift: Cell::new(0),
+            depth: compile.open_stack.len(),
+            pattern,
+            expression: iterated.code,
+            code_start: rust.code.len(),
+            body_start: 0,
             uses_index: Cell::new(false),
+            uses_length: Cell::new(false),
+            uses_last: Cell::new(false),
             has_else: Cell::new(false),
+        };
+        rust.code.push_str(&each.header());
+        Ok(Self {
+            body_start: rust.code.len(),
+            ..each
         })
     }
 
-    fn write_indexer(&self, rust: &mut Rust) {
-        if !self.uses_index.replace(true) {
-            const ITERATOR_PREFIX: &str = "::std::iter::IntoIterator::into_iter(";
-            const ITERATOR_SUFFIX: &str = ").enumerate()";
-            let shift = self.header_shift.get();
-            rust.code
-                .insert_str(self.expression_end + shift, ITERATOR_SUFFIX);
-            rust.code
-                .insert_str(self.expression_start + shift, ITERATOR_PREFIX);
-            rust.code.insert(self.pattern_end + shift, ')');
-
-            let mut pattern_prefix = String::from("(");
-            append_with_depth(self.depth, "_index", &mut pattern_prefix);
-            pattern_prefix.push(',');
-            rust.code
-                .insert_str(self.pattern_start + shift, &pattern_prefix);
-
-            self.body_start.set(
-                self.body_start.get()
-                    + ITERATOR_SUFFIX.len()
-                    + ITERATOR_PREFIX.len()
-                    + 1
-                    + pattern_prefix.len(),
-            );
+    /// Returns whether the loop needs its iterator bound to a variable.
+    fn binds_iterator(&self) -> bool {
+        self.uses_length.get() || self.uses_last.get()
+    }
+
+    /// Writes the loop header for the loop variables used so far.
+    ///
+    /// The header is written plainly when the block opens and rewritten once
+    /// the loop body is complete, so blocks nested in the body never see it
+    /// move.
+    fn header(&self) -> String {
+        let mut header = String::new();
+        if self.has_else.get() {
+            header.push_str("{let mut ");
+            append_with_depth(self.depth, "_empty", &mut header);
+            header.push_str("=true;");
+        }
+        let mut pattern = self.pattern.clone();
+        let mut iterator = self.expression.clone();
+        if self.uses_index.get() || self.binds_iterator() {
+            iterator = format!("::std::iter::IntoIterator::into_iter({iterator})");
+        }
+        if self.uses_index.get() {
+            let mut index = String::from("(");
+            append_with_depth(self.depth, "_index", &mut index);
+            pattern = format!("{index},{pattern})");
+            iterator.push_str(".enumerate()");
+        }
+        if self.binds_iterator() {
+            let mut name = String::new();
+            append_with_depth(self.depth, "_iter", &mut name);
+            header.push_str("{let mut ");
+            header.push_str(&name);
+            header.push('=');
+            header.push_str(&iterator);
+            if self.uses_last.get() {
+                header.push_str(".peekable()");
+            }
+            header.push(';');
+            if self.uses_length.get() {
+                header.push_str("let ");
+                append_with_depth(self.depth, "_length", &mut header);
+                header.push_str("=::std::iter::ExactSizeIterator::len(&");
+                header.push_str(&name);
+                header.push_str(");");
+            }
+            header.push_str("while let Some(");
+            header.push_str(&pattern);
+            header.push_str(")=");
+            header.push_str(&name);
+            header.push_str(".next(){");
+        } else {
+            header.push_str("for ");
+            header.push_str(&pattern);
+            header.push_str(" in ");
+            header.push_str(&iterator);
+            header.push('{');
+        }
+        if self.has_else.get() {
+            append_with_depth(self.depth, "_empty", &mut header);
+            header.push_str("=false;");
+        }
+        header
+    }
+
+    /// Rewrites the header and closes the loop.
+    fn close_loop(&self, rust: &mut Rust) {
+        rust.code
+            .replace_range(self.code_start..self.body_start, &self.header());
+        rust.code.push('}');
+        if self.binds_iterator() {
+            rust.code.push('}');
         }
-        append_with_depth(self.depth, "_index", &mut rust.code);
     }
 
     fn write_map_var(&self, depth: usize, suffix: &str, rust: &mut Rust) {
@@ -342,24 +483,23 @@ impl Each {
 }
 
 impl Block for Each {
-    fn handle_else<'a>(&self, expression: &'a Expression<'a>, rust: &mut Rust) -> Result<()> {
+    fn handle_else<'a>(
+        &self,
+        _compile: &'a Compile<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        if !expression.content.is_empty() {
+            return Err(ParseError::new(
+                &format!("else {} not expected in each", expression.content),
+                expression,
+            ));
+        }
         if self.has_else.replace(true) {
             return Err(ParseError::new("duplicate else", expression));
         }
-
-        let mut assignment = String::new();
-        append_with_depth(self.depth, "_empty", &mut assignment);
-        assignment.push_str("=false;");
-        rust.code.insert_str(self.body_start.get(), &assignment);
-
-        let mut opening = String::from("{let mut ");
-        append_with_depth(self.depth, "_empty", &mut opening);
-        opening.push_str("=true;");
-        rust.code.insert_str(self.code_start, &opening);
-        self.header_shift
-            .set(self.header_shift.get() + opening.len());
-
-        rust.code.push_str("}if ");
+        self.close_loop(rust);
+        rust.code.push_str("if ");
         append_with_depth(self.depth, "_empty", &mut rust.code);
         rust.code.push('{');
         Ok(())
@@ -373,25 +513,62 @@ impl Block for Each {
         rust: &mut Rust,
     ) -> Result<()> {
         match name {
-            "index" if !self.has_else.get() => self.write_indexer(rust),
-            "index" => Err(ParseError::new(
-                "@index is not available in an each else branch",
-                expression,
-            ))?,
-            "key" => self.write_map_var(depth, ".0", rust),
-            "value" => self.write_map_var(depth, ".1", rust),
-            _ => Err(ParseError::new(
-                &format!("unexpected variable {}", name),
+            "key" => {
+                self.write_map_var(depth, ".0", rust);
+                return Ok(());
+            }
+            "value" => {
+                self.write_map_var(depth, ".1", rust);
+                return Ok(());
+            }
+            "index" | "first" | "last" | "length" => {}
+            _ => {
+                return Err(ParseError::new(
+                    &format!("unexpected variable {}", name),
+                    expression,
+                ))
+            }
+        }
+        if self.has_else.get() {
+            return Err(ParseError::new(
+                &format!("@{name} is not available in an each else branch"),
                 expression,
-            ))?,
+            ));
+        }
+        match name {
+            "index" => {
+                self.uses_index.set(true);
+                append_with_depth(self.depth, "_index", &mut rust.code);
+            }
+            "first" => {
+                self.uses_index.set(true);
+                rust.code.push('(');
+                append_with_depth(self.depth, "_index", &mut rust.code);
+                rust.code.push_str("==0)");
+            }
+            "last" => {
+                self.uses_last.set(true);
+                rust.code.push('(');
+                append_with_depth(self.depth, "_iter", &mut rust.code);
+                rust.code.push_str(".peek().is_none())");
+            }
+            _ => {
+                self.uses_length.set(true);
+                append_with_depth(self.depth, "_length", &mut rust.code);
+            }
         }
         Ok(())
     }
 
+    fn has_private(&self) -> bool {
+        true
+    }
+
     fn handle_close(&self, rust: &mut Rust) {
-        rust.code.push('}');
         if self.has_else.get() {
-            rust.code.push('}');
+            rust.code.push_str("}}");
+        } else {
+            self.close_loop(rust);
         }
     }
 
@@ -428,6 +605,166 @@ impl BlockFactory for EachRefFty {
     }
 }
 
+fn variant_path<'a>(token: &Token<'a>, expression: &Expression<'a>) -> Result<&'a str> {
+    let path = match token.token_type {
+        TokenType::Literal if token.value.len() > 1 && token.value.starts_with('"') => {
+            &token.value[1..token.value.len() - 1]
+        }
+        _ => "",
+    };
+    if path.is_empty()
+        || !path.split("::").all(|segment| {
+            !segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_')
+        })
+    {
+        return Err(ParseError::new(
+            &format!("expected a quoted enum path, found {}", token.value),
+            expression,
+        ));
+    }
+    Ok(path)
+}
+
+struct Match {
+    path: String,
+    has_else: Cell<bool>,
+}
+
+impl Block for Match {
+    fn handle_close(&self, rust: &mut Rust) {
+        rust.code
+            .push_str(if self.has_else.get() { "}}" } else { "}" });
+    }
+
+    fn handle_else<'a>(
+        &self,
+        _compile: &'a Compile<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        if !expression.content.is_empty() {
+            return Err(ParseError::new(
+                &format!("else {} not expected in match", expression.content),
+                expression,
+            ));
+        }
+        if self.has_else.replace(true) {
+            return Err(ParseError::new("duplicate else", expression));
+        }
+        rust.code.push_str("_=>{");
+        Ok(())
+    }
+
+    fn variant_path(&self) -> Option<&str> {
+        Some(&self.path)
+    }
+}
+
+struct MatchFty {}
+
+impl BlockFactory for MatchFty {
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        let next = token
+            .next()?
+            .ok_or_else(|| ParseError::new("expected variable after match", expression))?;
+        rust.code.push_str("match &");
+        compile.write_var(expression, rust, &next)?;
+        rust.code.push('{');
+        let path = match next.next()? {
+            Some(enum_path) => {
+                if let Some(extra) = enum_path.next()? {
+                    return Err(ParseError::new(
+                        &format!("unexpected token {}", extra.value),
+                        expression,
+                    ));
+                }
+                format!("{}::", variant_path(&enum_path, expression)?)
+            }
+            None => String::new(),
+        };
+        Ok(Box::new(Match {
+            path,
+            has_else: Cell::new(false),
+        }))
+    }
+}
+
+struct Case {
+    local: Local,
+}
+
+impl Block for Case {
+    fn local(&self) -> &Local {
+        &self.local
+    }
+}
+
+struct CaseFty {}
+
+impl BlockFactory for CaseFty {
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        let path = compile
+            .open_stack
+            .last()
+            .and_then(|scope| scope.opened.variant_path())
+            .ok_or_else(|| ParseError::new("case must be directly inside match", expression))?;
+        let next = token
+            .next()?
+            .ok_or_else(|| ParseError::new("expected variant after case", expression))?;
+        let variant = variant_path(&next, expression)?;
+        let mut fields = Vec::new();
+        if let Some(binding) = next.next()? {
+            if binding.value != "as" {
+                return Err(ParseError::new(
+                    &format!("unexpected token {}", binding.value),
+                    expression,
+                ));
+            }
+            let mut field = binding;
+            while let Some(next) = field.next()? {
+                let name = next.value.trim_matches('|');
+                if !name.is_empty() {
+                    fields.push(name.to_string());
+                }
+                field = next;
+            }
+            if fields.is_empty() {
+                return Err(ParseError::new("expected variable after as", expression));
+            }
+        }
+        let depth = compile.open_stack.len();
+        rust.code.push_str(path);
+        rust.code.push_str(variant);
+        rust.code.push('{');
+        for field in &fields {
+            rust.code.push_str(field);
+            rust.code.push(':');
+            append_with_depth(depth, field, &mut rust.code);
+            rust.code.push(',');
+        }
+        rust.code.push_str("..}=>{");
+        Ok(Box::new(Case {
+            local: if fields.is_empty() {
+                Local::None
+            } else {
+                Local::Fields(fields)
+            },
+        }))
+    }
+}
+
 const IF: IfFty = IfFty {};
 const UNLESS: UnlessFty = UnlessFty {};
 const IF_SOME: IfSomeFty = IfSomeFty {};
@@ -436,9 +773,11 @@ const WITH: WithFty = WithFty {};
 const WITH_REF: WithRefFty = WithRefFty {};
 const EACH: EachFty = EachFty {};
 const EACH_REF: EachRefFty = EachRefFty {};
+const MATCH: MatchFty = MatchFty {};
+const CASE: CaseFty = CaseFty {};
 /// Registers the block helpers supported by the built-in template syntax.
 pub fn add_builtins(map: &mut BlockMap) {
-    map.reserve(8);
+    map.reserve(10);
     map.insert("if", &IF);
     map.insert("unless", &UNLESS);
     map.insert("if_some", &IF_SOME);
@@ -447,4 +786,6 @@ pub fn add_builtins(map: &mut BlockMap) {
     map.insert("with_ref", &WITH_REF);
     map.insert("each", &EACH);
     map.insert("each_ref", &EACH_REF);
+    map.insert("match", &MATCH);
+    map.insert("case", &CASE);
 }
@@ -0,0 +1,204 @@
+use std::{cell::Cell, collections::HashMap, sync::Mutex};
+
+use crate::{
+    block::read_local,
+    compiler::{Block, BlockFactory, Compile, Local, Rust},
+    error::{ParseError, Result},
+    expression::Expression,
+    expression_tokenizer::Token,
+};
+
+/// A block helper declared as Rust code with placeholders.
+///
+/// A spec is written as `name: code`, where `code` contains `{body}` once and
+/// may contain:
+///
+/// - `{arg}`, the block's argument as a Rust expression;
+/// - `{this}`, a binding that becomes the body's context, named by
+///   `as |alias|` when the block has one;
+/// - `{else}`, after `{body}`, where an optional `{{else}}` body goes.
+///
+/// Other braces are copied unchanged.
+///
+/// ```rust
+/// use rusty_handlebars_parser::{add_builtins, BlockMap, BlockSpec, Compiler, Options};
+///
+/// let spec = BlockSpec::intern("admin: if {arg}.is_admin() { {body} }").unwrap();
+/// let mut blocks = BlockMap::new();
+/// add_builtins(&mut blocks);
+/// blocks.insert(spec.name(), spec);
+/// let rust = Compiler::new(Options { write_var_name: "f", root_var_name: Some("self") }, blocks)
+///     .compile("{{#admin user}}hi{{/admin}}")
+///     .unwrap();
+/// assert_eq!(rust.code, "if self.user.is_admin() { write!(f, \"hi\")?; }");
+/// ```
+pub struct BlockSpec {
+    name: String,
+    open: String,
+    middle: Option<String>,
+    close: String,
+}
+
+const ARG: &str = "{arg}";
+const THIS: &str = "{this}";
+const BODY: &str = "{body}";
+const ELSE: &str = "{else}";
+
+impl BlockSpec {
+    /// Parses a `name: code` block spec.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, code) = spec.split_once(':').ok_or_else(|| {
+            ParseError::message(format!("block spec `{spec}` must start with `name:`"))
+        })?;
+        let name = name.trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(ParseError::message(format!(
+                "invalid block name `{name}` in block spec"
+            )));
+        }
+        let code = code.trim();
+        let (open, rest) = code.split_once(BODY).ok_or_else(|| {
+            ParseError::message(format!("block spec `{name}` must contain {BODY}"))
+        })?;
+        if rest.contains(BODY) || open.contains(ELSE) {
+            return Err(ParseError::message(format!(
+                "block spec `{name}` must contain {BODY} once, before any {ELSE}"
+            )));
+        }
+        let (middle, close) = match rest.split_once(ELSE) {
+            Some((middle, close)) => (Some(middle.to_string()), close),
+            None => (None, rest),
+        };
+        if close.contains(ELSE) {
+            return Err(ParseError::message(format!(
+                "block spec `{name}` may contain {ELSE} once"
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            open: open.to_string(),
+            middle,
+            close: close.to_string(),
+        })
+    }
+
+    /// Parses a block spec into the `'static` copy that [`BlockMap`] holds.
+    ///
+    /// Each distinct spec is parsed and leaked once per process, so a
+    /// long-lived process that compiles the same templates again, such as a
+    /// proc-macro server, does not leak a copy each time.
+    ///
+    /// [`BlockMap`]: crate::BlockMap
+    pub fn intern(spec: &str) -> Result<&'static Self> {
+        static INTERNED: Mutex<Option<HashMap<String, &'static BlockSpec>>> = Mutex::new(None);
+        let mut interned = INTERNED.lock().unwrap_or_else(|err| err.into_inner());
+        let interned = interned.get_or_insert_with(HashMap::new);
+        if let Some(parsed) = interned.get(spec) {
+            return Ok(parsed);
+        }
+        let parsed: &'static Self = Box::leak(Box::new(Self::parse(spec)?));
+        interned.insert(spec.to_string(), parsed);
+        Ok(parsed)
+    }
+
+    /// Returns the block helper name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn uses(&self, placeholder: &str) -> bool {
+        self.open.contains(placeholder)
+            || self
+                .middle
+                .as_ref()
+                .is_some_and(|middle| middle.contains(placeholder))
+            || self.close.contains(placeholder)
+    }
+}
+
+struct SpecBlock {
+    local: Local,
+    middle: Option<String>,
+    close: String,
+    has_else: Cell<bool>,
+}
+
+impl Block for SpecBlock {
+    fn handle_close(&self, rust: &mut Rust) {
+        if let (Some(middle), false) = (&self.middle, self.has_else.get()) {
+            rust.code.push_str(middle);
+        }
+        rust.code.push_str(&self.close);
+    }
+
+    fn handle_else<'a>(
+        &self,
+        _compile: &'a Compile<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let middle = match &self.middle {
+            Some(middle) if expression.content.is_empty() => middle,
+            _ => return Err(ParseError::new("else not expected here", expression)),
+        };
+        if self.has_else.replace(true) {
+            return Err(ParseError::new("duplicate else", expression));
+        }
+        rust.code.push_str(middle);
+        Ok(())
+    }
+
+    fn local(&self) -> &Local {
+        &self.local
+    }
+}
+
+impl BlockFactory for BlockSpec {
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        let mut arg = Rust::new();
+        let last = if self.uses(ARG) {
+            let next = token.next()?.ok_or_else(|| {
+                ParseError::new(
+                    &format!("expected argument after {}", self.name),
+                    expression,
+                )
+            })?;
+            compile.write_var(expression, &mut arg, &next)?;
+            next
+        } else {
+            token
+        };
+        let local = match (read_local(&last, expression)?, self.uses(THIS)) {
+            (Local::As(_), false) => {
+                return Err(ParseError::new(
+                    &format!("{} does not bind a value", self.name),
+                    expression,
+                ))
+            }
+            (_, false) => Local::None,
+            (local, true) => local,
+        };
+        let mut this = String::new();
+        if !matches!(local, Local::None) {
+            compile.write_local(&mut this, &local);
+        }
+        let fill = |code: &str| code.replace(ARG, &arg.code).replace(THIS, &this);
+        rust.code.push_str(&fill(&self.open));
+        Ok(Box::new(SpecBlock {
+            local,
+            middle: self.middle.as_deref().map(fill),
+            close: fill(&self.close),
+            has_else: Cell::new(false),
+        }))
+    }
+}
@@ -1,6 +1,6 @@
 //! Minifier configuration used by the derive macro.
 
-use minify_html::Cfg;
+use minify_html::{minify, Cfg};
 
 /// HTML minifier settings that preserve brace-template syntax.
 #[cfg(feature = "minify-html")]
@@ -21,3 +21,9 @@ pub static COMPRESS_CONFIG: Cfg = Cfg {
     remove_bangs: true,
     remove_processing_instructions: false,
 };
+
+/// Minifies a template with [`COMPRESS_CONFIG`].
+pub fn minify_template(src: &str) -> String {
+    String::from_utf8(minify(src.as_bytes(), &COMPRESS_CONFIG))
+        .expect("minify-html returned invalid UTF-8 for a UTF-8 template")
+}
@@ -1,16 +1,24 @@
-use std::{collections::HashMap, fmt::Write};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    path::PathBuf,
+};
 
 use crate::{
     error::{ParseError, Result},
     expression::{Expression, ExpressionType},
     expression_tokenizer::{Token, TokenType},
-    parse_template, BlockNode, Node, NodeKind, Span,
+    helper_signature::HelperSignature,
+    parse_partial, parse_template, trimmed_text, BlockNode, Diagnostic, DiagnosticCode, Node,
+    NodeKind, ParsedTemplate, Span,
 };
 
 /// Binding introduced by a block helper.
 pub enum Local {
     /// A name supplied with `as name` or `as |name|`.
     As(String),
+    /// Several names bound at once, such as the fields of a `case` arm.
+    Fields(Vec<String>),
     /// The implicit `this` binding.
     This,
     /// A block without a local binding.
@@ -25,23 +33,50 @@ pub struct Scope {
     pub depth: usize,
 }
 
+/// Output buffered until the next `write!`, with the path of the file each
+/// expression was read from so errors can be located in it.
 enum PendingWrite<'a> {
     Raw(&'a str),
-    Expression((Expression<'a>, DisplayKind)),
-    Format((&'a str, &'a str, &'a str)),
+    Expression((Expression<'a>, DisplayKind, &'a str)),
+    Format((Expression<'a>, &'a str, &'a str, &'a str)),
 }
 
+/// Function used for `{{{value}}}`, and for `{{value}}` without escaping.
+pub const AS_DISPLAY: &str = "::rusty_handlebars::AsDisplay::as_display";
+
+/// Default function used for `{{value}}`.
+pub const AS_DISPLAY_HTML: &str = "::rusty_handlebars::AsDisplayHtml::as_display_html";
+
 #[derive(Clone, Copy)]
 enum DisplayKind {
     Raw,
     HtmlEscaped,
+    Url,
+    Js,
+    Attr,
+    Css,
 }
 
 impl DisplayKind {
-    fn prefix(self) -> &'static str {
+    /// Returns the escaping selected by a `{{url value}}` style helper.
+    fn contextual(helper: &str) -> Option<Self> {
+        match helper {
+            "url" => Some(Self::Url),
+            "js" => Some(Self::Js),
+            "attr" => Some(Self::Attr),
+            "css" => Some(Self::Css),
+            _ => None,
+        }
+    }
+
+    fn path(self, escape_path: &str) -> &str {
         match self {
-            Self::Raw => ", ::rusty_handlebars::AsDisplay::as_display(&",
-            Self::HtmlEscaped => ", ::rusty_handlebars::AsDisplayHtml::as_display_html(&",
+            Self::Raw => AS_DISPLAY,
+            Self::HtmlEscaped => escape_path,
+            Self::Url => "::rusty_handlebars::AsDisplayUrl::as_display_url",
+            Self::Js => "::rusty_handlebars::AsDisplayJs::as_display_js",
+            Self::Attr => "::rusty_handlebars::AsDisplayAttr::as_display_attr",
+            Self::Css => "::rusty_handlebars::AsDisplayCss::as_display_css",
         }
     }
 }
@@ -51,6 +86,12 @@ impl DisplayKind {
 pub struct Rust {
     /// Statements that write the rendered template.
     pub code: String,
+    /// Bytes of static template text written by `code`.
+    ///
+    /// Text inside loops is counted once and text in every branch of a
+    /// conditional is counted, so this is an estimate of the output length for
+    /// preallocating buffers rather than a bound.
+    pub static_len: usize,
 }
 
 impl Rust {
@@ -81,11 +122,29 @@ pub trait Block {
         ))
     }
 
-    /// Writes the transition to this block's `else` branch.
-    fn handle_else<'a>(&self, expression: &'a Expression<'a>, _rust: &mut Rust) -> Result<()> {
+    /// Writes the transition to this block's next `else` branch.
+    ///
+    /// `expression` holds the text after `else`: empty for a plain `{{else}}`,
+    /// or a helper and its arguments for a chained `{{else if value}}`. The
+    /// block itself is popped from `compile` while this runs, so chained
+    /// arguments resolve in the scope that opened the block.
+    fn handle_else<'a>(
+        &self,
+        _compile: &'a Compile<'a>,
+        expression: &'a Expression<'a>,
+        _rust: &mut Rust,
+    ) -> Result<()> {
         Err(ParseError::new("else not expected here", expression))
     }
 
+    /// Returns whether this block provides private variables such as `@index`.
+    ///
+    /// Private variables resolve against the nearest such block, and each
+    /// `../` prefix skips one of them.
+    fn has_private(&self) -> bool {
+        false
+    }
+
     /// Returns the field path used as this block's context, if inherited.
     fn this(&self) -> Option<&str> {
         None
@@ -95,6 +154,12 @@ pub trait Block {
     fn local(&self) -> &Local {
         &Local::None
     }
+
+    /// Returns the enum path prefixed to `case` arms opened directly inside
+    /// this block, or `None` if the block does not accept `case` arms.
+    fn variant_path(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Opens a named block helper.
@@ -112,6 +177,19 @@ pub trait BlockFactory {
 /// Block helpers available to a [`Compiler`].
 pub type BlockMap = HashMap<&'static str, &'static dyn BlockFactory>;
 
+/// Supplies the source of partial templates included with `{{> "path"}}`.
+pub trait PartialLoader {
+    /// Returns the template source for a partial path.
+    fn load(&self, path: &str) -> std::io::Result<String>;
+}
+
+/// Reads partials relative to a root directory.
+impl PartialLoader for PathBuf {
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(self.join(path))
+    }
+}
+
 /// Mutable state used while one template is compiled.
 pub struct Compile<'a> {
     /// Currently open blocks, starting with the root scope.
@@ -119,6 +197,21 @@ pub struct Compile<'a> {
     /// Block helpers configured on the compiler.
     pub block_map: &'a BlockMap,
     helper_paths: &'a HashMap<String, String>,
+    helper_signatures: &'a HashMap<String, HelperSignature>,
+    partials: &'a HashMap<String, String>,
+    regions: HashMap<&'a str, Region<'a>>,
+    used_regions: HashSet<&'a str>,
+    /// Path of the partial or layout being compiled, empty for the template.
+    file: &'a str,
+}
+
+/// A layout region override and the template source it was parsed from.
+#[derive(Clone, Copy)]
+struct Region<'a> {
+    path: &'a str,
+    source: &'a str,
+    nodes: &'a [Node<'a>],
+    span: Span,
 }
 
 /// Appends `_<depth>` to a generated local name.
@@ -143,6 +236,8 @@ impl<'a> Compile<'a> {
         this: Option<&'static str>,
         block_map: &'a BlockMap,
         helper_paths: &'a HashMap<String, String>,
+        helper_signatures: &'a HashMap<String, HelperSignature>,
+        partials: &'a HashMap<String, String>,
     ) -> Self {
         Self {
             open_stack: vec![Scope {
@@ -151,18 +246,25 @@ impl<'a> Compile<'a> {
             }],
             block_map,
             helper_paths,
+            helper_signatures,
+            partials,
+            regions: HashMap::new(),
+            used_regions: HashSet::new(),
+            file: "",
         }
     }
 
-    fn find_scope(&self, var: &'a str) -> Result<(&'a str, &Scope)> {
+    fn find_scope(&self, var: &'a str, span: Span) -> Result<(&'a str, &Scope)> {
         let mut scope = self.open_stack.last().unwrap();
         let mut local = var;
         while local.starts_with("../") {
             match scope.depth {
                 0 => {
-                    return Err(ParseError {
-                        message: format!("unable to resolve scope for {}", var),
-                    })
+                    return Err(ParseError::at(
+                        DiagnosticCode::InvalidExpression,
+                        format!("unable to resolve scope for {}", var),
+                        span,
+                    ))
                 }
                 _ => {
                     local = &local[3..];
@@ -173,6 +275,28 @@ impl<'a> Compile<'a> {
         Ok((local, scope))
     }
 
+    fn find_private_scope(&self, var: &'a str, span: Span) -> Result<(&'a str, &Scope)> {
+        let mut local = var;
+        let mut parents = 0;
+        while let Some(parent) = local.strip_prefix("../") {
+            local = parent;
+            parents += 1;
+        }
+        self.open_stack
+            .iter()
+            .rev()
+            .filter(|scope| scope.opened.has_private())
+            .nth(parents)
+            .map(|scope| (local, scope))
+            .ok_or_else(|| {
+                ParseError::at(
+                    DiagnosticCode::InvalidExpression,
+                    format!("@{} is only available inside each", var),
+                    span,
+                )
+            })
+    }
+
     fn resolve_local(
         &self,
         depth: usize,
@@ -207,6 +331,9 @@ impl<'a> Compile<'a> {
         }
         if match scope.opened.local() {
             Local::As(local) => self.resolve_local(scope.depth, var, local, buffer),
+            Local::Fields(fields) => fields
+                .iter()
+                .any(|field| self.resolve_local(scope.depth, var, field, buffer)),
             Local::This => {
                 append_with_depth(scope.depth, "this", buffer);
                 if var != "this" {
@@ -232,7 +359,13 @@ impl<'a> Compile<'a> {
         Ok(())
     }
 
-    fn resolve_sub_expression(&self, raw: &str, value: &str, rust: &mut Rust) -> Result<()> {
+    fn resolve_sub_expression(
+        &self,
+        raw: &str,
+        value: &str,
+        span: Span,
+        rust: &mut Rust,
+    ) -> Result<()> {
         self.resolve(
             &Expression {
                 expression_type: ExpressionType::Raw,
@@ -240,6 +373,7 @@ impl<'a> Compile<'a> {
                 content: value,
                 postfix: "",
                 raw,
+                span,
             },
             rust,
         )
@@ -252,32 +386,41 @@ impl<'a> Compile<'a> {
         rust: &mut Rust,
         var: &Token<'a>,
     ) -> Result<()> {
+        if let Some(key) = var.key {
+            return Err(ParseError::new(
+                &format!("unexpected argument {key}=, only helpers with declared parameters take named arguments"),
+                expression,
+            ));
+        }
         match var.token_type {
             TokenType::PrivateVariable => {
-                let (name, scope) = self.find_scope(var.value)?;
+                let (name, scope) = self.find_private_scope(var.value, expression.span)?;
                 scope
                     .opened
                     .resolve_private(scope.depth, expression, name, rust)?;
             }
             TokenType::Variable => {
-                let (name, scope) = self.find_scope(var.value)?;
+                let (name, scope) = self.find_scope(var.value, expression.span)?;
                 self.resolve_var(name, scope, &mut rust.code)?;
             }
             TokenType::Literal => {
                 rust.code.push_str(var.value);
             }
             TokenType::SubExpression(raw) => {
-                self.resolve_sub_expression(raw, var.value, rust)?;
+                self.resolve_sub_expression(raw, var.value, expression.span, rust)?;
             }
         }
         Ok(())
     }
 
-    fn handle_else(&self, expression: &Expression<'a>, rust: &mut Rust) -> Result<()> {
-        match self.open_stack.last() {
-            Some(scope) => scope.opened.handle_else(expression, rust),
-            None => Err(ParseError::new("else not expected here", expression)),
-        }
+    fn handle_else(&mut self, expression: &Expression<'a>, rust: &mut Rust) -> Result<()> {
+        let scope = match self.open_stack.pop() {
+            Some(scope) => scope,
+            None => return Err(ParseError::new("else not expected here", expression)),
+        };
+        let result = scope.opened.handle_else(self, expression, rust);
+        self.open_stack.push(scope);
+        result
     }
 
     fn resolve_lookup(
@@ -301,6 +444,79 @@ impl<'a> Compile<'a> {
         Ok(())
     }
 
+    fn resolve_comparison(
+        &self,
+        expression: &Expression<'a>,
+        name: &str,
+        operator: &str,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let rhs = match args.next()? {
+            Some(rhs) if rhs.next()?.is_none() => rhs,
+            _ => {
+                return Err(ParseError::new(
+                    &format!("{} expects 2 arguments", name),
+                    expression,
+                ))
+            }
+        };
+        rust.code.push('(');
+        self.write_var(expression, rust, &args)?;
+        rust.code.push(' ');
+        rust.code.push_str(operator);
+        rust.code.push(' ');
+        self.write_var(expression, rust, &rhs)?;
+        rust.code.push(')');
+        Ok(())
+    }
+
+    fn write_bool(
+        &self,
+        expression: &Expression<'a>,
+        rust: &mut Rust,
+        var: &Token<'a>,
+    ) -> Result<()> {
+        rust.code.push_str("::rusty_handlebars::AsBool::as_bool(&");
+        self.write_var(expression, rust, var)?;
+        rust.code.push(')');
+        Ok(())
+    }
+
+    fn resolve_logical(
+        &self,
+        expression: &Expression<'a>,
+        name: &str,
+        operator: &str,
+        mut args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut next = match args.next()? {
+            Some(next) => next,
+            None => {
+                return Err(ParseError::new(
+                    &format!("{} expects at least 2 arguments", name),
+                    expression,
+                ))
+            }
+        };
+        rust.code.push('(');
+        self.write_bool(expression, rust, &args)?;
+        loop {
+            rust.code.push(' ');
+            rust.code.push_str(operator);
+            rust.code.push(' ');
+            self.write_bool(expression, rust, &next)?;
+            args = next;
+            next = match args.next()? {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        rust.code.push(')');
+        Ok(())
+    }
+
     fn resolve_helper(
         &self,
         expression: &Expression<'a>,
@@ -311,26 +527,117 @@ impl<'a> Compile<'a> {
         match name.value {
             "lookup" => self.resolve_lookup(expression, "[", ']', args, rust),
             "try_lookup" => self.resolve_lookup(expression, ".get(", ')', args, rust),
+            "eq" => self.resolve_comparison(expression, "eq", "==", args, rust),
+            "ne" => self.resolve_comparison(expression, "ne", "!=", args, rust),
+            "lt" => self.resolve_comparison(expression, "lt", "<", args, rust),
+            "gt" => self.resolve_comparison(expression, "gt", ">", args, rust),
+            "le" => self.resolve_comparison(expression, "le", "<=", args, rust),
+            "ge" => self.resolve_comparison(expression, "ge", ">=", args, rust),
+            "and" => self.resolve_logical(expression, "and", "&&", args, rust),
+            "or" => self.resolve_logical(expression, "or", "||", args, rust),
+            "not" => {
+                if args.next()?.is_some() {
+                    return Err(ParseError::new("not expects 1 argument", expression));
+                }
+                rust.code.push_str("(!");
+                self.write_bool(expression, rust, &args)?;
+                rust.code.push(')');
+                Ok(())
+            }
             name => {
                 rust.code
                     .push_str(self.helper_paths.get(name).map_or(name, String::as_str));
                 rust.code.push('(');
-                self.write_var(expression, rust, &args)?;
-                loop {
-                    args = match args.next()? {
-                        Some(token) => {
+                match self.helper_signatures.get(name) {
+                    Some(signature) => {
+                        self.write_signature_args(expression, name, signature, args, rust)?
+                    }
+                    None => {
+                        self.write_var(expression, rust, &args)?;
+                        while let Some(token) = args.next()? {
                             rust.code.push_str(", ");
                             self.write_var(expression, rust, &token)?;
-                            token
-                        }
-                        None => {
-                            rust.code.push(')');
-                            return Ok(());
+                            args = token;
                         }
-                    };
+                    }
+                }
+                rust.code.push(')');
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes helper arguments in the declared parameter order, filling
+    /// `key=value` arguments by name and missing ones from their defaults.
+    fn write_signature_args(
+        &self,
+        expression: &Expression<'a>,
+        name: &str,
+        signature: &HelperSignature,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let params = signature.params();
+        let mut values: Vec<Option<String>> = vec![None; params.len()];
+        let mut positional = 0;
+        let mut next = Some(args);
+        while let Some(token) = next {
+            let index = match token.key {
+                Some(key) => params
+                    .iter()
+                    .position(|param| param.name == key)
+                    .ok_or_else(|| {
+                        ParseError::new(&format!("{name} has no parameter {key}"), expression)
+                    })?,
+                None => {
+                    positional += 1;
+                    positional - 1
                 }
+            };
+            if index >= params.len() {
+                return Err(ParseError::new(
+                    &format!("{name} expects at most {} arguments", params.len()),
+                    expression,
+                ));
             }
+            if values[index].is_some() {
+                return Err(ParseError::new(
+                    &format!("{name} received {} twice", params[index].name),
+                    expression,
+                ));
+            }
+            let mut value = Rust::new();
+            self.write_var(
+                expression,
+                &mut value,
+                &Token {
+                    key: None,
+                    ..token.clone()
+                },
+            )?;
+            values[index] = Some(value.code);
+            next = token.next()?;
         }
+        for (i, (param, value)) in params.iter().zip(values).enumerate() {
+            if i > 0 {
+                rust.code.push_str(", ");
+            }
+            match value.or_else(|| param.default.clone()) {
+                Some(value) => {
+                    if param.by_ref {
+                        rust.code.push('&');
+                    }
+                    rust.code.push_str(&value)
+                }
+                None => {
+                    return Err(ParseError::new(
+                        &format!("{name} is missing argument {}", param.name),
+                        expression,
+                    ))
+                }
+            }
+        }
+        Ok(())
     }
 
     fn resolve(&self, expression: &Expression<'a>, rust: &mut Rust) -> Result<()> {
@@ -340,7 +647,7 @@ impl<'a> Compile<'a> {
         };
         rust.code.push_str(expression.prefix);
         if let TokenType::SubExpression(raw) = token.token_type {
-            self.resolve_sub_expression(raw, token.value, rust)?;
+            self.resolve_sub_expression(raw, token.value, expression.span, rust)?;
         } else if let Some(args) = token.next()? {
             self.resolve_helper(expression, token, args, rust)?;
         } else {
@@ -404,6 +711,11 @@ pub struct Compiler {
     options: Options,
     block_map: BlockMap,
     helper_paths: HashMap<String, String>,
+    helper_signatures: HashMap<String, HelperSignature>,
+    partial_loader: Option<Box<dyn PartialLoader>>,
+    escape_path: String,
+    write_macro: String,
+    block_end: Option<String>,
 }
 
 impl Compiler {
@@ -413,15 +725,189 @@ impl Compiler {
             options,
             block_map,
             helper_paths: HashMap::new(),
+            helper_signatures: HashMap::new(),
+            partial_loader: None,
+            escape_path: AS_DISPLAY_HTML.to_string(),
+            write_macro: "write!".to_string(),
+            block_end: None,
         }
     }
 
+    /// Configures the macro called as `write_macro(writer, "format", args...)?;`.
+    ///
+    /// The default is `write!`. Any macro with the same arguments and a
+    /// `Result` value can be used, including ones that expand to `.await`.
+    pub fn with_write_macro(mut self, write_macro: impl Into<String>) -> Self {
+        self.write_macro = write_macro.into();
+        self
+    }
+
+    /// Configures a statement written after each top-level block closes, such
+    /// as a flush of the output.
+    pub fn with_block_end(mut self, block_end: impl Into<String>) -> Self {
+        self.block_end = Some(block_end.into());
+        self
+    }
+
+    /// Configures the function called with a reference to each `{{value}}`.
+    ///
+    /// It must return a `Display` value. The default is [`AS_DISPLAY_HTML`];
+    /// [`AS_DISPLAY`] writes values without escaping.
+    pub fn with_escape_path(mut self, escape_path: impl Into<String>) -> Self {
+        self.escape_path = escape_path.into();
+        self
+    }
+
     /// Configures Rust function paths for inline helper names.
     pub fn with_helper_paths(mut self, helper_paths: HashMap<String, String>) -> Self {
         self.helper_paths = helper_paths;
         self
     }
 
+    /// Configures declared parameters for inline helper names.
+    ///
+    /// Calls to these helpers may pass `key=value` arguments, and every
+    /// declared parameter is passed positionally in declaration order.
+    pub fn with_helper_signatures(
+        mut self,
+        helper_signatures: HashMap<String, HelperSignature>,
+    ) -> Self {
+        self.helper_signatures = helper_signatures;
+        self
+    }
+
+    /// Configures how `{{> "path"}}` partials are read.
+    ///
+    /// Without a loader, templates that include partials fail to compile.
+    pub fn with_partial_loader(mut self, loader: impl PartialLoader + 'static) -> Self {
+        self.partial_loader = Some(Box::new(loader));
+        self
+    }
+
+    fn partial_error(
+        (path, source): (&str, &str),
+        code: DiagnosticCode,
+        span: Span,
+        message: String,
+    ) -> ParseError {
+        ParseError::from_diagnostic(&Diagnostic::error(code, span, message), source)
+            .in_partial(path)
+    }
+
+    /// Loads the partials and layouts named in `nodes`, which belong to the
+    /// `including` path and source.
+    fn load_partials(
+        &self,
+        nodes: &[Node<'_>],
+        including: (&str, &str),
+        stack: &mut Vec<String>,
+        partials: &mut HashMap<String, String>,
+    ) -> Result<()> {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Partial { path, path_span } => {
+                    self.load_partial(path, *path_span, including, stack, partials)?;
+                }
+                NodeKind::Block(block) => {
+                    if block.name == "extends" {
+                        if let Some(path) = block.name_argument() {
+                            let span = block.arguments[0].span;
+                            self.load_partial(path, span, including, stack, partials)?;
+                        }
+                    }
+                    for body in block.bodies() {
+                        self.load_partials(body, including, stack, partials)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn load_partial(
+        &self,
+        path: &str,
+        span: Span,
+        including: (&str, &str),
+        stack: &mut Vec<String>,
+        partials: &mut HashMap<String, String>,
+    ) -> Result<()> {
+        if stack.iter().any(|open| open == path) {
+            stack.push(path.to_string());
+            return Err(Self::partial_error(
+                including,
+                DiagnosticCode::PartialCycle,
+                span,
+                format!("partial includes itself: {}", stack.join(" -> ")),
+            ));
+        }
+        if partials.contains_key(path) {
+            return Ok(());
+        }
+        let loader = self.partial_loader.as_ref().ok_or_else(|| {
+            Self::partial_error(
+                including,
+                DiagnosticCode::UnresolvedPartial,
+                span,
+                format!("partial `{path}` cannot be loaded without a partial loader"),
+            )
+        })?;
+        let source = loader.load(path).map_err(|err| {
+            Self::partial_error(
+                including,
+                DiagnosticCode::UnresolvedPartial,
+                span,
+                format!("unable to read partial `{path}`, {err}"),
+            )
+        })?;
+        partials.insert(path.to_string(), source.clone());
+        let parsed = parse_partial(&source);
+        if !parsed.diagnostics.is_empty() {
+            return Err(ParseError::from_diagnostics(&parsed.diagnostics, &source).in_partial(path));
+        }
+        stack.push(path.to_string());
+        self.load_partials(&parsed.nodes, (path, &source), stack, partials)?;
+        stack.pop();
+        Ok(())
+    }
+
+    fn extended_layout<'a>(nodes: &[Node<'a>]) -> Option<&'a str> {
+        nodes.iter().find_map(|node| match &node.kind {
+            NodeKind::Block(block) if block.name == "extends" => block.name_argument(),
+            _ => None,
+        })
+    }
+
+    fn collect_regions<'a>(
+        nodes: &'a [Node<'a>],
+        path: &'a str,
+        source: &'a str,
+        compile: &mut Compile<'a>,
+    ) {
+        for node in nodes {
+            let NodeKind::Block(extends) = &node.kind else {
+                continue;
+            };
+            if extends.name != "extends" {
+                continue;
+            }
+            for child in &extends.body {
+                let NodeKind::Block(region) = &child.kind else {
+                    continue;
+                };
+                if let Some(name) = region.name_argument() {
+                    compile.regions.entry(name).or_insert(Region {
+                        path,
+                        source,
+                        nodes: &region.body,
+                        span: region.arguments[0].span,
+                    });
+                }
+            }
+        }
+    }
+
     fn write_escaped(content: &str, output: &mut String) {
         let mut start = 0;
         for (index, byte) in content.bytes().enumerate() {
@@ -452,42 +938,53 @@ impl Compiler {
         if pending.is_empty() {
             return Ok(());
         }
-        rust.code.push_str("write!(");
+        rust.code.push_str(&self.write_macro);
+        rust.code.push('(');
         rust.code.push_str(self.options.write_var_name);
         rust.code.push_str(", \"");
         for pending in pending.iter() {
             match pending {
-                PendingWrite::Raw(raw) => Self::write_escaped(raw, &mut rust.code),
+                PendingWrite::Raw(raw) => {
+                    rust.static_len += raw.len();
+                    Self::write_escaped(raw, &mut rust.code)
+                }
                 PendingWrite::Expression(_) => rust.code.push_str("{}"),
-                PendingWrite::Format((_, format, _)) => rust.code.push_str(format),
+                PendingWrite::Format((_, format, _, _)) => rust.code.push_str(format),
             }
         }
         rust.code.push('"');
         for pending in pending.iter() {
             match pending {
-                PendingWrite::Expression((expression, display)) => {
-                    compile.resolve(
-                        &Expression {
-                            expression_type: ExpressionType::Raw,
-                            prefix: display.prefix(),
-                            content: expression.content,
-                            postfix: ")",
-                            raw: expression.raw,
-                        },
-                        rust,
-                    )?;
+                PendingWrite::Expression((expression, display, file)) => {
+                    rust.code.push_str(", ");
+                    rust.code.push_str(display.path(&self.escape_path));
+                    compile
+                        .resolve(
+                            &Expression {
+                                expression_type: ExpressionType::Raw,
+                                prefix: "(&",
+                                content: expression.content,
+                                postfix: ")",
+                                raw: expression.raw,
+                                span: expression.span,
+                            },
+                            rust,
+                        )
+                        .map_err(|err| err.in_file(file))?;
                 }
-                PendingWrite::Format((raw, _, content)) => {
-                    compile.resolve(
-                        &Expression {
-                            expression_type: ExpressionType::Raw,
-                            prefix: ", ",
-                            content,
-                            postfix: "",
-                            raw,
-                        },
-                        rust,
-                    )?;
+                PendingWrite::Format((expression, _, content, file)) => {
+                    compile
+                        .resolve(
+                            &Expression {
+                                expression_type: ExpressionType::Raw,
+                                prefix: ", ",
+                                content,
+                                postfix: "",
+                                ..*expression
+                            },
+                            rust,
+                        )
+                        .map_err(|err| err.in_file(file))?;
                 }
                 _ => (),
             }
@@ -498,17 +995,43 @@ impl Compiler {
     }
 
     fn select_write<'a>(
+        &self,
         expression: &Expression<'a>,
         display: DisplayKind,
+        file: &'a str,
     ) -> Result<PendingWrite<'a>> {
         if let Some(token) = Token::first(expression.content)? {
             if let TokenType::Variable = token.token_type {
+                // A bare `{{url}}` is still a field named `url`, and a declared
+                // helper named `url` is still called.
+                let declared = self.helper_paths.contains_key(token.value)
+                    || self.helper_signatures.contains_key(token.value);
+                if let (Some(display), Some(value), false) = (
+                    DisplayKind::contextual(token.value),
+                    token.next()?,
+                    declared,
+                ) {
+                    if value.next()?.is_some() {
+                        return Err(ParseError::new(
+                            &format!("{} expects 1 argument", token.value),
+                            expression,
+                        ));
+                    }
+                    return Ok(PendingWrite::Expression((
+                        Expression {
+                            content: value.value,
+                            ..*expression
+                        },
+                        display,
+                        file,
+                    )));
+                }
                 if token.value != "format" {
-                    return Ok(PendingWrite::Expression((*expression, display)));
+                    return Ok(PendingWrite::Expression((*expression, display, file)));
                 }
                 let pattern = match token.next()? {
                     Some(token) => token,
-                    _ => return Ok(PendingWrite::Expression((*expression, display))),
+                    _ => return Ok(PendingWrite::Expression((*expression, display, file))),
                 };
                 let value = match pattern.next() {
                     Ok(Some(token)) => token,
@@ -517,9 +1040,10 @@ impl Compiler {
                 if let TokenType::Literal = pattern.token_type {
                     if pattern.value.starts_with('"') && pattern.value.ends_with('"') {
                         return Ok(PendingWrite::Format((
-                            expression.raw,
+                            *expression,
                             &pattern.value[1..pattern.value.len() - 1],
                             value.value,
+                            file,
                         )));
                     }
                 }
@@ -529,7 +1053,7 @@ impl Compiler {
                 ));
             }
         }
-        Ok(PendingWrite::Expression((*expression, display)))
+        Ok(PendingWrite::Expression((*expression, display, file)))
     }
 
     fn expression<'a>(
@@ -544,22 +1068,10 @@ impl Compiler {
             content: &source[expression_span.start..expression_span.end],
             postfix: "",
             raw: &source[raw_span.start..raw_span.end],
+            span: raw_span,
         }
     }
 
-    fn text_content(source: &str, span: Span) -> &str {
-        let mut content = &source[span.start..span.end];
-        let before = &source[..span.start];
-        if before.ends_with("~}}") || before.ends_with("~}}}") || before.ends_with("~}}}}") {
-            content = content.trim_start();
-        }
-        let after = &source[span.end..];
-        if after.starts_with("{{~") || after.starts_with("{{{~") || after.starts_with("{{{{~") {
-            content = content.trim_end();
-        }
-        content
-    }
-
     fn compile_block<'a>(
         &self,
         source: &'a str,
@@ -578,26 +1090,108 @@ impl Compiler {
             ),
             rust,
         )?;
-        self.compile_nodes(source, &block.body, pending, compile, rust)?;
-        if let Some(else_span) = block.else_span {
+        if block.name == "match" {
+            self.compile_arms(source, &block.body, pending, compile, rust)?;
+        } else {
+            self.compile_nodes(source, &block.body, pending, compile, rust)?;
+        }
+        for branch in &block.else_branches {
             self.commit_pending(pending, compile, rust)?;
             compile.handle_else(
                 &Self::expression(
                     source,
                     ExpressionType::HtmlEscaped,
-                    Span::new(else_span.start + 2, else_span.end - 2),
-                    else_span,
+                    branch.expression_span,
+                    branch.span,
                 ),
                 rust,
             )?;
-            self.compile_nodes(source, &block.else_body, pending, compile, rust)?;
+            self.compile_nodes(source, &branch.body, pending, compile, rust)?;
         }
         self.commit_pending(pending, compile, rust)?;
         let close_span = block.close_span.expect("validated block must have a close");
         compile.close(
             Self::expression(source, ExpressionType::Close, close_span, close_span),
             rust,
-        )
+        )?;
+        if let (Some(block_end), 1) = (&self.block_end, compile.open_stack.len()) {
+            rust.code.push_str(block_end);
+        }
+        Ok(())
+    }
+
+    /// Compiles the `case` arms of a `match` block, skipping the whitespace
+    /// between them since nothing can be written outside an arm.
+    fn compile_arms<'a>(
+        &self,
+        source: &'a str,
+        nodes: &[Node<'a>],
+        pending: &mut Vec<PendingWrite<'a>>,
+        compile: &mut Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Text(_) if source[node.span.start..node.span.end].trim().is_empty() => {}
+                NodeKind::Comment { .. } => {}
+                NodeKind::Block(block) if block.name == "case" => {
+                    self.compile_block(source, block, pending, compile, rust)?
+                }
+                _ => {
+                    let text = &source[node.span.start..node.span.end];
+                    let start = node.span.start + text.len() - text.trim_start().len();
+                    return Err(ParseError::at(
+                        DiagnosticCode::InvalidExpression,
+                        "only case arms may appear inside match".to_string(),
+                        Span::new(start, node.span.start + text.trim_end().len()),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_region<'a>(
+        &self,
+        source: &'a str,
+        block: &BlockNode<'a>,
+        pending: &mut Vec<PendingWrite<'a>>,
+        compile: &mut Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let name = block.name_argument().unwrap_or_default();
+        match compile.regions.get(name).copied() {
+            Some(region) => {
+                compile.used_regions.insert(name);
+                self.compile_file(
+                    region.path,
+                    region.source,
+                    region.nodes,
+                    pending,
+                    compile,
+                    rust,
+                )
+            }
+            None => self.compile_nodes(source, &block.body, pending, compile, rust),
+        }
+    }
+
+    /// Compiles the nodes of the file at `path`, locating errors in it.
+    fn compile_file<'a>(
+        &self,
+        path: &'a str,
+        source: &'a str,
+        nodes: &[Node<'a>],
+        pending: &mut Vec<PendingWrite<'a>>,
+        compile: &mut Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let including = std::mem::replace(&mut compile.file, path);
+        let result = self
+            .compile_nodes(source, nodes, pending, compile, rust)
+            .map_err(|err| err.in_file(path));
+        compile.file = including;
+        result
     }
 
     fn compile_nodes<'a>(
@@ -611,7 +1205,7 @@ impl Compiler {
         for node in nodes {
             match &node.kind {
                 NodeKind::Text(_) => {
-                    let content = Self::text_content(source, node.span);
+                    let content = trimmed_text(source, node.span);
                     if !content.is_empty() {
                         pending.push(PendingWrite::Raw(content));
                     }
@@ -632,20 +1226,36 @@ impl Compiler {
                         *expression_span,
                         node.span,
                     );
-                    pending.push(Self::select_write(
+                    pending.push(self.select_write(
                         &expression,
                         if *escaped {
                             DisplayKind::HtmlEscaped
                         } else {
                             DisplayKind::Raw
                         },
+                        compile.file,
                     )?);
                 }
-                NodeKind::Block(block) => {
-                    self.compile_block(source, block, pending, compile, rust)?;
+                NodeKind::Block(block) => match block.name {
+                    "block" => self.compile_region(source, block, pending, compile, rust)?,
+                    "extends" => {
+                        return Err(ParseError::at(
+                            DiagnosticCode::InvalidLayout,
+                            "only the compiled template can extend a layout".to_string(),
+                            block.name_span,
+                        ))
+                    }
+                    _ => self.compile_block(source, block, pending, compile, rust)?,
+                },
+                NodeKind::Partial { path, .. } => {
+                    let partials = compile.partials;
+                    let partial = partials[*path].as_str();
+                    let parsed = parse_partial(partial);
+                    self.compile_file(path, partial, &parsed.nodes, pending, compile, rust)
+                        .map_err(|err| err.in_partial(path))?;
                 }
                 NodeKind::RawBlock { content_span, .. } => {
-                    let content = Self::text_content(source, *content_span);
+                    let content = trimmed_text(source, *content_span);
                     if !content.is_empty() {
                         pending.push(PendingWrite::Raw(content));
                     }
@@ -660,22 +1270,81 @@ impl Compiler {
     ///
     /// The returned source expects the configured root and writer names to be
     /// valid in the context where the statements are inserted.
+    ///
+    /// Errors carry every parse diagnostic for the template, or the span of
+    /// the expression that failed to compile. See [`ParseError::render`].
     pub fn compile(&self, src: &str) -> Result<Rust> {
         let parsed = parse_template(src);
-        if let Some(diagnostic) = parsed.diagnostics.first() {
-            return Err(ParseError::from_diagnostic(diagnostic));
+        if !parsed.diagnostics.is_empty() {
+            return Err(ParseError::from_diagnostics(&parsed.diagnostics, src));
+        }
+        let mut partials = HashMap::new();
+        self.compile_parsed(&parsed, &mut partials)
+            .map_err(|err| err.locate(&partials))
+    }
+
+    fn compile_parsed(
+        &self,
+        parsed: &ParsedTemplate<'_>,
+        partials: &mut HashMap<String, String>,
+    ) -> Result<Rust> {
+        self.load_partials(
+            &parsed.nodes,
+            ("", parsed.source),
+            &mut Vec::new(),
+            partials,
+        )?;
+        let partials = &*partials;
+        let mut layouts = Vec::new();
+        let mut extended = Self::extended_layout(&parsed.nodes);
+        while let Some(path) = extended {
+            let layout = parse_partial(partials[path].as_str());
+            extended = Self::extended_layout(&layout.nodes);
+            layouts.push((path, layout));
         }
         let mut compile = Compile::new(
             self.options.root_var_name,
             &self.block_map,
             &self.helper_paths,
+            &self.helper_signatures,
+            partials,
         );
+        for (path, template) in std::iter::once(("", parsed))
+            .chain(layouts.iter().map(|(path, layout)| (*path, layout)))
+        {
+            Self::collect_regions(&template.nodes, path, template.source, &mut compile);
+        }
+        let (root_path, root) = layouts
+            .last()
+            .map_or(("", parsed), |(path, layout)| (*path, layout));
         let mut rust = Rust {
-            code: String::with_capacity(src.len().saturating_mul(2)),
+            code: String::with_capacity(parsed.source.len().saturating_mul(2)),
+            static_len: 0,
         };
         let mut pending: Vec<PendingWrite> = Vec::with_capacity(16);
-        self.compile_nodes(src, &parsed.nodes, &mut pending, &mut compile, &mut rust)?;
+        self.compile_file(
+            root_path,
+            root.source,
+            &root.nodes,
+            &mut pending,
+            &mut compile,
+            &mut rust,
+        )?;
         self.commit_pending(&mut pending, &mut compile, &mut rust)?;
+        let mut unused = compile
+            .regions
+            .iter()
+            .filter(|(name, _)| !compile.used_regions.contains(*name))
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(name, _)| **name);
+        if let Some((name, region)) = unused.first() {
+            return Err(ParseError::at(
+                DiagnosticCode::InvalidLayout,
+                format!("block `{name}` is not declared by the extended layout"),
+                region.span,
+            )
+            .in_file(region.path));
+        }
         Ok(rust)
     }
 }
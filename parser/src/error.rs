@@ -1,10 +1,29 @@
-use crate::{expression::Expression, Diagnostic};
-use std::{error::Error, fmt::Display};
+use crate::{expression::Expression, Diagnostic, DiagnosticCode, Span};
+use std::{collections::HashMap, error::Error, fmt::Display};
 
 /// A template parsing or source-generation error.
+///
+/// Errors found by the parser or resolved to a template location carry
+/// [`Diagnostic`]s. Use [`ParseError::render`] to report them with the source
+/// line they point at.
 #[derive(Debug)]
 pub struct ParseError {
     pub(crate) message: String,
+    diagnostics: Vec<Diagnostic>,
+    partial: Option<Box<(String, String)>>,
+    origin: Option<Box<Origin>>,
+}
+
+/// Location of an error raised while generating Rust.
+///
+/// The span is known where the error is raised, but the file it points into
+/// is recorded as the error passes out of a partial, layout, or region.
+#[derive(Debug)]
+struct Origin {
+    span: Span,
+    path: Option<String>,
+    code: DiagnosticCode,
+    message: String,
 }
 
 pub(crate) fn rcap(src: &str) -> &str {
@@ -17,29 +36,179 @@ pub(crate) fn rcap(src: &str) -> &str {
     }
 }
 
+/// Returns the 1-based line and character column of `offset` in `source`.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+    (
+        source[..line_start].matches('\n').count() + 1,
+        source[line_start..offset].chars().count() + 1,
+    )
+}
+
 impl ParseError {
     pub(crate) fn new(message: &str, expression: &Expression<'_>) -> Self {
+        let mut err = Self::at(
+            DiagnosticCode::InvalidExpression,
+            message.to_string(),
+            expression.span,
+        );
+        err.message = format!("{} near \"{}\"", message, expression.around());
+        err
+    }
+
+    /// Creates an error located at `span` in the source being compiled.
+    pub(crate) fn at(code: DiagnosticCode, message: String, span: Span) -> Self {
         Self {
-            message: format!("{} near \"{}\"", message, expression.around()),
+            message: message.clone(),
+            diagnostics: Vec::new(),
+            partial: None,
+            origin: Some(Box::new(Origin {
+                span,
+                path: None,
+                code,
+                message,
+            })),
         }
     }
 
-    pub(crate) fn unclosed(preffix: &str) -> Self {
+    pub(crate) fn message(message: String) -> Self {
         Self {
-            message: format!("unclosed block near {}", rcap(preffix)),
+            message,
+            diagnostics: Vec::new(),
+            partial: None,
+            origin: None,
         }
     }
 
-    pub(crate) fn from_diagnostic(diagnostic: &Diagnostic) -> Self {
+    pub(crate) fn unclosed(preffix: &str) -> Self {
+        Self::message(format!("unclosed block near {}", rcap(preffix)))
+    }
+
+    pub(crate) fn from_diagnostic(diagnostic: &Diagnostic, source: &str) -> Self {
+        Self::from_diagnostics(std::slice::from_ref(diagnostic), source)
+    }
+
+    /// Creates an error from diagnostics whose spans point into `source`.
+    pub(crate) fn from_diagnostics(diagnostics: &[Diagnostic], source: &str) -> Self {
         Self {
-            message: format!(
-                "{} [{}] at bytes {}..{}",
-                diagnostic.message, diagnostic.code, diagnostic.span.start, diagnostic.span.end
-            ),
+            message: diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    let (line, column) = line_column(source, diagnostic.span.start);
+                    format!(
+                        "{} [{}] at {line}:{column}",
+                        diagnostic.message, diagnostic.code
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            diagnostics: diagnostics.to_vec(),
+            partial: None,
+            origin: None,
+        }
+    }
+
+    pub(crate) fn in_partial(mut self, path: &str) -> Self {
+        if !path.is_empty() {
+            self.message = format!("{} in partial {}", self.message, path);
+            if !self.diagnostics.is_empty() && self.partial.is_none() {
+                self.partial = Some(Box::new((path.to_string(), String::new())));
+            }
+        }
+        self.in_file(path)
+    }
+
+    /// Records that a generated-code error was raised in the file at `path`,
+    /// or in the compiled template when `path` is empty.
+    ///
+    /// The innermost file wins, so callers apply this as the error leaves the
+    /// nodes of each file.
+    pub(crate) fn in_file(mut self, path: &str) -> Self {
+        if let Some(origin) = &mut self.origin {
+            origin.path.get_or_insert_with(|| path.to_string());
         }
+        self
+    }
+
+    /// Resolves the error's location against the compiled partials.
+    pub(crate) fn locate(mut self, partials: &HashMap<String, String>) -> Self {
+        if let Some(origin) = self.origin.take() {
+            self.diagnostics = vec![Diagnostic::error(origin.code, origin.span, origin.message)];
+            self.partial = origin
+                .path
+                .filter(|path| !path.is_empty())
+                .map(|path| Box::new((path, String::new())));
+        }
+        if let Some(partial) = &mut self.partial {
+            match partials.get(&partial.0) {
+                Some(text) => partial.1 = text.clone(),
+                None => {
+                    self.partial = None;
+                    self.diagnostics.clear();
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns the located diagnostics for this error.
+    ///
+    /// Spans are byte offsets into the template, or into
+    /// [`ParseError::partial`] when the error is inside a partial or layout.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the path of the partial or layout the diagnostics refer to.
+    pub fn partial(&self) -> Option<&str> {
+        self.partial.as_ref().map(|partial| partial.0.as_str())
+    }
+
+    /// Formats every diagnostic as `path:line:col` with the source line and a
+    /// caret under the span.
+    ///
+    /// `path` and `source` describe the compiled template. Errors without
+    /// diagnostics fall back to their message.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        if self.diagnostics.is_empty() {
+            return self.message.clone();
+        }
+        let (path, source) = match self.partial.as_deref() {
+            Some((path, source)) => (path.as_str(), source.as_str()),
+            None => (path, source),
+        };
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| render_diagnostic(diagnostic, path, source))
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
+fn render_diagnostic(diagnostic: &Diagnostic, path: &str, source: &str) -> String {
+    let start = diagnostic.span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |index| start + index);
+    let line = &source[line_start..line_end];
+    let (line_number, column) = line_column(source, start);
+    let end = diagnostic.span.end.clamp(start, line_end);
+    let width = source[start..end].chars().count().max(1);
+    let gutter = " ".repeat(line_number.to_string().len());
+    format!(
+        "error[{code}]: {message}\n{gutter}--> {path}:{line_number}:{column}\n{gutter} |\n{line_number} | {line}\n{gutter} | {pad}{carets}",
+        code = diagnostic.code,
+        message = diagnostic.message,
+        pad = line[..start - line_start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>(),
+        carets = "^".repeat(width),
+    )
+}
+
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.message)
@@ -48,9 +217,7 @@ impl Display for ParseError {
 
 impl From<std::io::Error> for ParseError {
     fn from(err: std::io::Error) -> Self {
-        Self {
-            message: err.to_string(),
-        }
+        Self::message(err.to_string())
     }
 }
 
@@ -1,6 +1,9 @@
 use std::{cmp::min, fmt::Display};
 
-use crate::error::{ParseError, Result};
+use crate::{
+    error::{ParseError, Result},
+    Span,
+};
 
 /// Kind of expression delimiter found in a template.
 #[derive(Debug, Clone, Copy)]
@@ -32,6 +35,11 @@ pub struct Expression<'a> {
     pub postfix: &'a str,
     /// The source slice used to produce this expression.
     pub raw: &'a str,
+    /// Location of [`Self::raw`] in the text it was parsed from.
+    ///
+    /// Expressions built by the compiler point into the template or partial
+    /// being compiled, and errors raised for them are reported at this span.
+    pub span: Span,
 }
 
 #[inline]
@@ -44,7 +52,9 @@ fn nibble(src: &str, start: usize, len: usize) -> Result<usize> {
 }
 
 impl<'a> Expression<'a> {
+    /// Closes an expression whose content begins `start`, a suffix of `src`.
     fn close(
+        src: &'a str,
         expression_type: ExpressionType,
         preffix: &'a str,
         start: &'a str,
@@ -53,34 +63,34 @@ impl<'a> Expression<'a> {
         match start.find(end) {
             Some(mut pos) => {
                 if pos == 0 {
-                    return Err(ParseError {
-                        message: format!("empty block near {}", preffix),
-                    });
+                    return Err(ParseError::message(format!("empty block near {}", preffix)));
                 }
                 let mut postfix = &start[pos + end.len()..];
                 if &start[pos - 1..pos] == "~" {
                     postfix = postfix.trim_start();
                     pos -= 1;
                 }
+                let offset = src.len() - start.len();
                 Ok(Self {
                     expression_type,
                     prefix: preffix,
                     content: &start[..pos],
                     postfix,
                     raw: &start[..pos + end.len()],
+                    span: Span::new(offset, offset + pos + end.len()),
                 })
             }
             None => Err(ParseError::unclosed(preffix)),
         }
     }
 
-    fn check_comment(preffix: &'a str, start: &'a str) -> Result<Self> {
+    fn check_comment(src: &'a str, preffix: &'a str, start: &'a str) -> Result<Self> {
         if let Some(pos) = start.find("--") {
             if pos == 0 {
-                return Self::close(ExpressionType::Comment, preffix, &start[2..], "--}}");
+                return Self::close(src, ExpressionType::Comment, preffix, &start[2..], "--}}");
             }
         }
-        Self::close(ExpressionType::Comment, preffix, start, "}}")
+        Self::close(src, ExpressionType::Comment, preffix, start, "}}")
     }
 
     fn find_closing_escape(open: Expression<'a>) -> Result<Self> {
@@ -101,6 +111,7 @@ impl<'a> Expression<'a> {
                     content: &open.postfix[..from + candidate],
                     postfix: &postfix[end..],
                     raw: open.raw,
+                    span: open.span,
                 });
             }
             from += end;
@@ -110,13 +121,15 @@ impl<'a> Expression<'a> {
 
     /// Parses the first expression in `src`.
     ///
-    /// Returns `None` when the source contains no opening delimiter.
+    /// Returns `None` when the source contains no opening delimiter. The
+    /// expression's span is an offset into `src`.
     pub fn from(src: &'a str) -> Result<Option<Self>> {
         match src.find("{{") {
             Some(start) => {
                 let mut second = nibble(src, start, 3)?;
                 if start > 0 && &src[start - 1..start] == "\\" {
                     return Ok(Some(Self::close(
+                        src,
                         ExpressionType::Escaped,
                         &src[..start - 1],
                         &src[second - 1..],
@@ -142,6 +155,7 @@ impl<'a> Expression<'a> {
                                 prefix = prefix.trim_end();
                             }
                             return Ok(Some(Self::find_closing_escape(Self::close(
+                                src,
                                 ExpressionType::Escaped,
                                 prefix,
                                 &src[second..],
@@ -152,12 +166,13 @@ impl<'a> Expression<'a> {
                             second = next;
                             prefix = prefix.trim_end();
                         }
-                        Self::close(ExpressionType::Raw, prefix, &src[second..], "}}}")?
+                        Self::close(src, ExpressionType::Raw, prefix, &src[second..], "}}}")?
                     }
-                    "!" => Self::check_comment(prefix, &src[second..])?,
-                    "#" => Self::close(ExpressionType::Open, prefix, &src[second..], "}}")?,
-                    "/" => Self::close(ExpressionType::Close, prefix, &src[second..], "}}")?,
+                    "!" => Self::check_comment(src, prefix, &src[second..])?,
+                    "#" => Self::close(src, ExpressionType::Open, prefix, &src[second..], "}}")?,
+                    "/" => Self::close(src, ExpressionType::Close, prefix, &src[second..], "}}")?,
                     _ => Self::close(
+                        src,
                         ExpressionType::HtmlEscaped,
                         prefix,
                         &src[second - 1..],
@@ -170,6 +185,8 @@ impl<'a> Expression<'a> {
     }
 
     /// Parses the next expression in [`Self::postfix`].
+    ///
+    /// The returned span is an offset into [`Self::postfix`].
     pub fn next(&self) -> Result<Option<Self>> {
         Self::from(self.postfix)
     }
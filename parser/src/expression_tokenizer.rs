@@ -20,6 +20,8 @@ pub struct Token<'a> {
     pub token_type: TokenType<'a>,
     /// The token source without an `@` prefix or subexpression parentheses.
     pub value: &'a str,
+    /// The name of a `key=value` hash argument; `value` holds what follows `=`.
+    pub key: Option<&'a str>,
     /// Unparsed expression text after this token.
     pub tail: &'a str,
 }
@@ -37,9 +39,10 @@ fn find_closing(src: &str) -> Result<usize> {
             return Ok(i + 1);
         }
     }
-    Err(ParseError {
-        message: format!("unmatched brackets near {}", rcap(src)),
-    })
+    Err(ParseError::message(format!(
+        "unmatched brackets near {}",
+        rcap(src)
+    )))
 }
 
 fn find_end_of_string(src: &str) -> Result<usize> {
@@ -52,9 +55,10 @@ fn find_end_of_string(src: &str) -> Result<usize> {
             _ => (),
         }
     }
-    Err(ParseError {
-        message: format!("unterminated string near {}", rcap(src)),
-    })
+    Err(ParseError::message(format!(
+        "unterminated string near {}",
+        rcap(src)
+    )))
 }
 
 fn find_end(src: &str) -> usize {
@@ -76,13 +80,40 @@ fn invalid_variable_name(src: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Returns `key` if `src` starts with a `key=value` hash argument.
+///
+/// The syntax tokenizer uses this too, so both agree on what is a hash key.
+pub(crate) fn hash_key(src: &str) -> Option<&str> {
+    let end = src.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+    let key = &src[..end];
+    (end > 0
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && src[end..].starts_with('=')
+        && !src[end..].starts_with("=="))
+    .then_some(key)
+}
+
 fn parse<'a>(src: &'a str) -> Result<Option<Token<'a>>> {
+    if let Some(key) = hash_key(src) {
+        let value = &src[key.len() + 1..];
+        return match parse(value)? {
+            Some(token) if !value.starts_with(char::is_whitespace) => Ok(Some(Token {
+                key: Some(key),
+                ..token
+            })),
+            _ => Err(ParseError::message(format!(
+                "expected value after {key}= near {}",
+                rcap(src)
+            ))),
+        };
+    }
     Ok(match src.chars().next() {
         Some('@') => {
             let end = find_end(src);
             Some(Token {
                 token_type: TokenType::PrivateVariable,
                 value: &src[1..end],
+                key: None,
                 tail: src[end..].trim_start(),
             })
         }
@@ -91,6 +122,7 @@ fn parse<'a>(src: &'a str) -> Result<Option<Token<'a>>> {
             Some(Token {
                 token_type: TokenType::SubExpression(&src[..end]),
                 value: &src[1..end],
+                key: None,
                 tail: src[end + 1..].trim_start(),
             })
         }
@@ -111,6 +143,7 @@ fn parse<'a>(src: &'a str) -> Result<Option<Token<'a>>> {
             Some(Token {
                 token_type,
                 value: &src[..end],
+                key: None,
                 tail: src[end..].trim_start(),
             })
         }
@@ -0,0 +1,574 @@
+//! Template formatting built on the recoverable syntax tree.
+
+use std::path::Path;
+
+use crate::{
+    error::{ParseError, Result},
+    syntax::{parse_partial, BlockNode, Node, NodeKind, Span, SyntaxToken, SyntaxTokenKind},
+};
+
+/// Indentation used by [`format_template`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Columns per indentation level. Tabs in the source count as this many.
+    pub indent_width: usize,
+    /// Indents with tabs instead of spaces.
+    pub use_tabs: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            use_tabs: false,
+        }
+    }
+}
+
+/// Returns whether a template path names plain text, such as `email.txt.rhbs`.
+///
+/// Plain-text templates are neither HTML escaped nor minified by default, and
+/// keep all of their whitespace, so they are not formatted either.
+pub fn is_plain_text(path: impl AsRef<Path>) -> bool {
+    let stem = path.as_ref().file_stem().map(Path::new);
+    matches!(
+        stem.and_then(Path::extension).and_then(|ext| ext.to_str()),
+        Some("txt" | "md")
+    )
+}
+
+/// Formats an HTML template without changing what it renders.
+///
+/// Only lines holding nothing but `{{ }}` tags are re-indented. Inside a
+/// block body they are indented one level deeper than the line that opens the
+/// block, keeping their indentation relative to each other, and lines
+/// starting with the block's `{{else}}` or closing tag line up with its
+/// opening line. Lines with text, and every line inside `<pre>` and
+/// `<textarea>`, keep their indentation. Whitespace inside `{{ }}` is
+/// normalized to single spaces, `~` trim markers are kept, and comments and
+/// raw blocks are copied unchanged. Lines are never added or removed.
+///
+/// The indentation of a tag line is still written by the template, which only
+/// HTML can ignore, so plain-text templates should not be formatted; see
+/// [`is_plain_text`].
+///
+/// Templates with syntax errors are returned as an error instead of being
+/// formatted.
+///
+/// ```rust
+/// use rusty_handlebars_parser::{format_template, FormatOptions};
+///
+/// let formatted = format_template(
+///     "<ul>\n{{# each items as | item |}}\n{{> \"row.rhbs\" }}\n  <li>{{ item }}</li>\n{{/each}}\n</ul>",
+///     &FormatOptions::default(),
+/// )
+/// .unwrap();
+/// assert_eq!(
+///     formatted,
+///     "<ul>\n{{#each items as |item|}}\n    {{> \"row.rhbs\"}}\n  <li>{{item}}</li>\n{{/each}}\n</ul>"
+/// );
+/// ```
+pub fn format_template(source: &str, options: &FormatOptions) -> Result<String> {
+    // Partials are formatted like any template, so private variables are not
+    // checked against the blocks around them.
+    let parsed = parse_partial(source);
+    if parsed.has_errors() {
+        return Err(ParseError::from_diagnostics(&parsed.diagnostics, source));
+    }
+    let mut verbatim = Vec::new();
+    collect_verbatim(&parsed.nodes, &mut verbatim);
+    let mut tags = Vec::new();
+    collect_tags(&parsed.nodes, &mut tags);
+    let tag_lines = tag_lines(source, &tags);
+    let mut formatter = Formatter {
+        source,
+        options,
+        verbatim,
+        tag_lines,
+        out: String::with_capacity(source.len()),
+        frames: Vec::new(),
+        line: None,
+    };
+    formatter.new_line(0);
+    formatter.body(&parsed.nodes, 0, source.len());
+    Ok(formatter.out)
+}
+
+/// Indentation of the lines inside a block body.
+struct Frame {
+    /// The formatted indentation of the line opening the block.
+    open: usize,
+    /// The smallest source indentation of the body's lines.
+    base: usize,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    options: &'a FormatOptions,
+    /// Comments and raw blocks, whose lines are left as they are.
+    verbatim: Vec<Span>,
+    /// The starts of the lines that may be re-indented, in order.
+    tag_lines: Vec<usize>,
+    out: String,
+    frames: Vec<Frame>,
+    /// The source indentation of a line whose indentation is not written yet.
+    line: Option<usize>,
+}
+
+impl Formatter<'_> {
+    fn body(&mut self, nodes: &[Node<'_>], mut cursor: usize, end: usize) {
+        for node in nodes {
+            // Text is copied from the source between tags, which keeps
+            // escaped `\{{` intact.
+            if matches!(node.kind, NodeKind::Text(_)) {
+                continue;
+            }
+            self.text(cursor, node.span.start);
+            match &node.kind {
+                NodeKind::Interpolation {
+                    escaped, tokens, ..
+                } => {
+                    let open = if *escaped { "{{" } else { "{{{" };
+                    self.tag(node.span, open, &join_tokens(tokens), false);
+                }
+                NodeKind::Partial { path, path_span } => {
+                    let quoted = self.source[..path_span.start].ends_with('"');
+                    let path = if quoted {
+                        format!("> \"{path}\"")
+                    } else {
+                        format!("> {path}")
+                    };
+                    self.tag(node.span, "{{", &path, false);
+                }
+                NodeKind::Block(block) => self.block(block),
+                NodeKind::Text(_)
+                | NodeKind::Comment { .. }
+                | NodeKind::RawBlock { .. }
+                | NodeKind::Error(_) => {
+                    self.start_line(false);
+                    self.out
+                        .push_str(&self.source[node.span.start..node.span.end]);
+                }
+            }
+            cursor = node.span.end;
+        }
+        self.text(cursor, end);
+    }
+
+    fn block(&mut self, block: &BlockNode<'_>) {
+        let mut header = format!("#{}", block.name);
+        if !block.arguments.is_empty() {
+            header.push(' ');
+            header.push_str(&join_tokens(&block.arguments));
+        }
+        self.tag(block.open_span, "{{", &header, false);
+        let close = block
+            .close_span
+            .unwrap_or(Span::new(block.full_span().end, block.full_span().end));
+        let mut ends = block
+            .else_branches
+            .iter()
+            .map(|branch| branch.span.start)
+            .chain(std::iter::once(close.start));
+        let skip = ends.clone().collect::<Vec<_>>();
+        self.frames.push(Frame {
+            open: self.current_indent(),
+            base: self.base_indent(block.open_span.end, close.start, &skip),
+        });
+        let first_end = ends.next().unwrap_or(close.start);
+        self.body(&block.body, block.open_span.end, first_end);
+        for branch in &block.else_branches {
+            let mut header = "else".to_owned();
+            if !branch.arguments.is_empty() {
+                header.push(' ');
+                header.push_str(&join_tokens(&branch.arguments));
+            }
+            self.tag(branch.span, "{{", &header, true);
+            let end = ends.next().unwrap_or(close.start);
+            self.body(&branch.body, branch.span.end, end);
+        }
+        if !close.is_empty() {
+            self.tag(
+                close,
+                "{{",
+                &format!("/{}", close_name(self.source, close)),
+                true,
+            );
+        }
+        self.frames.pop();
+    }
+
+    /// Writes a tag with normalized contents, keeping its `~` markers.
+    fn tag(&mut self, span: Span, open: &str, contents: &str, closes_body: bool) {
+        let text = &self.source[span.start..span.end];
+        let close = if open == "{{{" { "}}}" } else { "}}" };
+        self.start_line(closes_body);
+        self.out.push_str(open);
+        if text[open.len()..].starts_with('~') {
+            self.out.push('~');
+        }
+        self.out.push_str(contents);
+        if text[..text.len() - close.len()].ends_with('~') {
+            self.out.push('~');
+        }
+        self.out.push_str(close);
+    }
+
+    /// Copies source text, replacing the indentation of each line it starts.
+    fn text(&mut self, start: usize, end: usize) {
+        for (relative, character) in self.source[start..end].char_indices() {
+            match character {
+                '\n' => {
+                    self.out.push('\n');
+                    self.new_line(start + relative + 1);
+                }
+                ' ' | '\t' if self.line.is_some() => {}
+                '\r' if self.line.is_some() => self.out.push('\r'),
+                character => {
+                    self.start_line(false);
+                    self.out.push(character);
+                }
+            }
+        }
+    }
+
+    /// Starts the line at `line_start`, whose indentation is replaced only if
+    /// it holds nothing but tags.
+    fn new_line(&mut self, line_start: usize) {
+        self.line = self
+            .tag_lines
+            .binary_search(&line_start)
+            .is_ok()
+            .then(|| indent_width(self.source, line_start, self.options.indent_width));
+    }
+
+    /// Writes the indentation of a pending line before its first content.
+    ///
+    /// `closes_body` is set for the `{{else}}` and closing tags of the
+    /// innermost block.
+    fn start_line(&mut self, closes_body: bool) {
+        let Some(source_indent) = self.line.take() else {
+            return;
+        };
+        let width = match self.frames.last() {
+            None => source_indent,
+            Some(frame) if closes_body => frame.open,
+            Some(frame) => {
+                frame.open + self.options.indent_width + source_indent.saturating_sub(frame.base)
+            }
+        };
+        if self.options.use_tabs && self.options.indent_width > 0 {
+            self.out
+                .extend(std::iter::repeat_n('\t', width / self.options.indent_width));
+            self.out
+                .extend(std::iter::repeat_n(' ', width % self.options.indent_width));
+        } else {
+            self.out.extend(std::iter::repeat_n(' ', width));
+        }
+    }
+
+    /// Returns the formatted indentation of the line being written.
+    fn current_indent(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |index| index + 1);
+        indent_width(&self.out, line_start, self.options.indent_width)
+    }
+
+    /// Returns the smallest indentation of the tag lines starting between
+    /// `start` and `end`, ignoring lines inside comments and raw blocks, and
+    /// lines starting at an offset in `skip`.
+    fn base_indent(&self, start: usize, end: usize, skip: &[usize]) -> usize {
+        self.source[start..end]
+            .match_indices('\n')
+            .map(|(relative, _)| start + relative + 1)
+            .filter(|line_start| {
+                self.tag_lines.binary_search(line_start).is_ok()
+                    && !self
+                        .verbatim
+                        .iter()
+                        .any(|span| span.start < *line_start && *line_start < span.end)
+            })
+            .filter_map(|line_start| {
+                let content = line_start + self.source[line_start..].len()
+                    - self.source[line_start..]
+                        .trim_start_matches([' ', '\t'])
+                        .len();
+                let blank = self.source[content..].is_empty()
+                    || self.source[content..].starts_with(['\n', '\r']);
+                (!blank && !skip.contains(&content))
+                    .then(|| indent_width(self.source, line_start, self.options.indent_width))
+            })
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+fn collect_verbatim(nodes: &[Node<'_>], spans: &mut Vec<Span>) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Comment { .. } | NodeKind::RawBlock { .. } => spans.push(node.span),
+            NodeKind::Block(block) => {
+                for body in block.bodies() {
+                    collect_verbatim(body, spans);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the spans of the `{{ }}` tags in `nodes`.
+fn collect_tags(nodes: &[Node<'_>], spans: &mut Vec<Span>) {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Interpolation { .. }
+            | NodeKind::Partial { .. }
+            | NodeKind::Comment { .. } => spans.push(node.span),
+            NodeKind::Block(block) => {
+                spans.push(block.open_span);
+                spans.extend(block.else_branches.iter().map(|branch| branch.span));
+                spans.extend(block.close_span);
+                for body in block.bodies() {
+                    collect_tags(body, spans);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Elements whose text keeps its whitespace when rendered.
+const PRESERVED_ELEMENTS: &[&str] = &["pre", "textarea"];
+
+/// Returns the starts of the lines that hold at least one of the tags at
+/// `spans` and nothing else but whitespace, outside elements that preserve
+/// whitespace.
+fn tag_lines(source: &str, spans: &[Span]) -> Vec<usize> {
+    let mut covered = vec![false; source.len()];
+    for span in spans {
+        covered[span.start..span.end].fill(true);
+    }
+    let preserved = preserved_ranges(source);
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    for line in source.split('\n') {
+        let range = line_start..line_start + line.len();
+        let tag_only = range.clone().any(|offset| covered[offset])
+            && range
+                .clone()
+                .all(|offset| covered[offset] || source.as_bytes()[offset].is_ascii_whitespace());
+        let preserved = preserved
+            .iter()
+            .any(|preserved| preserved.start < line_start && line_start <= preserved.end);
+        if tag_only && !preserved {
+            lines.push(line_start);
+        }
+        line_start = range.end + 1;
+    }
+    lines
+}
+
+/// Returns the spans from the opening to the closing tag of each element in
+/// [`PRESERVED_ELEMENTS`]. An element that is never closed runs to the end.
+fn preserved_ranges(source: &str) -> Vec<Span> {
+    let lower = source.to_ascii_lowercase();
+    let mut ranges = Vec::new();
+    for element in PRESERVED_ELEMENTS {
+        let open = format!("<{element}");
+        let close = format!("</{element}");
+        let mut cursor = 0;
+        while let Some(relative) = lower[cursor..].find(&open) {
+            let start = cursor + relative;
+            let after = lower.as_bytes().get(start + open.len());
+            if !after.is_some_and(|byte| *byte == b'>' || byte.is_ascii_whitespace()) {
+                cursor = start + open.len();
+                continue;
+            }
+            let end = lower[start..]
+                .find(&close)
+                .map_or(source.len(), |relative| start + relative);
+            ranges.push(Span::new(start, end));
+            cursor = end.max(start + open.len());
+        }
+    }
+    ranges
+}
+
+/// Returns the width of the indentation starting at `line_start`.
+fn indent_width(text: &str, line_start: usize, tab_width: usize) -> usize {
+    text[line_start..]
+        .chars()
+        .map_while(|character| match character {
+            ' ' => Some(1),
+            '\t' => Some(tab_width),
+            _ => None,
+        })
+        .sum()
+}
+
+/// Returns the block name in a `{{/name}}` tag.
+fn close_name(source: &str, span: Span) -> &str {
+    source[span.start..span.end]
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .trim_matches('~')
+        .trim()
+        .trim_start_matches('/')
+        .trim()
+}
+
+/// Joins expression tokens with single spaces, writing `key=value` arguments
+/// and `as |alias|` bindings without inner spaces.
+fn join_tokens(tokens: &[SyntaxToken<'_>]) -> String {
+    let mut out = String::new();
+    let mut in_pipes = false;
+    let mut glued = true;
+    for token in tokens {
+        let pipe = token.kind == SyntaxTokenKind::Punctuation && token.text == "|";
+        let closing_pipe = pipe && in_pipes;
+        if !(glued || closing_pipe) {
+            out.push(' ');
+        }
+        glued = false;
+        match token.kind {
+            SyntaxTokenKind::HashKey => {
+                out.push_str(token.text);
+                out.push('=');
+                glued = true;
+            }
+            SyntaxTokenKind::Subexpression => normalize_subexpression(token.text, &mut out),
+            _ => {
+                out.push_str(token.text);
+                if pipe {
+                    glued = !in_pipes;
+                    in_pipes = !in_pipes;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Collapses whitespace in a `(helper args)` subexpression, dropping it next
+/// to the parentheses and leaving string literals unchanged.
+fn normalize_subexpression(text: &str, out: &mut String) {
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut space = false;
+    for character in text.chars() {
+        if in_string {
+            out.push(character);
+            match character {
+                '"' if !escaped => in_string = false,
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+            continue;
+        }
+        if character.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && character != ')' && !out.ends_with('(') {
+            out.push(' ');
+        }
+        space = false;
+        in_string = character == '"';
+        out.push(character);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_template(source, &FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn reindents_nested_bodies() {
+        let source = "<ul>\n  {{#each items as |item|}}\n{{#if item.done}}\n        {{item.name}}\n  <b>done</b>\n{{else}}\n    {{> row.rhbs}} {{ item.name }}\n{{/if}}\n      {{/each}}\n</ul>\n";
+        let formatted = format(source);
+        assert_eq!(
+            formatted,
+            "<ul>\n  {{#each items as |item|}}\n      {{#if item.done}}\n              {{item.name}}\n  <b>done</b>\n      {{else}}\n          {{> row.rhbs}} {{item.name}}\n      {{/if}}\n  {{/each}}\n</ul>\n"
+        );
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn normalizes_tags_and_keeps_markers() {
+        assert_eq!(
+            format("{{~  name ~}} {{{ raw }}} {{ truncate  text   length=80 }}"),
+            "{{~name~}} {{{raw}}} {{truncate text length=80}}"
+        );
+        assert_eq!(
+            format("{{#if ( eq  a  \"x  y\" )}}{{~ else   if b}}{{ / if }}"),
+            "{{#if (eq a \"x  y\")}}{{~else if b}}{{/if}}"
+        );
+        assert_eq!(
+            format("{{>  partials/row.rhbs }}{{>\"a b.rhbs\"}}"),
+            "{{> partials/row.rhbs}}{{> \"a b.rhbs\"}}"
+        );
+    }
+
+    #[test]
+    fn copies_comments_raw_blocks_and_escapes() {
+        let source = "{{#if a}}\n{{!--\n  keep\n--}}\n  {{{{raw}}}}\n  {{ x }}\n{{{{/raw}}}}\n  \\{{  y  }}\n{{/if}}";
+        assert_eq!(
+            format(source),
+            "{{#if a}}\n    {{!--\n  keep\n--}}\n  {{{{raw}}}}\n  {{ x }}\n{{{{/raw}}}}\n  \\{{  y  }}\n{{/if}}"
+        );
+    }
+
+    #[test]
+    fn uses_tabs_and_rejects_errors() {
+        let options = FormatOptions {
+            indent_width: 2,
+            use_tabs: true,
+        };
+        assert_eq!(
+            format_template("{{#if a}}\n  {{x}}\n{{/if}}", &options).unwrap(),
+            "{{#if a}}\n\t{{x}}\n{{/if}}"
+        );
+        assert!(format_template("{{#if a}}", &options).is_err());
+    }
+
+    #[test]
+    fn keeps_text_and_preformatted_lines() {
+        let source = "<div>\n{{#if a}}\n    text {{b}}\n<pre>\n{{#if c}}\n  {{d}}\n{{/if}}\n</pre>\n<TEXTAREA>\n  {{e}}</textarea>\n  {{f}}\n{{/if}}\n</div>";
+        assert_eq!(
+            format(source),
+            "<div>\n{{#if a}}\n    text {{b}}\n<pre>\n{{#if c}}\n  {{d}}\n{{/if}}\n</pre>\n<TEXTAREA>\n  {{e}}</textarea>\n    {{f}}\n{{/if}}\n</div>"
+        );
+        assert!(is_plain_text("templates/mail.txt.rhbs"));
+        assert!(is_plain_text("README.md.rhbs"));
+        assert!(!is_plain_text("templates/page.rhbs"));
+        assert!(!is_plain_text("templates/page.html.rhbs"));
+    }
+
+    #[cfg(feature = "minify-html")]
+    #[test]
+    fn renders_the_same_after_formatting() {
+        use crate::{build_helper::minify_template, BlockMap, Compiler, Options};
+
+        let compile = |source: &str| {
+            let mut block_map = BlockMap::new();
+            crate::add_builtins(&mut block_map);
+            Compiler::new(
+                Options {
+                    root_var_name: Some("self"),
+                    write_var_name: "f",
+                },
+                block_map,
+            )
+            .compile(&minify_template(source))
+            .unwrap()
+            .code
+        };
+        let source = "<ul>\n{{#each items as |item|}}\n<li>\n  {{#if item.done}}\n<b>{{item.name}}</b>\n  {{else}}\n      {{item.name}}\n  {{/if}}\n</li>\n  {{/each}}\n</ul>\n<pre>\n{{#if code}}\n  {{code}}\n{{/if}}\n</pre>\n";
+        let formatted = format(source);
+        assert_ne!(formatted, source);
+        assert_eq!(compile(&formatted), compile(source));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::error::{ParseError, Result};
+
+/// Parameters declared for an inline helper.
+///
+/// A helper is declared as `path(name, name = default)`. Template arguments
+/// fill the parameters in order, `key=value` arguments fill them by name, and
+/// parameters left out are passed their default Rust expression. A parameter
+/// written `&name` is passed by reference, so fields that are not `Copy` can be
+/// used without moving them out of the template.
+///
+/// ```rust
+/// use rusty_handlebars_parser::HelperSignature;
+///
+/// let (path, signature) =
+///     HelperSignature::split("crate::truncate(text, length = 80)").unwrap();
+/// assert_eq!(path, "crate::truncate");
+/// let signature = signature.unwrap();
+/// assert_eq!(signature.params()[1].name, "length");
+/// assert_eq!(signature.params()[1].default.as_deref(), Some("80"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelperSignature {
+    params: Vec<HelperParam>,
+}
+
+/// One declared helper parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelperParam {
+    /// Name used by `name=value` arguments.
+    pub name: String,
+    /// Rust expression passed when the argument is left out.
+    pub default: Option<String>,
+    /// Whether the argument is passed as `&value`.
+    pub by_ref: bool,
+}
+
+impl HelperSignature {
+    /// Splits a helper declaration into its path and optional signature.
+    pub fn split(declaration: &str) -> Result<(&str, Option<Self>)> {
+        let declaration = declaration.trim();
+        let Some(open) = declaration.find('(') else {
+            return Ok((declaration, None));
+        };
+        let params = declaration[open + 1..]
+            .strip_suffix(')')
+            .ok_or_else(|| ParseError::message(format!("helper `{declaration}` is missing `)`")))?;
+        let mut signature = Self { params: Vec::new() };
+        for param in split_params(params) {
+            let (name, default) = match param.split_once('=') {
+                Some((name, default)) => (name.trim(), Some(default.trim().to_string())),
+                None => (param.trim(), None),
+            };
+            let (name, by_ref) = match name.strip_prefix('&') {
+                Some(name) => (name.trim_start(), true),
+                None => (name, false),
+            };
+            if name.is_empty()
+                || name.starts_with(|c: char| c.is_ascii_digit())
+                || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            {
+                return Err(ParseError::message(format!(
+                    "invalid parameter `{}` in helper `{declaration}`",
+                    param.trim()
+                )));
+            }
+            if default.as_ref().is_some_and(String::is_empty) {
+                return Err(ParseError::message(format!(
+                    "parameter `{name}` in helper `{declaration}` is missing its default"
+                )));
+            }
+            if signature.params.iter().any(|param| param.name == name) {
+                return Err(ParseError::message(format!(
+                    "duplicate parameter `{name}` in helper `{declaration}`"
+                )));
+            }
+            signature.params.push(HelperParam {
+                name: name.to_string(),
+                default,
+                by_ref,
+            });
+        }
+        Ok((declaration[..open].trim_end(), Some(signature)))
+    }
+
+    /// Returns the parameters in declaration order.
+    pub fn params(&self) -> &[HelperParam] {
+        &self.params
+    }
+}
+
+/// Parameters of the helpers in `rusty_handlebars::helpers`, used when one is
+/// configured without a declaration of its own.
+pub const STANDARD_HELPERS: &[&str] = &[
+    "capitalize(&value)",
+    "upper(&value)",
+    "lower(&value)",
+    "truncate(&value, length = 80, &ellipsis = \"…\")",
+    "join(&values, &separator = \", \")",
+    "default(&value, &fallback)",
+    "pluralize(&count, &singular, &plural)",
+    "json(&value)",
+    "url_encode(&value)",
+    "number_with_separators(&value, &separator = \",\")",
+    "format_date(&value, &format = \"%Y-%m-%d\")",
+];
+
+/// An inline helper configured with `helpers = ["..."]`.
+///
+/// ```rust
+/// use rusty_handlebars_parser::HelperDeclaration;
+///
+/// let helper = HelperDeclaration::parse("helpers::truncate").unwrap();
+/// assert_eq!(helper.name, "truncate");
+/// assert_eq!(helper.path, "::rusty_handlebars::helpers::truncate");
+/// assert_eq!(helper.signature.unwrap().params()[1].name, "length");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelperDeclaration {
+    /// Name templates call the helper by, the final segment of its path.
+    pub name: String,
+    /// Qualified Rust path of the helper function.
+    pub path: String,
+    /// Declared parameters, or those of the standard helper `path` names.
+    pub signature: Option<HelperSignature>,
+}
+
+impl HelperDeclaration {
+    /// Parses a helper declaration.
+    ///
+    /// Paths that do not start with `::`, `crate::`, `self::`, or `super::`
+    /// are relative to `::rusty_handlebars`, so `"helpers::join"` names a
+    /// standard helper.
+    pub fn parse(declaration: &str) -> Result<Self> {
+        let (path, signature) = HelperSignature::split(declaration)?;
+        let name = path.rsplit("::").next().unwrap_or(path).to_string();
+        let path = if ["::", "crate::", "self::", "super::"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+        {
+            path.to_string()
+        } else {
+            format!("::rusty_handlebars::{path}")
+        };
+        let signature = signature.or_else(|| standard_signature(&path));
+        Ok(Self {
+            name,
+            path,
+            signature,
+        })
+    }
+}
+
+fn standard_signature(path: &str) -> Option<HelperSignature> {
+    let name = path.strip_prefix("::rusty_handlebars::helpers::")?;
+    STANDARD_HELPERS
+        .iter()
+        .find_map(|declaration| match HelperSignature::split(declaration) {
+            Ok((standard, signature)) if standard == name => signature,
+            _ => None,
+        })
+}
+
+/// Splits on commas outside strings and brackets, so defaults may contain them.
+fn split_params(params: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in params.char_indices() {
+        if in_string {
+            match c {
+                '"' if !escaped => in_string = false,
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&params[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !params[start..].trim().is_empty() || !parts.is_empty() {
+        parts.push(&params[start..]);
+    }
+    parts
+}
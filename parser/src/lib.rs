@@ -19,19 +19,25 @@
 //! Most applications should use the `rusty-handlebars` derive macro instead.
 
 mod block;
+mod block_spec;
 #[cfg(feature = "minify-html")]
 pub mod build_helper;
 mod compiler;
 mod error;
 mod expression;
 mod expression_tokenizer;
+mod format;
+mod helper_signature;
 mod syntax;
 
 pub use block::*;
+pub use block_spec::*;
 pub use compiler::*;
 pub use error::*;
 pub use expression::*;
 pub use expression_tokenizer::*;
+pub use format::*;
+pub use helper_signature::*;
 pub use syntax::*;
 
 #[cfg(test)]
@@ -55,6 +61,23 @@ mod tests {
         map
     }
 
+    struct Partials(HashMap<&'static str, &'static str>);
+
+    impl PartialLoader for Partials {
+        fn load(&self, path: &str) -> std::io::Result<String> {
+            self.0
+                .get(path)
+                .map(|source| source.to_string())
+                .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn compile_with_partials(src: &str, partials: &[(&'static str, &'static str)]) -> Result<Rust> {
+        Compiler::new(OPTIONS, make_map())
+            .with_partial_loader(Partials(partials.iter().copied().collect()))
+            .compile(src)
+    }
+
     fn compile(src: &str) -> String {
         Compiler::new(OPTIONS, make_map())
             .compile(src)
@@ -79,12 +102,187 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_comparison_helpers() {
+        let rust = compile("{{#if (eq status \"paid\")}}Paid{{else if (ge total 10)}}Big{{/if}}");
+        assert_eq!(
+            rust,
+            "if ::rusty_handlebars::AsBool::as_bool(&(self.status == \"paid\")){write!(f, \"Paid\")?;}else if ::rusty_handlebars::AsBool::as_bool(&(self.total >= 10)){write!(f, \"Big\")?;}"
+        );
+    }
+
+    #[test]
+    fn test_boolean_helpers() {
+        let rust = compile("{{#unless (or a (and b (not c)) d)}}x{{/unless}}");
+        assert_eq!(
+            rust,
+            "if !::rusty_handlebars::AsBool::as_bool(&(::rusty_handlebars::AsBool::as_bool(&self.a) || ::rusty_handlebars::AsBool::as_bool(&(::rusty_handlebars::AsBool::as_bool(&self.b) && ::rusty_handlebars::AsBool::as_bool(&(!::rusty_handlebars::AsBool::as_bool(&self.c))))) || ::rusty_handlebars::AsBool::as_bool(&self.d))){write!(f, \"x\")?;}"
+        );
+        let err = Compiler::new(OPTIONS, make_map())
+            .compile("{{#if (eq a)}}{{/if}}")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("eq expects 2 arguments"), "{err}");
+    }
+
+    #[test]
+    fn test_static_len() {
+        let rust = Compiler::new(OPTIONS, make_map())
+            .compile("Hello {{name}}!{{#each items}}<li>{{/each}}")
+            .unwrap();
+        assert_eq!(rust.static_len, "Hello !<li>".len());
+    }
+
+    #[test]
+    fn test_contextual_escaping() {
+        assert_eq!(
+            compile("{{url link}}{{{js (lookup names 0)}}}{{url}}"),
+            "write!(f, \"{}{}{}\", ::rusty_handlebars::AsDisplayUrl::as_display_url(&self.link), ::rusty_handlebars::AsDisplayJs::as_display_js(&self.names[0]), ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.url))?;"
+        );
+        let err = Compiler::new(OPTIONS, make_map())
+            .compile("{{css a b}}")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("css expects 1 argument"), "{err}");
+        let rust = Compiler::new(OPTIONS, make_map())
+            .with_helper_paths(HashMap::from([(
+                "url".to_string(),
+                "crate::routes::url".to_string(),
+            )]))
+            .compile("{{url \"orders\" id}}")
+            .unwrap();
+        assert_eq!(
+            rust.code,
+            "write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&crate::routes::url(\"orders\", self.id)))?;"
+        );
+    }
+
+    fn compile_with_specs(src: &str, specs: &[&str]) -> Result<String> {
+        let mut map = make_map();
+        for spec in specs {
+            let spec = BlockSpec::intern(spec)?;
+            map.insert(spec.name(), spec);
+        }
+        Compiler::new(OPTIONS, map)
+            .compile(src)
+            .map(|rust| rust.code)
+    }
+
+    #[test]
+    fn test_block_specs() {
+        let specs = [
+            "permission: if self.user.can({arg}) { {body} } else { {else} }",
+            "first: if let Some({this}) = {arg}.first() { {body} }",
+        ];
+        assert_eq!(
+            compile_with_specs("{{#permission \"admin\"}}yes{{/permission}}", &specs).unwrap(),
+            "if self.user.can(\"admin\") { write!(f, \"yes\")?; } else {  }"
+        );
+        assert_eq!(
+            compile_with_specs(
+                "{{#permission \"admin\"}}yes{{else}}no{{/permission}}",
+                &specs
+            )
+            .unwrap(),
+            "if self.user.can(\"admin\") { write!(f, \"yes\")?; } else { write!(f, \"no\")?; }"
+        );
+        assert_eq!(
+            compile_with_specs("{{#first items}}{{name}}{{/first}}", &specs).unwrap(),
+            "if let Some(this_1) = self.items.first() { write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&this_1.name))?; }"
+        );
+        assert_eq!(
+            compile_with_specs("{{#first items as |item|}}{{item}}{{/first}}", &specs).unwrap(),
+            "if let Some(item_1) = self.items.first() { write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&item_1))?; }"
+        );
+        assert!(std::ptr::eq(
+            BlockSpec::intern(specs[0]).unwrap(),
+            BlockSpec::intern(specs[0]).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_block_spec_errors() {
+        assert!(BlockSpec::parse("no body: {arg}").is_err());
+        assert!(BlockSpec::parse("twice: {body}{body}").is_err());
+        assert!(BlockSpec::parse("order: {else}{body}").is_err());
+        let specs = ["plain: { {body} }"];
+        let err = compile_with_specs("{{#plain a}}{{/plain}}", &specs)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("unexpected token a"), "{err}");
+        let err = compile_with_specs("{{#plain}}{{else}}{{/plain}}", &specs)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("else not expected"), "{err}");
+    }
+
     #[test]
     fn test_else() {
         let rust = compile("{{#if some}}Hello{{else}}World{{/if}}");
         assert_eq!(rust, "if ::rusty_handlebars::AsBool::as_bool(&self.some){write!(f, \"Hello\")?;}else{write!(f, \"World\")?;}");
     }
 
+    #[test]
+    fn test_else_chain() {
+        let rust = compile("{{#if a}}A{{else if b}}B{{else unless c}}C{{else}}D{{/if}}");
+        assert_eq!(rust, "if ::rusty_handlebars::AsBool::as_bool(&self.a){write!(f, \"A\")?;}else if ::rusty_handlebars::AsBool::as_bool(&self.b){write!(f, \"B\")?;}else if !::rusty_handlebars::AsBool::as_bool(&self.c){write!(f, \"C\")?;}else{write!(f, \"D\")?;}");
+    }
+
+    #[test]
+    fn test_if_some_chain() {
+        let rust = compile("{{#with_ref user}}{{#if_some_ref nickname as |name|}}{{name}}{{else if_some_ref full_name as |name|}}{{name}}{{else if active}}{{/if_some_ref}}{{/with_ref}}");
+        assert_eq!(rust, "{let this_1 = &self.user;if let Some(name_2) = &this_1.nickname{write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&name_2))?;}else if let Some(name_2) = &this_1.full_name{write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&name_2))?;}else if ::rusty_handlebars::AsBool::as_bool(&this_1.active){}}");
+        // Only chained if_some branches bind the alias; the rest see the outer `v`.
+        let rust = compile("{{#if_some x as |v|}}{{v}}{{else if cond}}{{v}}{{else}}{{v}}{{/if_some}}");
+        assert_eq!(rust, "if let Some(v_1) = self.x{write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&v_1))?;}else if ::rusty_handlebars::AsBool::as_bool(&self.cond){write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.v))?;}else{write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.v))?;}");
+    }
+
+    #[test]
+    fn test_else_chain_errors() {
+        let compile = |src| Compiler::new(OPTIONS, make_map()).compile(src);
+        let each = compile("{{#each items}}{{else if a}}{{/each}}")
+            .err()
+            .unwrap();
+        assert!(each.to_string().contains("not expected in each"), "{each}");
+        let alias = compile("{{#if_some a as |x|}}{{else if_some b as |y|}}{{/if_some}}")
+            .err()
+            .unwrap();
+        assert!(alias.to_string().contains("same name"), "{alias}");
+    }
+
+    #[test]
+    fn test_match() {
+        let rust = compile(
+            "{{#match status \"Status\"}}\n  {{#case \"Shipped\" as |tracking carrier|}}{{tracking}} via {{carrier.name}}{{/case}}\n  {{#case \"Paid\"}}{{total}}{{/case}}\n{{else}}pending{{/match}}",
+        );
+        assert_eq!(rust, "match &self.status{Status::Shipped{tracking:tracking_2,carrier:carrier_2,..}=>{write!(f, \"{} via {}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&tracking_2), ::rusty_handlebars::AsDisplayHtml::as_display_html(&carrier_2.name))?;}Status::Paid{..}=>{write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.total))?;}_=>{write!(f, \"pending\")?;}}");
+        let rust =
+            compile("{{#match status}}{{#case \"crate::Status::Paid\"}}paid{{/case}}{{/match}}");
+        assert_eq!(
+            rust,
+            "match &self.status{crate::Status::Paid{..}=>{write!(f, \"paid\")?;}}"
+        );
+    }
+
+    #[test]
+    fn test_match_errors() {
+        let compile = |src| Compiler::new(OPTIONS, make_map()).compile(src);
+        let outside = compile("{{#case \"Paid\"}}{{/case}}").err().unwrap();
+        assert!(
+            outside.to_string().contains("directly inside match"),
+            "{outside}"
+        );
+        let text = compile("{{#match status}}paid{{/match}}").err().unwrap();
+        assert!(text.to_string().contains("only case arms"), "{text}");
+        let variant = compile("{{#match status}}{{#case Paid}}{{/case}}{{/match}}")
+            .err()
+            .unwrap();
+        assert!(
+            variant.to_string().contains("quoted enum path"),
+            "{variant}"
+        );
+    }
+
     #[test]
     fn test_unless() {
         let rust = compile("{{#unless some}}Hello{{/unless}}");
@@ -106,6 +304,18 @@ mod tests {
         assert_eq!(rust, "{let mut _empty_1=true;for this_1 in self.some{_empty_1=false;write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&this_1))?;}if _empty_1{write!(f, \"empty\")?;}}");
     }
 
+    #[test]
+    fn test_each_first_last_length() {
+        let rust = compile("{{#each items}}{{#unless @first}}, {{/unless}}{{this}}{{/each}}");
+        assert_eq!(rust, "for (_index_1,this_1) in ::std::iter::IntoIterator::into_iter(self.items).enumerate(){if !::rusty_handlebars::AsBool::as_bool(&(_index_1==0)){write!(f, \", \")?;}write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&this_1))?;}");
+        let rust = compile("{{#each_ref items}}{{@index}}/{{@length}}{{#if @last}}.{{/if}}{{else}}none{{/each_ref}}");
+        assert_eq!(rust, "{let mut _empty_1=true;{let mut _iter_1=::std::iter::IntoIterator::into_iter(&self.items).enumerate().peekable();let _length_1=::std::iter::ExactSizeIterator::len(&_iter_1);while let Some((_index_1,this_1))=_iter_1.next(){_empty_1=false;write!(f, \"{}/{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&_index_1), ::rusty_handlebars::AsDisplayHtml::as_display_html(&_length_1))?;if ::rusty_handlebars::AsBool::as_bool(&(_iter_1.peek().is_none())){write!(f, \".\")?;}}}if _empty_1{write!(f, \"none\")?;}}");
+        let rust = compile("{{#each items}}{{#with this}}{{@length}}{{/with}}{{/each}}");
+        assert_eq!(rust, "{let mut _iter_1=::std::iter::IntoIterator::into_iter(self.items);let _length_1=::std::iter::ExactSizeIterator::len(&_iter_1);while let Some(this_1)=_iter_1.next(){{let this_2 = this_1;write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&_length_1))?;}}}");
+        let rust = compile("{{#each outer}}{{#each inner}}{{@../last}}{{/each}}{{/each}}");
+        assert_eq!(rust, "{let mut _iter_1=::std::iter::IntoIterator::into_iter(self.outer).peekable();while let Some(this_1)=_iter_1.next(){for this_2 in this_1.inner{write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&(_iter_1.peek().is_none())))?;}}}");
+    }
+
     #[test]
     fn test_each_ref_index_and_else() {
         let rust = compile("{{#each_ref some}}{{@index}}{{else}}empty{{/each_ref}}");
@@ -227,4 +437,244 @@ mod tests {
             "write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&::rusty_handlebars::helpers::capitalize(self.name)))?;"
         );
     }
+
+    #[test]
+    fn test_hash_arguments() {
+        let compile = |src| {
+            let (path, signature) =
+                HelperSignature::split("crate::truncate(text, length = 80, ellipsis = \"...\")")
+                    .unwrap();
+            Compiler::new(OPTIONS, make_map())
+                .with_helper_paths(HashMap::from([("truncate".to_string(), path.to_string())]))
+                .with_helper_signatures(HashMap::from([(
+                    "truncate".to_string(),
+                    signature.unwrap(),
+                )]))
+                .compile(src)
+                .map(|rust| rust.code)
+        };
+        assert_eq!(
+            compile("{{truncate body ellipsis=\"…\"}}").unwrap(),
+            "write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&crate::truncate(self.body, 80, \"…\")))?;"
+        );
+        assert_eq!(
+            compile("{{truncate length=(len title) text=body}}").unwrap(),
+            "write!(f, \"{}\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&crate::truncate(self.body, len(self.title), \"...\")))?;"
+        );
+        for (src, message) in [
+            ("{{truncate body width=3}}", "has no parameter width"),
+            ("{{truncate body text=body}}", "received text twice"),
+            ("{{truncate body 1 \"…\" 2}}", "at most 3 arguments"),
+            ("{{truncate length=3}}", "missing argument text"),
+            ("{{capitalize name width=3}}", "unexpected argument width="),
+        ] {
+            let err = compile(src).err().unwrap();
+            assert!(err.to_string().contains(message), "{src}: {err}");
+            assert!(err.render("page.rhbs", src).contains("--> page.rhbs:1:1"));
+        }
+        for (src, location, message) in [
+            (
+                "{{truncate body length=}}",
+                "1:17",
+                "expected a value after `length=`",
+            ),
+            (
+                "<p>\n{{truncate body length= 3}}",
+                "2:17",
+                "expected a value after `length=`",
+            ),
+            (
+                "{{truncate (len a b=)}}",
+                "1:19",
+                "expected a value after `b=`",
+            ),
+            (
+                "{{truncate body length=ellipsis=\"…\"}}",
+                "1:17",
+                "`length=` is followed by another key instead of a value",
+            ),
+            ("{{truncate body =3}}", "1:17", "expected a key before `=`"),
+            ("{{truncate body 2x=3}}", "1:17", "`2x` is not a valid key"),
+        ] {
+            let rendered = compile(src).err().unwrap().render("page.rhbs", src);
+            assert!(
+                rendered.contains(&format!("--> page.rhbs:{location}"))
+                    && rendered.contains(message),
+                "{src}: {rendered}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_helper_signatures() {
+        let (path, signature) =
+            HelperSignature::split("crate::pad(&text, fill = ' ', width = [1, 2].len())").unwrap();
+        assert_eq!(path, "crate::pad");
+        let params = signature.unwrap();
+        let params = params
+            .params()
+            .iter()
+            .map(|param| (param.name.as_str(), param.default.as_deref(), param.by_ref))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            params,
+            [
+                ("text", None, true),
+                ("fill", Some("' '"), false),
+                ("width", Some("[1, 2].len()"), false)
+            ]
+        );
+        assert_eq!(
+            HelperSignature::split("crate::title").unwrap(),
+            ("crate::title", None)
+        );
+        assert!(HelperSignature::split("crate::pad(text").is_err());
+        assert!(HelperSignature::split("crate::pad(a, a)").is_err());
+        assert!(HelperSignature::split("crate::pad(a =)").is_err());
+    }
+
+    #[test]
+    fn test_write_macro_and_block_end() {
+        let rust = Compiler::new(OPTIONS, make_map())
+            .with_write_macro("out!")
+            .with_block_end("f.flush()?;")
+            .compile("a{{#each items}}{{#if this}}b{{/if}}{{/each}}c")
+            .unwrap();
+        assert_eq!(rust.code, "out!(f, \"a\")?;for this_1 in self.items{if ::rusty_handlebars::AsBool::as_bool(&this_1){out!(f, \"b\")?;}}f.flush()?;out!(f, \"c\")?;");
+    }
+
+    #[test]
+    fn test_partial() {
+        let rust = compile_with_partials(
+            "{{#each_ref items}}{{> \"row.rhbs\"}}{{/each_ref}}",
+            &[("row.rhbs", "<li>{{@index}} {{name}} {{../title}}</li>")],
+        )
+        .unwrap();
+        assert_eq!(rust.code, "for (_index_1,this_1) in ::std::iter::IntoIterator::into_iter(&self.items).enumerate(){write!(f, \"<li>{} {} {}</li>\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&_index_1), ::rusty_handlebars::AsDisplayHtml::as_display_html(&this_1.name), ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.title))?;}");
+    }
+
+    #[test]
+    fn test_nested_partials() {
+        let rust = compile_with_partials(
+            "{{> layout}}",
+            &[("layout", "<b>{{> name}}</b>"), ("name", "{{~name~}}")],
+        )
+        .unwrap();
+        assert_eq!(rust.code, "write!(f, \"<b>{}</b>\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.name))?;");
+    }
+
+    #[test]
+    fn test_partial_cycle() {
+        let err = compile_with_partials(
+            "{{> a}}",
+            &[("a", "{{> b}}"), ("b", "{{#if x}}{{> a}}{{/if}}")],
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("a -> b -> a"), "{err}");
+        assert!(err.to_string().contains("[partial-cycle]"), "{err}");
+    }
+
+    #[test]
+    fn test_missing_partial() {
+        let err = compile_with_partials("{{> missing}}", &[]).err().unwrap();
+        assert!(err.to_string().contains("[unresolved-partial]"), "{err}");
+        assert!(Compiler::new(OPTIONS, make_map())
+            .compile("{{> a}}")
+            .is_err());
+    }
+
+    #[test]
+    fn test_layout() {
+        let rust = compile_with_partials(
+            "{{#extends \"page\"}}{{#block title}}{{name}}{{/block}}{{#block sidebar}}side{{/block}}{{/extends}}",
+            &[
+                ("base", "<title>{{#block title}}Site{{/block}}</title>{{#block content}}empty{{/block}}"),
+                ("page", "{{#extends base}}{{#block content}}<main>{{#block sidebar}}{{/block}}</main>{{/block}}{{/extends}}"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(rust.code, "write!(f, \"<title>{}</title><main>side</main>\", ::rusty_handlebars::AsDisplayHtml::as_display_html(&self.name))?;");
+    }
+
+    #[test]
+    fn test_error_rendering() {
+        let src = "<ul>\n  {{#each items}}{{@nope}}{{/each}}\n</ul>";
+        let err = Compiler::new(OPTIONS, make_map())
+            .compile(src)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.render("list.rhbs", src),
+            "error[unknown-private-variable]: unknown private variable `@nope`\n --> list.rhbs:2:20\n  |\n2 |   {{#each items}}{{@nope}}{{/each}}\n  |                    ^^^^^"
+        );
+        let src = "{{#if a}}{{/each}}\n{{#with}}";
+        let err = Compiler::new(OPTIONS, make_map())
+            .compile(src)
+            .err()
+            .unwrap();
+        assert!(err.diagnostics().len() > 1, "{err}");
+        assert_eq!(
+            err.render("many.rhbs", src).matches("error[").count(),
+            err.diagnostics().len()
+        );
+    }
+
+    #[test]
+    fn test_compile_errors_are_located() {
+        let src = "a\n{{> \"item\"}}";
+        let err = compile_with_partials(src, &[("item", "x {{../../up}}")])
+            .err()
+            .unwrap();
+        assert_eq!(err.partial(), Some("item"));
+        assert_eq!(err.diagnostics()[0].span, Span::new(2, 14));
+        assert!(err.render("page.rhbs", src).contains("--> item:1:3"));
+        let err = Compiler::new(OPTIONS, make_map())
+            .compile("{{#if a}}\n{{lookup a}}{{/if}}")
+            .err()
+            .unwrap();
+        assert_eq!(err.diagnostics()[0].code, DiagnosticCode::InvalidExpression);
+        assert_eq!(err.diagnostics()[0].span, Span::new(10, 22));
+        // A region is compiled inside the layout but reported in the template.
+        let src = "{{#extends base}}{{#block title}}{{../../x}}{{/block}}{{/extends}}";
+        let err = compile_with_partials(src, &[("base", "<h1>{{#block title}}{{/block}}</h1>")])
+            .err()
+            .unwrap();
+        assert_eq!(err.partial(), None);
+        assert_eq!(err.diagnostics()[0].span, Span::new(33, 44));
+        let err = compile_with_partials(
+            "{{#extends base}}{{/extends}}",
+            &[("base", "<h1>\n{{../../x}}</h1>")],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.partial(), Some("base"));
+        assert_eq!(err.diagnostics()[0].span, Span::new(5, 16));
+        let err = Compiler::new(OPTIONS, make_map())
+            .compile("a\n  {{#if}}")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("at 2:6"), "{err}");
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        let unknown = compile_with_partials(
+            "{{#extends base}}{{#block missing}}x{{/block}}{{/extends}}",
+            &[("base", "{{#block title}}{{/block}}")],
+        )
+        .err()
+        .unwrap();
+        assert!(unknown.to_string().contains("block `missing`"), "{unknown}");
+        let cycle = compile_with_partials(
+            "{{#extends a}}{{/extends}}",
+            &[
+                ("a", "{{#extends b}}{{/extends}}"),
+                ("b", "{{#extends a}}{{/extends}}"),
+            ],
+        )
+        .err()
+        .unwrap();
+        assert!(cycle.to_string().contains("[partial-cycle]"), "{cycle}");
+    }
 }
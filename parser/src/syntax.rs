@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::expression_tokenizer::hash_key;
+
 /// A half-open byte range in the original template source.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct Span {
@@ -12,6 +14,10 @@ impl Span {
         Self { start, end }
     }
 
+    pub const fn len(self) -> usize {
+        self.end - self.start
+    }
+
     pub const fn is_empty(self) -> bool {
         self.start == self.end
     }
@@ -41,6 +47,10 @@ pub enum DiagnosticCode {
     UnmatchedSubexpression,
     InvalidHelperArguments,
     UnknownPrivateVariable,
+    UnresolvedPartial,
+    PartialCycle,
+    InvalidLayout,
+    InvalidExpression,
 }
 
 impl DiagnosticCode {
@@ -56,6 +66,10 @@ impl DiagnosticCode {
             Self::UnmatchedSubexpression => "unmatched-subexpression",
             Self::InvalidHelperArguments => "invalid-helper-arguments",
             Self::UnknownPrivateVariable => "unknown-private-variable",
+            Self::UnresolvedPartial => "unresolved-partial",
+            Self::PartialCycle => "partial-cycle",
+            Self::InvalidLayout => "invalid-layout",
+            Self::InvalidExpression => "invalid-expression",
         }
     }
 }
@@ -66,6 +80,24 @@ impl fmt::Display for DiagnosticCode {
     }
 }
 
+/// Built-in block helpers that require an argument.
+///
+/// Blocks declared with `BlockSpec` may take none.
+const ARGUMENT_BLOCKS: &[&str] = &[
+    "if",
+    "unless",
+    "if_some",
+    "if_some_ref",
+    "with",
+    "with_ref",
+    "each",
+    "each_ref",
+    "match",
+    "case",
+    "extends",
+    "block",
+];
+
 /// A problem found while parsing a template.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Diagnostic {
@@ -76,7 +108,7 @@ pub struct Diagnostic {
 }
 
 impl Diagnostic {
-    fn error(code: DiagnosticCode, span: Span, message: impl Into<String>) -> Self {
+    pub(crate) fn error(code: DiagnosticCode, span: Span, message: impl Into<String>) -> Self {
         Self {
             span,
             message: message.into(),
@@ -96,6 +128,8 @@ pub enum SyntaxTokenKind {
     Keyword,
     Subexpression,
     Punctuation,
+    /// The `key` of a `key=value` hash argument; the value is the next token.
+    HashKey,
 }
 
 /// A borrowed expression token with its exact source range.
@@ -106,6 +140,32 @@ pub struct SyntaxToken<'source> {
     pub span: Span,
 }
 
+impl<'source> SyntaxToken<'source> {
+    /// Returns the tokens between the parentheses of a subexpression, whose
+    /// first token names its helper. Other tokens have none.
+    pub fn subexpression_tokens(&self) -> Vec<SyntaxToken<'source>> {
+        if self.kind != SyntaxTokenKind::Subexpression {
+            return Vec::new();
+        }
+        let end = if self.text.len() > 1 && self.text.ends_with(')') {
+            self.text.len() - 1
+        } else {
+            self.text.len()
+        };
+        Parser::new(self.text, false)
+            .tokenize(Span::new(1, end))
+            .into_iter()
+            .map(|token| SyntaxToken {
+                span: Span::new(
+                    self.span.start + token.span.start,
+                    self.span.start + token.span.end,
+                ),
+                ..token
+            })
+            .collect()
+    }
+}
+
 /// One parsed template node.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Node<'source> {
@@ -127,6 +187,10 @@ pub enum NodeKind<'source> {
         tokens: Vec<SyntaxToken<'source>>,
     },
     Block(BlockNode<'source>),
+    Partial {
+        path: &'source str,
+        path_span: Span,
+    },
     RawBlock {
         name: &'source str,
         name_span: Span,
@@ -148,12 +212,43 @@ pub struct BlockNode<'source> {
     pub arguments: Vec<SyntaxToken<'source>>,
     pub alias: Option<SyntaxToken<'source>>,
     pub body: Vec<Node<'source>>,
-    pub else_span: Option<Span>,
-    pub else_body: Vec<Node<'source>>,
+    pub else_branches: Vec<ElseBranch<'source>>,
     pub close_span: Option<Span>,
 }
 
-impl BlockNode<'_> {
+/// A plain `{{else}}` or chained `{{else if value}}` branch of a block.
+///
+/// `arguments` is empty for a plain `else`. Otherwise it starts with the
+/// chained helper name, such as `if` or `unless`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElseBranch<'source> {
+    pub span: Span,
+    pub expression_span: Span,
+    pub arguments: Vec<SyntaxToken<'source>>,
+    pub body: Vec<Node<'source>>,
+}
+
+impl<'source> BlockNode<'source> {
+    /// Returns the first argument with any string quotes removed.
+    ///
+    /// This is the layout path of `{{#extends "path"}}` and the region name of
+    /// `{{#block name}}`.
+    pub fn name_argument(&self) -> Option<&'source str> {
+        self.arguments.first().map(|token| match token.kind {
+            SyntaxTokenKind::String => token.text.trim_matches('"'),
+            _ => token.text,
+        })
+    }
+
+    /// Returns the main body followed by the body of each `else` branch.
+    pub fn bodies(&self) -> impl Iterator<Item = &[Node<'source>]> {
+        std::iter::once(self.body.as_slice()).chain(
+            self.else_branches
+                .iter()
+                .map(|branch| branch.body.as_slice()),
+        )
+    }
+
     pub fn full_span(&self) -> Span {
         Span::new(
             self.open_span.start,
@@ -185,15 +280,62 @@ impl ParsedTemplate<'_> {
     }
 }
 
+/// The text at `span` in `source`, without the whitespace that an adjacent
+/// `~` in the tag before or after it removes.
+pub fn trimmed_text(source: &str, span: Span) -> &str {
+    let mut content = &source[span.start..span.end];
+    let before = &source[..span.start];
+    if before.ends_with("~}}") || before.ends_with("~}}}") || before.ends_with("~}}}}") {
+        content = content.trim_start();
+    }
+    let after = &source[span.end..];
+    if after.starts_with("{{~") || after.starts_with("{{{~") || after.starts_with("{{{{~") {
+        content = content.trim_end();
+    }
+    content
+}
+
+/// A replacement of `span` in a previous source by `len` bytes of new text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemplateEdit {
+    pub span: Span,
+    pub len: usize,
+}
+
+impl TemplateEdit {
+    /// Combines this edit with a later one whose span is in the edited source.
+    pub fn then(self, next: TemplateEdit) -> Self {
+        let end = self.span.start + self.len;
+        let start = self.span.start.min(next.span.start);
+        let old_end = if next.span.end > end {
+            self.span.end + (next.span.end - end)
+        } else {
+            self.span.end
+        };
+        let new_end = end.max(next.span.end) + next.len - next.span.len();
+        Self {
+            span: Span::new(start, old_end),
+            len: new_end - start,
+        }
+    }
+}
+
 struct Parser<'source> {
     source: &'source str,
     offset: usize,
     diagnostics: Vec<Diagnostic>,
+    validate_private: bool,
+    /// Top-level offsets where a reparse can continue with previous nodes.
+    resume: Vec<usize>,
 }
 
 enum Terminator<'source> {
     End,
-    Else(Span),
+    Resume(usize),
+    Else {
+        span: Span,
+        expression_span: Span,
+    },
     Close {
         name: &'source str,
         name_span: Span,
@@ -202,21 +344,29 @@ enum Terminator<'source> {
 }
 
 impl<'source> Parser<'source> {
-    fn new(source: &'source str) -> Self {
+    fn new(source: &'source str, validate_private: bool) -> Self {
         Self {
             source,
             offset: 0,
             diagnostics: Vec::new(),
+            validate_private,
+            resume: Vec::new(),
         }
     }
 
     fn parse(mut self) -> ParsedTemplate<'source> {
         let mut nodes = Vec::new();
+        self.parse_top_level(&mut nodes);
+        self.finish(nodes)
+    }
+
+    /// Parses top-level nodes until the end, or until a resume offset.
+    fn parse_top_level(&mut self, nodes: &mut Vec<Node<'source>>) -> Option<usize> {
         loop {
             let (parsed, terminator) = self.parse_nodes(None);
             nodes.extend(parsed);
             match terminator {
-                Terminator::Else(span) => self.diagnostics.push(Diagnostic::error(
+                Terminator::Else { span, .. } => self.diagnostics.push(Diagnostic::error(
                     DiagnosticCode::UnexpectedElse,
                     span,
                     "`else` is only valid inside a block",
@@ -228,10 +378,17 @@ impl<'source> Parser<'source> {
                     name_span,
                     format!("closing block `{name}` has no matching opening block"),
                 )),
-                Terminator::End => break,
+                Terminator::Resume(offset) => return Some(offset),
+                Terminator::End => return None,
             }
         }
-        validate_private_variables(&nodes, &mut self.diagnostics, &[]);
+    }
+
+    fn finish(mut self, nodes: Vec<Node<'source>>) -> ParsedTemplate<'source> {
+        if self.validate_private {
+            validate_private_variables(&nodes, &mut self.diagnostics, &[]);
+        }
+        validate_layout(&nodes, &mut self.diagnostics);
         ParsedTemplate {
             source: self.source,
             nodes,
@@ -254,8 +411,8 @@ impl<'source> Parser<'source> {
             let open = self.offset + relative_open;
             if open > self.offset {
                 if open > 0 && self.source.as_bytes()[open - 1] == b'\\' {
-                    self.push_text(&mut nodes, self.offset, open - 1);
                     if let Some(close) = self.source[open + 2..].find("}}") {
+                        self.push_text(&mut nodes, self.offset, open - 1);
                         let content_start = open + 2;
                         let content_end = content_start + close;
                         let end = content_end + 2;
@@ -267,6 +424,9 @@ impl<'source> Parser<'source> {
                 self.push_text(&mut nodes, self.offset, open);
             }
             self.offset = open;
+            if expected_close.is_none() && self.resume.binary_search(&open).is_ok() {
+                return (nodes, Terminator::Resume(open));
+            }
 
             if self.source[open..].starts_with("{{{{") {
                 self.parse_raw_block(&mut nodes);
@@ -317,19 +477,20 @@ impl<'source> Parser<'source> {
                 + self.source[inner_start..inner_end]
                     .len()
                     .saturating_sub(self.source[inner_start..inner_end].trim_start().len());
-            let trimmed_end = inner_end
-                - (self.source[inner_start..inner_end].len()
-                    - self.source[inner_start..inner_end].trim_end().len());
+            let trimmed_end =
+                trimmed_start + self.source[trimmed_start..inner_end].trim_end().len();
             let content = &self.source[trimmed_start..trimmed_end];
             self.offset = end;
 
             if !triple && (content.starts_with('!')) {
-                let (comment_start, comment_end) =
-                    if content.starts_with("!--") && content.ends_with("--") {
-                        (trimmed_start + 3, trimmed_end - 2)
-                    } else {
-                        (trimmed_start + 1, trimmed_end)
-                    };
+                let (comment_start, comment_end) = if content.len() >= 5
+                    && content.starts_with("!--")
+                    && content.ends_with("--")
+                {
+                    (trimmed_start + 3, trimmed_end - 2)
+                } else {
+                    (trimmed_start + 1, trimmed_end)
+                };
                 nodes.push(Node {
                     span: whole_span,
                     kind: NodeKind::Comment {
@@ -340,8 +501,23 @@ impl<'source> Parser<'source> {
                 continue;
             }
 
-            if !triple && content == "else" {
-                return (nodes, Terminator::Else(whole_span));
+            if !triple
+                && content
+                    .strip_prefix("else")
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+            {
+                let expression_start = trimmed_start + 4;
+                let leading = self.source[expression_start..trimmed_end].len()
+                    - self.source[expression_start..trimmed_end]
+                        .trim_start()
+                        .len();
+                return (
+                    nodes,
+                    Terminator::Else {
+                        span: whole_span,
+                        expression_span: Span::new(expression_start + leading, trimmed_end),
+                    },
+                );
             }
 
             if !triple && content.starts_with('/') {
@@ -356,6 +532,11 @@ impl<'source> Parser<'source> {
                 );
             }
 
+            if !triple && content.starts_with('>') {
+                nodes.push(self.parse_partial(whole_span, trimmed_start + 1, trimmed_end));
+                continue;
+            }
+
             if !triple && content.starts_with('#') {
                 let block = self.parse_block(whole_span, trimmed_start + 1, trimmed_end);
                 nodes.push(Node {
@@ -424,7 +605,7 @@ impl<'source> Parser<'source> {
                     .copied()
                     .find(|token| token.kind != SyntaxTokenKind::Punctuation)
             });
-        if arguments.is_empty() {
+        if arguments.is_empty() && ARGUMENT_BLOCKS.contains(&name) {
             self.diagnostics.push(Diagnostic::error(
                 DiagnosticCode::InvalidHelperArguments,
                 name_span,
@@ -432,37 +613,48 @@ impl<'source> Parser<'source> {
             ));
         }
 
-        let (body, terminator) = self.parse_nodes(Some(name));
-        let mut else_span = None;
-        let mut else_body = Vec::new();
-        let close = match terminator {
-            Terminator::Else(span) => {
-                else_span = Some(span);
-                let (parsed_else, else_terminator) = self.parse_nodes(Some(name));
-                else_body = parsed_else;
-                match else_terminator {
-                    Terminator::Else(duplicate) => {
+        let (body, mut terminator) = self.parse_nodes(Some(name));
+        let mut else_branches: Vec<ElseBranch<'source>> = Vec::new();
+        let close = loop {
+            match terminator {
+                Terminator::Else {
+                    span,
+                    expression_span,
+                } => {
+                    if else_branches
+                        .iter()
+                        .any(|branch| branch.arguments.is_empty())
+                    {
                         self.diagnostics.push(Diagnostic::error(
                             DiagnosticCode::DuplicateElse,
-                            duplicate,
+                            span,
                             format!("block `{name}` has more than one `else` branch"),
                         ));
-                        None
                     }
-                    Terminator::Close {
-                        name: close_name,
-                        name_span: close_name_span,
+                    let arguments = self.tokenize(expression_span);
+                    if arguments.len() == 1 {
+                        self.diagnostics.push(Diagnostic::error(
+                            DiagnosticCode::InvalidHelperArguments,
+                            arguments[0].span,
+                            format!("`else {}` expects an argument", arguments[0].text),
+                        ));
+                    }
+                    let (branch, next) = self.parse_nodes(Some(name));
+                    else_branches.push(ElseBranch {
                         span,
-                    } => self.check_close(name, close_name, close_name_span, span),
-                    Terminator::End => None,
+                        expression_span,
+                        arguments,
+                        body: branch,
+                    });
+                    terminator = next;
                 }
+                Terminator::Close {
+                    name: close_name,
+                    name_span: close_name_span,
+                    span,
+                } => break self.check_close(name, close_name, close_name_span, span),
+                Terminator::End | Terminator::Resume(_) => break None,
             }
-            Terminator::Close {
-                name: close_name,
-                name_span: close_name_span,
-                span,
-            } => self.check_close(name, close_name, close_name_span, span),
-            Terminator::End => None,
         };
 
         BlockNode {
@@ -473,12 +665,44 @@ impl<'source> Parser<'source> {
             arguments,
             alias,
             body,
-            else_span,
-            else_body,
+            else_branches,
             close_span: close,
         }
     }
 
+    fn parse_partial(&mut self, span: Span, start: usize, end: usize) -> Node<'source> {
+        let tokens = self.tokenize(Span::new(start, end));
+        let (path, path_span) = match tokens.first() {
+            Some(token) if token.kind == SyntaxTokenKind::String => {
+                // An unterminated string has no closing quote to drop.
+                let start = token.text.len() - token.text.trim_start_matches('"').len();
+                let path = token.text[start..].trim_end_matches('"');
+                let start = token.span.start + start;
+                (path, Span::new(start, start + path.len()))
+            }
+            Some(token) => (token.text, token.span),
+            None => {
+                self.diagnostics.push(Diagnostic::error(
+                    DiagnosticCode::InvalidToken,
+                    span,
+                    "partial path is missing",
+                ));
+                ("", Span::new(start, start))
+            }
+        };
+        if let Some(extra) = tokens.get(1) {
+            self.diagnostics.push(Diagnostic::error(
+                DiagnosticCode::InvalidHelperArguments,
+                Span::new(extra.span.start, end),
+                format!("partial `{path}` does not accept arguments"),
+            ));
+        }
+        Node {
+            span,
+            kind: NodeKind::Partial { path, path_span },
+        }
+    }
+
     fn check_close(
         &mut self,
         expected: &str,
@@ -612,6 +836,16 @@ impl<'source> Parser<'source> {
             };
             cursor += relative;
             let start = cursor;
+            if let Some(key_end) = hash_key(&self.source[start..span.end]).map(str::len) {
+                tokens.push(SyntaxToken {
+                    kind: SyntaxTokenKind::HashKey,
+                    text: &self.source[start..start + key_end],
+                    span: Span::new(start, start + key_end),
+                });
+                cursor = start + key_end + 1;
+                self.check_hash_value(Span::new(start, start + key_end), span.end);
+                continue;
+            }
             let (end, kind) = match character {
                 '"' => self.string_end(start, span.end),
                 '(' => self.subexpression_end(start, span.end),
@@ -623,6 +857,7 @@ impl<'source> Parser<'source> {
                 _ => {
                     let end = self.plain_token_end(start, span.end);
                     let text = &self.source[start..end];
+                    self.check_malformed_key(text, start);
                     let kind = if text == "as" || text == "else" {
                         SyntaxTokenKind::Keyword
                     } else if text.parse::<f64>().is_ok()
@@ -646,6 +881,46 @@ impl<'source> Parser<'source> {
         tokens
     }
 
+    /// Reports a `key=` hash argument at `key` that is not followed by a value.
+    fn check_hash_value(&mut self, key: Span, limit: usize) {
+        let value = &self.source[key.end + 1..limit];
+        let name = &self.source[key.start..key.end];
+        let message = if value.is_empty() || value.starts_with(char::is_whitespace) {
+            format!("expected a value after `{name}=`")
+        } else if hash_key(value).is_some() {
+            format!("`{name}=` is followed by another key instead of a value")
+        } else {
+            return;
+        };
+        self.diagnostics.push(Diagnostic::error(
+            DiagnosticCode::InvalidToken,
+            Span::new(key.start, key.end + 1),
+            message,
+        ));
+    }
+
+    /// Reports a plain token at `start` that looks like a `key=value` argument
+    /// but whose key is missing or does not start with a letter or `_`.
+    fn check_malformed_key(&mut self, text: &str, start: usize) {
+        let Some(equals) = text.find('=') else {
+            return;
+        };
+        let key = &text[..equals];
+        if text[equals..].starts_with("==") || !key.chars().all(|c| c.is_alphanumeric() || c == '_')
+        {
+            return;
+        }
+        self.diagnostics.push(Diagnostic::error(
+            DiagnosticCode::InvalidToken,
+            Span::new(start, start + equals + 1),
+            if key.is_empty() {
+                "expected a key before `=`".to_string()
+            } else {
+                format!("`{key}` is not a valid key; keys start with a letter or `_`")
+            },
+        ));
+    }
+
     fn string_end(&mut self, start: usize, limit: usize) -> (usize, SyntaxTokenKind) {
         let mut escaped = false;
         for (relative, character) in self.source[start + 1..limit].char_indices() {
@@ -667,7 +942,8 @@ impl<'source> Parser<'source> {
         let mut depth = 0usize;
         let mut in_string = false;
         let mut escaped = false;
-        for (relative, character) in self.source[start..limit].char_indices() {
+        let source = self.source;
+        for (relative, character) in source[start..limit].char_indices() {
             if in_string {
                 match character {
                     '"' if !escaped => in_string = false,
@@ -682,6 +958,9 @@ impl<'source> Parser<'source> {
                 ')' => {
                     depth = depth.saturating_sub(1);
                     if depth == 0 {
+                        // Only diagnostics are needed from the arguments here;
+                        // `subexpression_tokens` tokenizes them on demand.
+                        self.tokenize(Span::new(start + 1, start + relative));
                         return (start + relative + 1, SyntaxTokenKind::Subexpression);
                     }
                 }
@@ -733,7 +1012,10 @@ fn validate_private_variables(
                 if let Some(scope) = nested.last_mut() {
                     scope.else_branch = true;
                 }
-                validate_private_variables(&block.else_body, diagnostics, &nested);
+                for branch in &block.else_branches {
+                    validate_private_tokens(&branch.arguments, diagnostics, scopes);
+                    validate_private_variables(&branch.body, diagnostics, &nested);
+                }
             }
             _ => {}
         }
@@ -760,7 +1042,10 @@ fn validate_private_tokens(
             .rev()
             .filter(|scope| scope.each)
             .nth(parent_count);
-        let valid_name = matches!(name, "index" | "key" | "value");
+        let valid_name = matches!(
+            name,
+            "index" | "key" | "value" | "first" | "last" | "length"
+        );
         let valid_scope = scope.is_some_and(|scope| !scope.else_branch);
         if !valid_name || !valid_scope {
             diagnostics.push(Diagnostic::error(
@@ -776,9 +1061,328 @@ fn validate_private_tokens(
     }
 }
 
+fn validate_layout(nodes: &[Node<'_>], diagnostics: &mut Vec<Diagnostic>) {
+    let extends = nodes
+        .iter()
+        .filter_map(|node| match &node.kind {
+            NodeKind::Block(block) if block.name == "extends" => Some(block),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for duplicate in extends.iter().skip(1) {
+        diagnostics.push(Diagnostic::error(
+            DiagnosticCode::InvalidLayout,
+            duplicate.name_span,
+            "a template can extend only one layout",
+        ));
+    }
+    if !extends.is_empty() {
+        for node in nodes {
+            let ignored = match &node.kind {
+                NodeKind::Text(text) => !text.trim().is_empty(),
+                NodeKind::Comment { .. } => false,
+                NodeKind::Block(block) => block.name != "extends",
+                _ => true,
+            };
+            if ignored {
+                diagnostics.push(Diagnostic::error(
+                    DiagnosticCode::InvalidLayout,
+                    node.span,
+                    "content outside `extends` is not rendered",
+                ));
+            }
+        }
+    }
+    let mut regions = Vec::new();
+    validate_regions(nodes, true, &mut regions, diagnostics);
+}
+
+fn validate_regions<'source>(
+    nodes: &[Node<'source>],
+    top_level: bool,
+    regions: &mut Vec<&'source str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for node in nodes {
+        let NodeKind::Block(block) = &node.kind else {
+            continue;
+        };
+        match block.name {
+            "extends" => {
+                if !top_level {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticCode::InvalidLayout,
+                        block.name_span,
+                        "`extends` must be the outermost block of a template",
+                    ));
+                }
+                if let Some(branch) = block.else_branches.first() {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticCode::InvalidLayout,
+                        branch.span,
+                        "`extends` does not accept an `else` branch",
+                    ));
+                }
+                for child in &block.body {
+                    let region = match &child.kind {
+                        NodeKind::Text(text) => text.trim().is_empty(),
+                        NodeKind::Comment { .. } => true,
+                        NodeKind::Block(child) => child.name == "block",
+                        _ => false,
+                    };
+                    if !region {
+                        diagnostics.push(Diagnostic::error(
+                            DiagnosticCode::InvalidLayout,
+                            child.span,
+                            "only `block` regions are allowed inside `extends`",
+                        ));
+                    }
+                }
+            }
+            "block" => {
+                if let Some(name) = block.name_argument() {
+                    if regions.contains(&name) {
+                        diagnostics.push(Diagnostic::error(
+                            DiagnosticCode::InvalidLayout,
+                            block.arguments[0].span,
+                            format!("block `{name}` is declared more than once"),
+                        ));
+                    } else {
+                        regions.push(name);
+                    }
+                }
+                if let Some(branch) = block.else_branches.first() {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticCode::InvalidLayout,
+                        branch.span,
+                        "`block` does not accept an `else` branch",
+                    ));
+                }
+            }
+            _ => {}
+        }
+        for body in block.bodies() {
+            validate_regions(body, false, regions, diagnostics);
+        }
+    }
+}
+
 /// Parses a complete template and recovers useful syntax after errors.
 pub fn parse_template(source: &str) -> ParsedTemplate<'_> {
-    Parser::new(source).parse()
+    Parser::new(source, true).parse()
+}
+
+/// Parses `source`, the result of applying `edit` to the source of
+/// `previous`, reusing the top-level nodes that the edit cannot change.
+///
+/// `previous` must come from [`parse_template`] or an earlier reparse. The
+/// result equals `parse_template(source)`; an edit that does not match the two
+/// sources falls back to a full parse.
+pub fn reparse_template<'source>(
+    previous: &ParsedTemplate<'_>,
+    edit: TemplateEdit,
+    source: &'source str,
+) -> ParsedTemplate<'source> {
+    let old = previous.source;
+    if edit.span.start > edit.span.end
+        || edit.span.end > old.len()
+        || old.len() - edit.span.len() + edit.len != source.len()
+    {
+        return parse_template(source);
+    }
+    let nodes = &previous.nodes;
+    // Text before the edit can grow into it, and a node that searched past
+    // its span for a delimiter can find one in the edit, so both are parsed
+    // again along with everything after them.
+    let mut prefix = nodes.partition_point(|node| node.span.end < edit.span.start);
+    prefix = nodes[..prefix]
+        .iter()
+        .position(scanned_past_end)
+        .unwrap_or(prefix);
+    while prefix > 0 && matches!(nodes[prefix - 1].kind, NodeKind::Text(_)) {
+        prefix -= 1;
+    }
+    let start = nodes[..prefix].last().map_or(0, |node| node.span.end);
+    // A tag after the edit parses as before once the parser reaches it
+    // outside any block.
+    let suffix = nodes.partition_point(|node| node.span.start < edit.span.end);
+    let moved = |offset: usize| offset - edit.span.end + edit.span.start + edit.len;
+
+    let mut parser = Parser::new(source, true);
+    parser.offset = start;
+    parser.resume = nodes[suffix..]
+        .iter()
+        .filter(|node| !matches!(node.kind, NodeKind::Text(_)))
+        .map(|node| moved(node.span.start))
+        .collect();
+    parser.diagnostics = previous
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| !is_validation(diagnostic.code) && diagnostic.span.start < start)
+        .cloned()
+        .collect();
+    let unchanged = Rebase {
+        to: source,
+        old_start: 0,
+        new_start: 0,
+    };
+    let mut reparsed = unchanged.nodes(&nodes[..prefix]);
+    if let Some(new_start) = parser.parse_top_level(&mut reparsed) {
+        let old_start = new_start + edit.span.end - edit.span.start - edit.len;
+        let after = Rebase {
+            to: source,
+            old_start,
+            new_start,
+        };
+        let resumed = nodes.partition_point(|node| node.span.start < old_start);
+        reparsed.extend(nodes[resumed..].iter().map(|node| after.node(node)));
+        parser.diagnostics.extend(
+            previous
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| {
+                    !is_validation(diagnostic.code) && diagnostic.span.start >= old_start
+                })
+                .map(|diagnostic| Diagnostic {
+                    span: after.span(diagnostic.span),
+                    ..diagnostic.clone()
+                }),
+        );
+    }
+    parser.finish(reparsed)
+}
+
+/// Whether parsing `node` read source after its span: an unterminated
+/// expression searched on for its closing delimiter, and an unclosed block
+/// consumed the rest of the template.
+fn scanned_past_end(node: &Node<'_>) -> bool {
+    match &node.kind {
+        NodeKind::Error(_) => true,
+        NodeKind::RawBlock { close_span, .. } => close_span.is_none(),
+        NodeKind::Block(block) => {
+            block.close_span.is_none()
+                || block.bodies().any(|body| body.iter().any(scanned_past_end))
+        }
+        _ => false,
+    }
+}
+
+/// Whether a diagnostic comes from the passes over the finished tree.
+fn is_validation(code: DiagnosticCode) -> bool {
+    matches!(
+        code,
+        DiagnosticCode::UnknownPrivateVariable | DiagnosticCode::InvalidLayout
+    )
+}
+
+/// Copies nodes onto the same text in `to`, which starts at `new_start`
+/// instead of `old_start`.
+struct Rebase<'new> {
+    to: &'new str,
+    old_start: usize,
+    new_start: usize,
+}
+
+impl<'new> Rebase<'new> {
+    fn offset(&self, offset: usize) -> usize {
+        offset - self.old_start + self.new_start
+    }
+
+    fn span(&self, span: Span) -> Span {
+        Span::new(self.offset(span.start), self.offset(span.end))
+    }
+
+    /// The text in `to` at the rebased position of `span`.
+    fn text(&self, span: Span) -> &'new str {
+        let span = self.span(span);
+        &self.to[span.start..span.end]
+    }
+
+    fn token(&self, token: &SyntaxToken<'_>) -> SyntaxToken<'new> {
+        SyntaxToken {
+            kind: token.kind,
+            text: self.text(token.span),
+            span: self.span(token.span),
+        }
+    }
+
+    fn tokens(&self, tokens: &[SyntaxToken<'_>]) -> Vec<SyntaxToken<'new>> {
+        tokens.iter().map(|token| self.token(token)).collect()
+    }
+
+    fn nodes(&self, nodes: &[Node<'_>]) -> Vec<Node<'new>> {
+        nodes.iter().map(|node| self.node(node)).collect()
+    }
+
+    fn node(&self, node: &Node<'_>) -> Node<'new> {
+        let kind = match &node.kind {
+            NodeKind::Text(_) => NodeKind::Text(self.text(node.span)),
+            NodeKind::Comment { content_span, .. } => NodeKind::Comment {
+                content: self.text(*content_span),
+                content_span: self.span(*content_span),
+            },
+            NodeKind::Interpolation {
+                escaped,
+                expression_span,
+                tokens,
+            } => NodeKind::Interpolation {
+                escaped: *escaped,
+                expression_span: self.span(*expression_span),
+                tokens: self.tokens(tokens),
+            },
+            NodeKind::Block(block) => NodeKind::Block(BlockNode {
+                name: self.text(block.name_span),
+                name_span: self.span(block.name_span),
+                open_span: self.span(block.open_span),
+                expression_span: self.span(block.expression_span),
+                arguments: self.tokens(&block.arguments),
+                alias: block.alias.as_ref().map(|token| self.token(token)),
+                body: self.nodes(&block.body),
+                else_branches: block
+                    .else_branches
+                    .iter()
+                    .map(|branch| ElseBranch {
+                        span: self.span(branch.span),
+                        expression_span: self.span(branch.expression_span),
+                        arguments: self.tokens(&branch.arguments),
+                        body: self.nodes(&branch.body),
+                    })
+                    .collect(),
+                close_span: block.close_span.map(|span| self.span(span)),
+            }),
+            NodeKind::Partial { path_span, .. } => NodeKind::Partial {
+                path: self.text(*path_span),
+                path_span: self.span(*path_span),
+            },
+            NodeKind::RawBlock {
+                name_span,
+                content_span,
+                open_span,
+                close_span,
+                ..
+            } => NodeKind::RawBlock {
+                name: self.text(*name_span),
+                name_span: self.span(*name_span),
+                content: self.text(*content_span),
+                content_span: self.span(*content_span),
+                open_span: self.span(*open_span),
+                close_span: close_span.map(|span| self.span(span)),
+            },
+            NodeKind::Error(_) => NodeKind::Error(self.text(node.span)),
+        };
+        Node {
+            span: self.span(node.span),
+            kind,
+        }
+    }
+}
+
+/// Parses a partial template included with `{{> "path"}}`.
+///
+/// Private variables such as `@index` are left unvalidated because they
+/// resolve against the blocks open where the partial is included.
+pub fn parse_partial(source: &str) -> ParsedTemplate<'_> {
+    Parser::new(source, false).parse()
 }
 
 #[cfg(test)]
@@ -799,7 +1403,7 @@ mod tests {
             "each_ref"
         );
         assert_eq!(block.alias.map(|token| token.text), Some("item"));
-        assert!(block.else_span.is_some());
+        assert_eq!(block.else_branches.len(), 1);
         assert!(block.close_span.is_some());
     }
 
@@ -834,13 +1438,208 @@ mod tests {
             .any(|diagnostic| diagnostic.code == DiagnosticCode::MismatchedBlock));
     }
 
+    #[test]
+    fn tokenizes_hash_arguments() {
+        let parsed = parse_template("{{truncate body length=80 ellipsis=\"…\"}}");
+        assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+        let NodeKind::Interpolation { tokens, .. } = &parsed.nodes[0].kind else {
+            panic!("expected an interpolation");
+        };
+        let tokens = tokens
+            .iter()
+            .map(|token| (token.kind, token.text))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens[2..],
+            [
+                (SyntaxTokenKind::HashKey, "length"),
+                (SyntaxTokenKind::Number, "80"),
+                (SyntaxTokenKind::HashKey, "ellipsis"),
+                (SyntaxTokenKind::String, "\"…\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn locates_malformed_hash_arguments() {
+        let cases = [
+            ("{{foo bar=}}", "bar=", "expected a value after `bar=`"),
+            ("{{foo bar= 1}}", "bar=", "expected a value after `bar=`"),
+            ("{{foo (eq a b=)}}", "b=", "expected a value after `b=`"),
+            (
+                "{{foo bar=baz=1}}",
+                "bar=",
+                "`bar=` is followed by another key instead of a value",
+            ),
+            ("{{foo =1}}", "=", "expected a key before `=`"),
+            (
+                "{{foo 1a=1}}",
+                "1a=",
+                "`1a` is not a valid key; keys start with a letter or `_`",
+            ),
+            (
+                "{{#each a as |x| b=}}{{/each}}",
+                "b=",
+                "expected a value after `b=`",
+            ),
+            (
+                "{{#if a}}{{else if b c=}}{{/if}}",
+                "c=",
+                "expected a value after `c=`",
+            ),
+        ];
+        for (source, located, message) in cases {
+            let parsed = parse_template(source);
+            let start = source.find(located).unwrap();
+            assert_eq!(
+                parsed
+                    .diagnostics
+                    .iter()
+                    .map(|diagnostic| (
+                        diagnostic.code,
+                        diagnostic.span,
+                        diagnostic.message.as_str()
+                    ))
+                    .collect::<Vec<_>>(),
+                [(
+                    DiagnosticCode::InvalidToken,
+                    Span::new(start, start + located.len()),
+                    message
+                )],
+                "{source}"
+            );
+        }
+        assert!(parse_template("{{foo (eq a b=1) c=(d) e=\"=\" f==g}}")
+            .diagnostics
+            .is_empty());
+    }
+
+    #[test]
+    fn tokenizes_subexpressions() {
+        let source = "{{#if (and (eq a \"x)\") b)}}{{/if}}";
+        let parsed = parse_template(source);
+        let NodeKind::Block(block) = &parsed.nodes[0].kind else {
+            panic!("expected a block");
+        };
+        let inner = block.arguments[0].subexpression_tokens();
+        assert_eq!(
+            inner
+                .iter()
+                .map(|token| (token.kind, &source[token.span.start..token.span.end]))
+                .collect::<Vec<_>>(),
+            [
+                (SyntaxTokenKind::Variable, "and"),
+                (SyntaxTokenKind::Subexpression, "(eq a \"x)\")"),
+                (SyntaxTokenKind::Variable, "b"),
+            ]
+        );
+        assert_eq!(inner[1].subexpression_tokens().len(), 3);
+        assert!(inner[0].subexpression_tokens().is_empty());
+    }
+
     #[test]
     fn arbitrary_utf8_always_makes_progress() {
-        for source in ["", "{", "{{", "{{{", "é{{", "{{(((((", "{{!--", "\0{{x}}"] {
+        for source in [
+            "", "{", "{{", "{{{", "é{{", "{{(((((", "{{!--", "{{!--}}", "{{!---}}", "\0{{x}}",
+        ] {
             let _ = parse_template(source);
         }
     }
 
+    #[test]
+    fn parses_partials() {
+        let source = "{{> \"partials/header.rhbs\"}}{{~> footer.rhbs ~}}";
+        let parsed = parse_template(source);
+        assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+        let paths = parsed
+            .nodes
+            .iter()
+            .filter_map(|node| match node.kind {
+                NodeKind::Partial { path, path_span } => {
+                    assert_eq!(&source[path_span.start..path_span.end], path);
+                    Some(path)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["partials/header.rhbs", "footer.rhbs"]);
+
+        let source = "{{> \"open}}";
+        let parsed = parse_template(source);
+        let NodeKind::Partial { path, path_span } = parsed.nodes[0].kind else {
+            panic!("expected a partial");
+        };
+        assert_eq!(
+            (path, &source[path_span.start..path_span.end]),
+            ("open", "open")
+        );
+    }
+
+    #[test]
+    fn partials_defer_private_variable_validation() {
+        assert!(!parse_template("{{@index}}").diagnostics.is_empty());
+        assert!(parse_partial("{{@index}}").diagnostics.is_empty());
+    }
+
+    #[test]
+    fn parses_else_chains() {
+        let source = "{{#if a}}A{{else if b}}B{{~else unless c~}}C{{else}}D{{/if}}";
+        let parsed = parse_template(source);
+        assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+        let NodeKind::Block(block) = &parsed.nodes[0].kind else {
+            panic!("expected a block");
+        };
+        let branches = block
+            .else_branches
+            .iter()
+            .map(|branch| {
+                (
+                    &source[branch.expression_span.start..branch.expression_span.end],
+                    branch.body.len(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(branches, [("if b", 1), ("unless c", 1), ("", 1)]);
+        assert_eq!(block.bodies().count(), 4);
+
+        let invalid = parse_template("{{#if a}}{{else}}{{else if b}}{{else if}}{{/if}}");
+        let codes = invalid
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                DiagnosticCode::DuplicateElse,
+                DiagnosticCode::DuplicateElse,
+                DiagnosticCode::InvalidHelperArguments
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_layouts() {
+        let valid = parse_template(
+            "{{#extends \"base.rhbs\"}}\n  {{#block title}}Hi{{/block}}\n{{/extends}}\n",
+        );
+        assert!(valid.diagnostics.is_empty(), "{:?}", valid.diagnostics);
+        let NodeKind::Block(extends) = &valid.nodes[0].kind else {
+            panic!("expected extends");
+        };
+        assert_eq!(extends.name_argument(), Some("base.rhbs"));
+
+        let invalid = parse_template(
+            "text{{#extends base}}{{name}}{{#block a}}{{#block a}}{{/block}}{{/block}}{{/extends}}{{#if x}}{{#extends other}}{{/extends}}{{/if}}",
+        );
+        let layout_errors = invalid
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.code == DiagnosticCode::InvalidLayout)
+            .count();
+        assert_eq!(layout_errors, 5, "{:?}", invalid.diagnostics);
+    }
+
     #[test]
     fn validates_private_variable_scope() {
         let parsed =
@@ -853,5 +1652,198 @@ mod tests {
                 .count(),
             2
         );
+        let parsed = parse_template(
+            "{{#each values}}{{@first}}{{@last}}{{@length}}{{@count}}{{else}}{{@last}}{{/each}}",
+        );
+        assert_eq!(
+            parsed
+                .diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.message.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "unknown private variable `@count`",
+                "`@last` is not available in this block scope"
+            ]
+        );
+    }
+
+    #[test]
+    fn reparses_like_a_full_parse() {
+        let sources = [
+            "<p>{{title}}</p>\n{{#each items as |item|}}\n  {{item.name}} {{@index}}\n{{else}}none{{/each}}\n{{!-- note --}}{{{{raw}}}}{{x}}{{{{/raw}}}}",
+            "{{#extends \"base.rhbs\"}}{{#block body}}{{> \"row.rhbs\"}}{{/block}}{{/extends}}",
+            "a\\{{b}}c{{#if x}}{{/unless}}{{else}}{{/if}} {{oops\n{{/each}}{{y}}",
+            "{{{x}}}{{!---}}{{#with a}}{{/each}}{{b}}{{#if c}}{{else if d}}{{/if}}{{{{r}}}}{{/r}}",
+        ];
+        let insertions = [
+            "",
+            "x",
+            "{",
+            "}}",
+            "{{",
+            "\\",
+            "{{/if}}",
+            "{{#if a}}",
+            "{{!--",
+            "\n",
+            "é",
+        ];
+        for source in sources {
+            let previous = parse_template(source);
+            for start in (0..=source.len()).filter(|i| source.is_char_boundary(*i)) {
+                for removed in 0..4 {
+                    let end = start + removed;
+                    if end > source.len() || !source.is_char_boundary(end) {
+                        continue;
+                    }
+                    for insertion in insertions {
+                        let edited = format!("{}{insertion}{}", &source[..start], &source[end..]);
+                        let edit = TemplateEdit {
+                            span: Span::new(start, end),
+                            len: insertion.len(),
+                        };
+                        assert_eq!(
+                            reparse_template(&previous, edit, &edited),
+                            parse_template(&edited),
+                            "{edited:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reparses_generated_edits_like_a_full_parse() {
+        const FRAGMENTS: &[&str] = &[
+            "a",
+            "\n",
+            "\n",
+            "\\",
+            "{",
+            "}",
+            "{{",
+            "}}",
+            "{{{",
+            "}}}",
+            "{{{{",
+            "}}}}",
+            "~",
+            "!",
+            "--",
+            "#",
+            "/",
+            "x",
+            "\"",
+            "(",
+            "{{x}}",
+            "{{#if a}}",
+            "{{/if}}",
+            "{{else}}",
+            "{{!--",
+            "--}}",
+            "{{{{r}}}}",
+            "{{{{/r}}}}",
+            "{{#match v}}",
+            "{{/match}}",
+            "é",
+        ];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        for _ in 0..20000 {
+            let mut source = String::new();
+            for _ in 0..next(12) {
+                source.push_str(FRAGMENTS[next(FRAGMENTS.len())]);
+            }
+            let boundaries = (0..=source.len())
+                .filter(|offset| source.is_char_boundary(*offset))
+                .collect::<Vec<_>>();
+            let start = boundaries[next(boundaries.len())];
+            let ends = boundaries
+                .iter()
+                .copied()
+                .filter(|end| *end >= start)
+                .collect::<Vec<_>>();
+            let end = ends[next(ends.len().min(4))];
+            let mut insertion = String::new();
+            for _ in 0..next(4) {
+                insertion.push_str(FRAGMENTS[next(FRAGMENTS.len())]);
+            }
+            let edited = format!("{}{insertion}{}", &source[..start], &source[end..]);
+            let edit = TemplateEdit {
+                span: Span::new(start, end),
+                len: insertion.len(),
+            };
+            assert_eq!(
+                reparse_template(&parse_template(&source), edit, &edited),
+                parse_template(&edited),
+                "{source:?} -> {edited:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reparses_edits_that_close_earlier_nodes() {
+        for (source, start, insertion) in [
+            ("<p>{{!-- todo\n</p>\n--}", 22, "}"),
+            (
+                "}}}}\\{{\n",
+                8,
+                "{{{#match v}}{{#case A}}1{{/case}}{{/match}}",
+            ),
+        ] {
+            let edited = format!("{}{insertion}{}", &source[..start], &source[start..]);
+            let edit = TemplateEdit {
+                span: Span::new(start, start),
+                len: insertion.len(),
+            };
+            assert_eq!(
+                reparse_template(&parse_template(source), edit, &edited),
+                parse_template(&edited),
+                "{edited:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn combines_edits() {
+        let source = "{{#if a}}{{b}}{{/if}}{{c}}";
+        let first = TemplateEdit {
+            span: Span::new(5, 6),
+            len: 3,
+        };
+        let once = "{{#if abc}}{{b}}{{/if}}{{c}}";
+        let second = TemplateEdit {
+            span: Span::new(15, 16),
+            len: 0,
+        };
+        let twice = "{{#if abc}}{{b}{{/if}}{{c}}";
+        let edit = first.then(second);
+        assert_eq!(
+            edit,
+            TemplateEdit {
+                span: Span::new(5, 14),
+                len: 10
+            }
+        );
+        assert_eq!(
+            format!(
+                "{}{}{}",
+                &source[..edit.span.start],
+                &twice[edit.span.start..edit.span.start + edit.len],
+                &source[edit.span.end..]
+            ),
+            twice
+        );
+        assert_eq!(
+            reparse_template(&parse_template(once), second, twice),
+            reparse_template(&parse_template(source), edit, twice)
+        );
     }
 }
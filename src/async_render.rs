@@ -0,0 +1,135 @@
+use std::{fmt, future::Future, io};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Renders a template to a [`tokio::io::AsyncWrite`], a chunk at a time.
+///
+/// `#[derive(WithRustyHandlebars)]` implements this when the `async` feature
+/// is enabled. Each run of text and values is written as soon as it is
+/// formatted, and the writer is flushed after every top-level block and at the
+/// end, so large pages reach the client while the rest is still rendering.
+///
+/// The returned future is `Send`, as is needed to spawn it on a
+/// multi-threaded runtime or serve it from axum or hyper, so templates must be
+/// `Sync` and writers `Send`.
+pub trait AsyncRender: Sync {
+    /// Writes the rendered template to `w`.
+    fn render_async<W: AsyncWrite + Unpin + Send + ?Sized>(
+        &self,
+        w: &mut W,
+    ) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Writer used by the code generated for [`AsyncRender`].
+///
+/// Each chunk is formatted into a reused buffer before it is written, so no
+/// formatting state is held across an `.await`.
+#[doc(hidden)]
+pub struct AsyncWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    buf: String,
+}
+
+impl<'a, W: AsyncWrite + Unpin + ?Sized> AsyncWriter<'a, W> {
+    pub fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            buf: String::new(),
+        }
+    }
+
+    pub fn buffer(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+        self.buf.clear();
+        fmt::Write::write_fmt(&mut self.buf, args)
+            .map_err(|_| io::Error::other("a value failed to format"))
+    }
+
+    pub async fn write_buffer(&mut self) -> io::Result<()> {
+        self.inner.write_all(self.buf.as_bytes()).await
+    }
+
+    pub async fn write_rendered(&mut self, rendered: &str) -> io::Result<()> {
+        self.inner.write_all(rendered.as_bytes()).await?;
+        self.inner.flush().await
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+}
+
+/// `write!` for [`AsyncWriter`], used by the code generated for [`AsyncRender`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! write_async {
+    ($w:ident, $($arg:tt)*) => {{
+        let formatted = $w.buffer(::std::format_args!($($arg)*));
+        match formatted {
+            Ok(()) => $w.write_buffer().await,
+            Err(err) => Err(err),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{AsyncRender, WithRustyHandlebars};
+
+    #[derive(WithRustyHandlebars)]
+    #[template(
+        source = "<ul>{{#each items}}<li>{{this}}</li>{{/each}}</ul>{{#if done}}done{{/if}}"
+    )]
+    struct ListTemplate<'a> {
+        items: &'a [&'a str],
+        done: bool,
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    /// Renders through the trait alone, as generic callers do.
+    fn spawnable<'a, T: AsyncRender>(
+        template: &'a T,
+        out: &'a mut Vec<u8>,
+    ) -> impl Future<Output = std::io::Result<()>> + Send + 'a {
+        template.render_async(out)
+    }
+
+    #[test]
+    fn renders_like_display() {
+        let template = ListTemplate {
+            items: &["a", "<b>"],
+            done: true,
+        };
+        let mut out = Vec::new();
+        block_on(template.render_async(&mut out)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), template.to_string());
+    }
+
+    #[test]
+    fn future_is_send() {
+        let template = ListTemplate {
+            items: &[],
+            done: false,
+        };
+        let mut out = Vec::new();
+        assert_send(&template.render_async(&mut out));
+        block_on(spawnable(&template, &mut out)).unwrap();
+        assert_eq!(out, b"<ul></ul>");
+    }
+}
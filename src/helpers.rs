@@ -0,0 +1,452 @@
+//! Inline helpers for common formatting.
+//!
+//! Each helper returns an [`Adapter`] that writes straight to the formatter,
+//! so none of them allocate. Configure them by path, and the derive macro
+//! supplies their parameters:
+//!
+//! ```rust
+//! use rusty_handlebars::WithRustyHandlebars;
+//!
+//! #[derive(WithRustyHandlebars)]
+//! #[template(
+//!     source = "{{capitalize name}}: {{truncate bio length=12}}",
+//!     helpers = ["helpers::capitalize", "helpers::truncate"]
+//! )]
+//! struct Card {
+//!     name: String,
+//!     bio: String,
+//! }
+//!
+//! let card = Card {
+//!     name: "ada".to_string(),
+//!     bio: "Wrote the first <program>".to_string(),
+//! };
+//! assert_eq!(card.to_string(), "Ada: Wrote the fi…");
+//! ```
+//!
+//! `json` requires the `json` feature and `format_date` requires `chrono`.
+
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::{AsBool, AsDisplay, AsDisplayHtml};
+
+/// Text written by a helper.
+///
+/// `{{{helper value}}}` writes it unchanged and `{{helper value}}` escapes it
+/// for HTML. It also implements [`AsDisplay`], so helpers can be nested.
+pub struct Adapter<F>(F);
+
+fn adapter<F: Fn(&mut Formatter<'_>) -> fmt::Result>(write: F) -> Adapter<F> {
+    Adapter(write)
+}
+
+impl<F: Fn(&mut Formatter<'_>) -> fmt::Result> Display for Adapter<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.0)(f)
+    }
+}
+
+impl<F: Fn(&mut Formatter<'_>) -> fmt::Result> AsDisplay for Adapter<F> {
+    fn as_display(&self) -> impl Display {
+        self
+    }
+}
+
+impl<F: Fn(&mut Formatter<'_>) -> fmt::Result> AsDisplayHtml for Adapter<F> {
+    fn as_display_html(&self) -> impl Display {
+        adapter(move |f| {
+            write_chars(f, self, |f, c| match c {
+                '&' => f.write_str("&amp;"),
+                '<' => f.write_str("&lt;"),
+                '>' => f.write_str("&gt;"),
+                '"' => f.write_str("&quot;"),
+                c => f.write_char(c),
+            })
+        })
+    }
+}
+
+/// Passes each character written through it to `map`.
+struct CharWriter<'a, 'b, M> {
+    f: &'a mut Formatter<'b>,
+    map: M,
+}
+
+impl<M: FnMut(&mut Formatter<'_>, char) -> fmt::Result> Write for CharWriter<'_, '_, M> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            (self.map)(self.f, c)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_chars(
+    f: &mut Formatter<'_>,
+    value: impl Display,
+    map: impl FnMut(&mut Formatter<'_>, char) -> fmt::Result,
+) -> fmt::Result {
+    write!(CharWriter { f, map }, "{value}")
+}
+
+/// Writes `value` with its first character in upper case.
+pub fn capitalize<T: AsDisplay + ?Sized>(
+    value: &T,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + '_> {
+    adapter(move |f| {
+        let mut first = true;
+        write_chars(f, value.as_display(), |f, c| {
+            if std::mem::take(&mut first) {
+                c.to_uppercase().try_for_each(|c| f.write_char(c))
+            } else {
+                f.write_char(c)
+            }
+        })
+    })
+}
+
+/// Writes `value` in upper case.
+pub fn upper<T: AsDisplay + ?Sized>(
+    value: &T,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + '_> {
+    adapter(move |f| {
+        write_chars(f, value.as_display(), |f, c| {
+            c.to_uppercase().try_for_each(|c| f.write_char(c))
+        })
+    })
+}
+
+/// Writes `value` in lower case.
+pub fn lower<T: AsDisplay + ?Sized>(
+    value: &T,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + '_> {
+    adapter(move |f| {
+        write_chars(f, value.as_display(), |f, c| {
+            c.to_lowercase().try_for_each(|c| f.write_char(c))
+        })
+    })
+}
+
+/// Writes at most `length` characters of `value`, followed by `ellipsis` if
+/// anything was cut.
+pub fn truncate<'a, T: AsDisplay + ?Sized, E: AsDisplay + ?Sized>(
+    value: &'a T,
+    length: usize,
+    ellipsis: &'a E,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + 'a> {
+    adapter(move |f| {
+        let mut count = 0;
+        write_chars(f, value.as_display(), |f, c| {
+            count += 1;
+            if count <= length {
+                f.write_char(c)
+            } else if count == length + 1 {
+                write!(f, "{}", ellipsis.as_display())
+            } else {
+                Ok(())
+            }
+        })
+    })
+}
+
+/// Writes each of `values` with `separator` between them.
+pub fn join<'a, I, S>(
+    values: &'a I,
+    separator: &'a S,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + 'a>
+where
+    I: ?Sized,
+    &'a I: IntoIterator,
+    <&'a I as IntoIterator>::Item: AsDisplay,
+    S: AsDisplay + ?Sized,
+{
+    adapter(move |f| {
+        for (index, value) in values.into_iter().enumerate() {
+            if index > 0 {
+                write!(f, "{}", separator.as_display())?;
+            }
+            write!(f, "{}", value.as_display())?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes `value`, or `fallback` when `value` is false according to [`AsBool`].
+pub fn default<'a, T, F>(
+    value: &'a T,
+    fallback: &'a F,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + 'a>
+where
+    T: AsBool + AsDisplay + ?Sized,
+    F: AsDisplay + ?Sized,
+{
+    adapter(move |f| {
+        if value.as_bool() {
+            write!(f, "{}", value.as_display())
+        } else {
+            write!(f, "{}", fallback.as_display())
+        }
+    })
+}
+
+/// Numbers that [`pluralize`] can count with.
+pub trait Count {
+    /// Returns whether the value is exactly one.
+    fn is_one(&self) -> bool;
+}
+
+macro_rules! impl_count {
+    ($one:literal: $($t:ty),*) => {
+        $(
+            impl Count for $t {
+                fn is_one(&self) -> bool {
+                    *self == $one
+                }
+            }
+        )*
+    };
+}
+
+impl_count!(1: u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+impl_count!(1.0: f32, f64);
+
+/// Writes `singular` when `count` is one and `plural` otherwise.
+pub fn pluralize<'a, C, S, P>(
+    count: &'a C,
+    singular: &'a S,
+    plural: &'a P,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + 'a>
+where
+    C: Count + ?Sized,
+    S: AsDisplay + ?Sized,
+    P: AsDisplay + ?Sized,
+{
+    adapter(move |f| {
+        if count.is_one() {
+            write!(f, "{}", singular.as_display())
+        } else {
+            write!(f, "{}", plural.as_display())
+        }
+    })
+}
+
+/// Percent-encodes `value` for use as a URL query or path component.
+///
+/// Every byte except ASCII letters, digits, `-`, `_`, `.`, and `~` is encoded,
+/// including `/`, `&`, and `=`.
+pub fn url_encode<T: AsDisplay + ?Sized>(
+    value: &T,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + '_> {
+    adapter(move |f| {
+        write_chars(f, value.as_display(), |f, c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~') {
+                return f.write_char(c);
+            }
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                write!(f, "%{byte:02X}")?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Writes a number with `separator` between each group of three integer
+/// digits, such as `1,234,567.89`.
+pub fn number_with_separators<'a, T, S>(
+    value: &'a T,
+    separator: &'a S,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + 'a>
+where
+    T: AsDisplay + ?Sized,
+    S: AsDisplay + ?Sized,
+{
+    adapter(move |f| {
+        // Count the integer digits first, so separators can be placed while
+        // the number is written the second time.
+        let mut digits = 0usize;
+        let mut integer = true;
+        write!(
+            CharWriter {
+                f: &mut *f,
+                map: |_: &mut Formatter<'_>, c: char| {
+                    integer &= c.is_ascii_digit() || c == '-' || c == '+';
+                    if integer && c.is_ascii_digit() {
+                        digits += 1;
+                    }
+                    Ok(())
+                },
+            },
+            "{}",
+            value.as_display()
+        )?;
+        let mut integer = true;
+        write_chars(f, value.as_display(), |f, c| {
+            integer &= c.is_ascii_digit() || c == '-' || c == '+';
+            if integer && c.is_ascii_digit() {
+                f.write_char(c)?;
+                digits = digits.saturating_sub(1);
+                // `is_multiple_of` needs Rust 1.87.
+                #[allow(clippy::manual_is_multiple_of)]
+                if digits > 0 && digits % 3 == 0 {
+                    write!(f, "{}", separator.as_display())?;
+                }
+                Ok(())
+            } else {
+                f.write_char(c)
+            }
+        })
+    })
+}
+
+/// Writes `value` as JSON that is safe inside a `<script>` element.
+///
+/// `<`, `>`, and `&` are written as `\u003c`, `\u003e`, and `\u0026`. Use
+/// `{{{json value}}}` in scripts and `{{json value}}` in attributes.
+#[cfg(feature = "json")]
+pub fn json<T: serde::Serialize + ?Sized>(
+    value: &T,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + '_> {
+    struct JsonWriter<'a, 'b> {
+        f: &'a mut Formatter<'b>,
+    }
+
+    impl std::io::Write for JsonWriter<'_, '_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let text = std::str::from_utf8(buf)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+            let result = write_chars(self.f, text, |f, c| match c {
+                '<' => f.write_str("\\u003c"),
+                '>' => f.write_str("\\u003e"),
+                '&' => f.write_str("\\u0026"),
+                c => f.write_char(c),
+            });
+            result.map_err(std::io::Error::other)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    adapter(move |f| serde_json::to_writer(JsonWriter { f }, value).map_err(|_| fmt::Error))
+}
+
+/// Dates and times that [`format_date`] can write.
+#[cfg(feature = "chrono")]
+pub trait FormatDate {
+    /// Returns the value formatted with chrono's `strftime` syntax.
+    fn format_date<'a>(&self, format: &'a str) -> impl Display + 'a;
+}
+
+#[cfg(feature = "chrono")]
+macro_rules! impl_format_date {
+    ($($t:ty),*) => {
+        $(
+            impl FormatDate for $t {
+                fn format_date<'a>(&self, format: &'a str) -> impl Display + 'a {
+                    self.format(format)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "chrono")]
+impl_format_date!(chrono::NaiveDate, chrono::NaiveDateTime, chrono::NaiveTime);
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> FormatDate for chrono::DateTime<Tz>
+where
+    Tz::Offset: Display,
+{
+    fn format_date<'a>(&self, format: &'a str) -> impl Display + 'a {
+        self.format(format)
+    }
+}
+
+/// Writes a chrono date or time with a `strftime` format such as `%Y-%m-%d`.
+///
+/// An invalid format makes rendering fail with [`fmt::Error`].
+#[cfg(feature = "chrono")]
+pub fn format_date<'a, T, S>(
+    value: &'a T,
+    format: &'a S,
+) -> Adapter<impl Fn(&mut Formatter<'_>) -> fmt::Result + 'a>
+where
+    T: FormatDate + ?Sized,
+    S: AsRef<str> + ?Sized,
+{
+    adapter(move |f| write!(f, "{}", value.format_date(format.as_ref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms_case() {
+        assert_eq!(capitalize(&"élan vital").to_string(), "Élan vital");
+        assert_eq!(upper(&"straße").to_string(), "STRASSE");
+        assert_eq!(lower(&"ÀB").to_string(), "àb");
+        assert_eq!(upper(&"<b>").as_display_html().to_string(), "&lt;B&gt;");
+    }
+
+    #[test]
+    fn truncates_characters() {
+        assert_eq!(truncate(&"héllo world", 5, &"…").to_string(), "héllo…");
+        assert_eq!(truncate(&"hello", 5, &"…").to_string(), "hello");
+        assert_eq!(truncate(&upper(&"abc"), 2, &"").to_string(), "AB");
+    }
+
+    #[test]
+    fn joins_and_chooses_values() {
+        assert_eq!(join(&vec!["a", "b", "c"], &", ").to_string(), "a, b, c");
+        assert_eq!(join(&[1, 2], &"-").to_string(), "1-2");
+        assert_eq!(default(&"", &"none").to_string(), "none");
+        assert_eq!(default(&Some(3), &0).to_string(), "3");
+        assert_eq!(pluralize(&1usize, &"item", &"items").to_string(), "item");
+        assert_eq!(pluralize(&2.5, &"item", &"items").to_string(), "items");
+        assert_eq!(pluralize(&1i8, &"item", &"items").to_string(), "item");
+        assert_eq!(pluralize(&-1i8, &"item", &"items").to_string(), "items");
+    }
+
+    #[test]
+    fn encodes_urls() {
+        assert_eq!(
+            url_encode(&"a b/c?d=é").to_string(),
+            "a%20b%2Fc%3Fd%3D%C3%A9"
+        );
+    }
+
+    #[test]
+    fn separates_number_groups() {
+        assert_eq!(
+            number_with_separators(&1234567, &",").to_string(),
+            "1,234,567"
+        );
+        assert_eq!(
+            number_with_separators(&-1234.5, &" ").to_string(),
+            "-1 234.5"
+        );
+        assert_eq!(number_with_separators(&255u8, &",").to_string(), "255");
+        assert_eq!(number_with_separators(&0, &",").to_string(), "0");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn writes_script_safe_json() {
+        assert_eq!(
+            json(&["</script>", "a&b"]).to_string(),
+            r#"["\u003c/script\u003e","a\u0026b"]"#
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn formats_dates() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 9).unwrap();
+        assert_eq!(format_date(&date, &"%d/%m/%Y").to_string(), "09/03/2024");
+    }
+}
@@ -0,0 +1,666 @@
+//! Runtime rendering of template files for the `hot-reload` feature.
+//!
+//! In builds with `debug_assertions`, derived templates read their file on
+//! every render and interpret it against the fields exposed by [`Reflect`], so
+//! template edits show up without recompiling. Release builds always use the
+//! compiled template.
+//!
+//! The interpreter covers text, values, `if`, `unless`, `if_some`, `with`,
+//! `each`, partials, and the `and`, `or`, and `not` helpers. Templates that
+//! use anything else, or read a field that does not implement [`Reflect`], are
+//! rendered by the compiled code instead, with a warning logged through `log`.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use rusty_handlebars_parser::{
+    parse_partial, parse_template, trimmed_text, BlockNode, Node, NodeKind, ParsedTemplate,
+    Severity, SyntaxToken, SyntaxTokenKind,
+};
+
+use crate::{reflect::Item, DisplayHtml, Reflect};
+
+/// Partials nested deeper than this are reported as a cycle.
+const MAX_PARTIAL_DEPTH: usize = 32;
+
+/// Renders the template file at `path`, reading partials relative to `root`.
+///
+/// `escape` selects HTML escaping for `{{value}}` and `minify` minifies the
+/// files as the derive does. Returns `None` after logging a warning if the
+/// file cannot be read or uses something the interpreter does not support.
+#[doc(hidden)]
+pub fn render(
+    path: &str,
+    root: &str,
+    escape: bool,
+    minify: bool,
+    context: &dyn Reflect,
+) -> Option<String> {
+    let mut interpreter = Interpreter {
+        root: Path::new(root),
+        escape,
+        minify,
+        frames: vec![Frame {
+            local: Local::This(Value::Ref(context)),
+            each: None,
+        }],
+        partial_depth: 0,
+        out: String::new(),
+    };
+    let result = interpreter
+        .read(Path::new(path))
+        .map_err(|err| Error::Unsupported(format!("unable to read the file, {err}")))
+        .and_then(|source| {
+            let parsed = parse_template(&source);
+            interpreter.render_parsed(&parsed)
+        });
+    match result {
+        Ok(()) => Some(interpreter.out),
+        Err(Error::Fmt) => None,
+        Err(Error::Unsupported(reason)) => {
+            warn_once(path, reason);
+            None
+        }
+    }
+}
+
+/// Logs why the template at `path` fell back to compiled code, once per
+/// template until the reason changes, so a template rendered in a loop does
+/// not flood the log.
+///
+/// Only the last reason is kept for each path, so the memory used is bounded
+/// by the number of templates.
+fn warn_once(path: &str, reason: String) {
+    static WARNED: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+    let mut warned = WARNED.lock().unwrap_or_else(|err| err.into_inner());
+    let warned = warned.get_or_insert_with(HashMap::new);
+    if warned.get(path) != Some(&reason) {
+        log::warn!("rusty-handlebars: {path}: {reason}; using the compiled template");
+        warned.insert(path.to_string(), reason);
+    }
+}
+
+enum Error {
+    /// A value's `Display` implementation failed.
+    Fmt,
+    /// The template needs compiled code.
+    Unsupported(String),
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Self::Fmt
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn unsupported<T>(reason: impl Into<String>) -> Result<T> {
+    Err(Error::Unsupported(reason.into()))
+}
+
+#[derive(Clone)]
+enum Value<'c> {
+    Ref(&'c dyn Reflect),
+    Text(String),
+    Number(String),
+    Bool(bool),
+    Nothing,
+}
+
+impl Value<'_> {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Ref(value) => value.truthy(),
+            Value::Text(text) => !text.is_empty(),
+            Value::Number(number) => number.parse::<f64>().is_ok_and(|number| number != 0.0),
+            Value::Bool(value) => *value,
+            Value::Nothing => false,
+        }
+    }
+}
+
+/// The binding a block introduces, mirroring the compiler's scopes.
+enum Local<'c> {
+    None,
+    This(Value<'c>),
+    As(String, Value<'c>),
+}
+
+struct EachState<'c> {
+    index: usize,
+    length: usize,
+    item: Item<'c>,
+}
+
+struct Frame<'c> {
+    local: Local<'c>,
+    each: Option<EachState<'c>>,
+}
+
+impl<'c> Frame<'c> {
+    fn new(local: Local<'c>) -> Self {
+        Self { local, each: None }
+    }
+}
+
+struct Interpreter<'r, 'c> {
+    root: &'r Path,
+    escape: bool,
+    minify: bool,
+    frames: Vec<Frame<'c>>,
+    partial_depth: usize,
+    out: String,
+}
+
+impl<'c> Interpreter<'_, 'c> {
+    fn read(&self, path: &Path) -> std::io::Result<String> {
+        let source = std::fs::read_to_string(path)?;
+        Ok(if self.minify { minify(&source) } else { source })
+    }
+
+    fn render_parsed(&mut self, parsed: &ParsedTemplate<'_>) -> Result<()> {
+        let error = parsed
+            .diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.severity == Severity::Error);
+        if let Some(diagnostic) = error {
+            let line = parsed.source[..diagnostic.span.start]
+                .lines()
+                .count()
+                .max(1);
+            return unsupported(format!("line {line}: {}", diagnostic.message));
+        }
+        self.render_nodes(parsed.source, &parsed.nodes)
+    }
+
+    /// Renders `nodes` parsed from `source`, which `~` trimming looks at.
+    fn render_nodes(&mut self, source: &str, nodes: &[Node<'_>]) -> Result<()> {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Text(_) => self.out.push_str(trimmed_text(source, node.span)),
+                NodeKind::Comment { .. } => {}
+                NodeKind::RawBlock { content_span, .. } => {
+                    self.out.push_str(trimmed_text(source, *content_span))
+                }
+                NodeKind::Interpolation {
+                    escaped, tokens, ..
+                } => {
+                    let value = self.evaluate(tokens)?;
+                    self.write(&value, *escaped, tokens)?;
+                }
+                NodeKind::Block(block) => self.render_block(source, block)?,
+                NodeKind::Partial { path, .. } => self.render_partial(path)?,
+                NodeKind::Error(text) => return unsupported(format!("invalid syntax {text}")),
+            }
+        }
+        Ok(())
+    }
+
+    fn render_partial(&mut self, path: &str) -> Result<()> {
+        if self.partial_depth == MAX_PARTIAL_DEPTH {
+            return unsupported(format!("partial {path} includes itself"));
+        }
+        let source = self
+            .read(&self.root.join(path))
+            .map_err(|err| Error::Unsupported(format!("unable to read partial {path}, {err}")))?;
+        self.partial_depth += 1;
+        let result = self.render_parsed(&parse_partial(&source));
+        self.partial_depth -= 1;
+        result
+    }
+
+    fn write(
+        &mut self,
+        value: &Value<'c>,
+        escaped: bool,
+        tokens: &[SyntaxToken<'_>],
+    ) -> Result<()> {
+        let escape = escaped && self.escape;
+        match value {
+            Value::Ref(value) => match value.write(&mut self.out, escape) {
+                Some(result) => Ok(result?),
+                None => unsupported(format!("{} cannot be written at runtime", text(tokens))),
+            },
+            Value::Text(value) if escape => {
+                Ok(write!(self.out, "{}", DisplayHtml { string: value })?)
+            }
+            Value::Text(value) | Value::Number(value) => {
+                self.out.push_str(value);
+                Ok(())
+            }
+            Value::Bool(value) => Ok(write!(self.out, "{value}")?),
+            Value::Nothing => Ok(()),
+        }
+    }
+
+    fn render_block(&mut self, source: &str, block: &BlockNode<'_>) -> Result<()> {
+        let arguments = match block.arguments.iter().position(|token| token.text == "as") {
+            Some(position) => &block.arguments[..position],
+            None => &block.arguments[..],
+        };
+        let alias = block.alias.map(|token| token.text.trim_matches('|'));
+        match block.name {
+            "if" | "unless" => {
+                let condition = self.evaluate(arguments)?.truthy() == (block.name == "if");
+                self.render_branches(source, block, condition.then_some(Local::None))
+            }
+            "if_some" | "if_some_ref" => {
+                let local = self.unwrap_option(arguments, alias)?;
+                self.render_branches(source, block, local)
+            }
+            "with" | "with_ref" => {
+                if !block.else_branches.is_empty() {
+                    return unsupported("else inside with");
+                }
+                let value = self.evaluate(arguments)?;
+                self.render_body(source, &block.body, bind(alias, value))
+            }
+            "each" | "each_ref" => self.render_each(source, block, arguments, alias),
+            name => unsupported(format!("the {name} block")),
+        }
+    }
+
+    /// Renders the body with `local` bound, or else the first matching else
+    /// branch when `local` is `None`.
+    fn render_branches(
+        &mut self,
+        source: &str,
+        block: &BlockNode<'_>,
+        local: Option<Local<'c>>,
+    ) -> Result<()> {
+        if let Some(local) = local {
+            return self.render_body(source, &block.body, local);
+        }
+        for branch in &block.else_branches {
+            let local = match branch.arguments.split_first() {
+                None => Some(Local::None),
+                Some((helper, arguments)) => match helper.text {
+                    "if" => self.evaluate(arguments)?.truthy().then_some(Local::None),
+                    "unless" => (!self.evaluate(arguments)?.truthy()).then_some(Local::None),
+                    "if_some" | "if_some_ref" => {
+                        let (arguments, alias) = match arguments.iter().position(|t| t.text == "as")
+                        {
+                            Some(position) => (
+                                &arguments[..position],
+                                arguments[position + 1..]
+                                    .iter()
+                                    .find(|token| token.kind != SyntaxTokenKind::Punctuation)
+                                    .map(|token| token.text.trim_matches('|')),
+                            ),
+                            None => (arguments, None),
+                        };
+                        self.unwrap_option(arguments, alias)?
+                    }
+                    name => return unsupported(format!("else {name}")),
+                },
+            };
+            if let Some(local) = local {
+                return self.render_body(source, &branch.body, local);
+            }
+        }
+        Ok(())
+    }
+
+    fn unwrap_option(
+        &self,
+        arguments: &[SyntaxToken<'_>],
+        alias: Option<&str>,
+    ) -> Result<Option<Local<'c>>> {
+        match self.evaluate(arguments)? {
+            Value::Ref(value) => match value.option() {
+                Some(inner) => Ok(inner.map(|inner| bind(alias, Value::Ref(inner)))),
+                None => unsupported(format!("{} is not an Option", text(arguments))),
+            },
+            Value::Nothing => Ok(None),
+            _ => unsupported(format!("{} is not an Option", text(arguments))),
+        }
+    }
+
+    fn render_each(
+        &mut self,
+        source: &str,
+        block: &BlockNode<'_>,
+        arguments: &[SyntaxToken<'_>],
+        alias: Option<&str>,
+    ) -> Result<()> {
+        let items = match self.evaluate(arguments)? {
+            Value::Ref(value) => value.items(),
+            _ => None,
+        };
+        let Some(items) = items else {
+            return unsupported(format!("{} cannot be iterated at runtime", text(arguments)));
+        };
+        if items.is_empty() {
+            return match block.else_branches.first() {
+                Some(branch) if branch.arguments.is_empty() => {
+                    self.render_body(source, &branch.body, Local::None)
+                }
+                Some(_) => unsupported("else with arguments inside each"),
+                None => Ok(()),
+            };
+        }
+        let length = items.len();
+        for (index, item) in items.into_iter().enumerate() {
+            self.frames.push(Frame {
+                local: bind(alias, Value::Ref(item.value)),
+                each: Some(EachState {
+                    index,
+                    length,
+                    item,
+                }),
+            });
+            let result = self.render_nodes(source, &block.body);
+            self.frames.pop();
+            result?;
+        }
+        Ok(())
+    }
+
+    fn render_body(&mut self, source: &str, body: &[Node<'_>], local: Local<'c>) -> Result<()> {
+        self.frames.push(Frame::new(local));
+        let result = self.render_nodes(source, body);
+        self.frames.pop();
+        result
+    }
+
+    /// Evaluates a value or an `and`, `or`, or `not` helper call.
+    fn evaluate(&self, tokens: &[SyntaxToken<'_>]) -> Result<Value<'c>> {
+        match tokens {
+            [] => unsupported("an empty expression"),
+            [token] => self.token(token),
+            [helper, arguments @ ..] => match helper.text {
+                "and" => {
+                    for argument in arguments {
+                        if !self.token(argument)?.truthy() {
+                            return Ok(Value::Bool(false));
+                        }
+                    }
+                    Ok(Value::Bool(true))
+                }
+                "or" => {
+                    for argument in arguments {
+                        if self.token(argument)?.truthy() {
+                            return Ok(Value::Bool(true));
+                        }
+                    }
+                    Ok(Value::Bool(false))
+                }
+                "not" if arguments.len() == 1 => {
+                    Ok(Value::Bool(!self.token(&arguments[0])?.truthy()))
+                }
+                name => unsupported(format!("the {name} helper")),
+            },
+        }
+    }
+
+    fn token(&self, token: &SyntaxToken<'_>) -> Result<Value<'c>> {
+        match token.kind {
+            SyntaxTokenKind::Variable => self.variable(token.text),
+            SyntaxTokenKind::PrivateVariable => self.private(&token.text[1..]),
+            SyntaxTokenKind::String => Ok(Value::Text(unquote(token.text))),
+            SyntaxTokenKind::Number => Ok(match token.text {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "None" => Value::Nothing,
+                number => Value::Number(number.to_string()),
+            }),
+            _ => unsupported(format!("the expression {}", token.text)),
+        }
+    }
+
+    fn variable(&self, var: &str) -> Result<Value<'c>> {
+        let mut depth = self.frames.len() - 1;
+        let mut local = var;
+        while let Some(parent) = local.strip_prefix("../") {
+            if depth == 0 {
+                return unsupported(format!("{var} goes above the template"));
+            }
+            depth -= 1;
+            local = parent;
+        }
+        self.resolve(var, local, depth)
+    }
+
+    fn resolve(&self, var: &str, local: &str, depth: usize) -> Result<Value<'c>> {
+        let (value, path) = match &self.frames[depth].local {
+            Local::This(value) => match local {
+                "this" => (value, None),
+                _ => (value, Some(local.strip_prefix("this.").unwrap_or(local))),
+            },
+            Local::As(name, value) if local == name => (value, None),
+            Local::As(name, value)
+                if local.starts_with(name.as_str()) && local[name.len()..].starts_with('.') =>
+            {
+                (value, Some(&local[name.len() + 1..]))
+            }
+            _ if depth == 0 => return unsupported(format!("{var} is not defined")),
+            _ => return self.resolve(var, local, depth - 1),
+        };
+        let Some(path) = path else {
+            return Ok(value.clone());
+        };
+        let &Value::Ref(mut value) = value else {
+            return unsupported(format!("{var} has no fields"));
+        };
+        for field in path.split('.') {
+            value = value.field(field).ok_or_else(|| {
+                Error::Unsupported(format!("{var} has no field {field} readable at runtime"))
+            })?;
+        }
+        Ok(Value::Ref(value))
+    }
+
+    fn private(&self, var: &str) -> Result<Value<'c>> {
+        let mut local = var;
+        let mut parents = 0;
+        while let Some(parent) = local.strip_prefix("../") {
+            local = parent;
+            parents += 1;
+        }
+        let Some(each) = self
+            .frames
+            .iter()
+            .rev()
+            .filter_map(|frame| frame.each.as_ref())
+            .nth(parents)
+        else {
+            return unsupported(format!("@{var} outside each"));
+        };
+        Ok(match local {
+            "index" => Value::Number(each.index.to_string()),
+            "first" => Value::Bool(each.index == 0),
+            "last" => Value::Bool(each.index + 1 == each.length),
+            "length" => Value::Number(each.length.to_string()),
+            "key" => match each.item.key {
+                Some(key) => Value::Ref(key),
+                None => return unsupported("@key outside a map"),
+            },
+            "value" => Value::Ref(each.item.value),
+            _ => return unsupported(format!("@{var}")),
+        })
+    }
+}
+
+#[cfg(feature = "minify-html")]
+fn minify(source: &str) -> String {
+    rusty_handlebars_parser::build_helper::minify_template(source)
+}
+
+#[cfg(not(feature = "minify-html"))]
+fn minify(source: &str) -> String {
+    source.to_string()
+}
+
+fn bind<'c>(alias: Option<&str>, value: Value<'c>) -> Local<'c> {
+    match alias {
+        Some(alias) => Local::As(alias.to_string(), value),
+        None => Local::This(value),
+    }
+}
+
+fn text(tokens: &[SyntaxToken<'_>]) -> String {
+    tokens
+        .iter()
+        .map(|token| token.text)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Removes the quotes and backslash escapes of a string literal.
+fn unquote(literal: &str) -> String {
+    let mut text = String::new();
+    let mut chars = literal.trim_matches('"').chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(c) => text.push(c),
+                None => {}
+            },
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::render;
+    use crate::{Reflect, WithRustyHandlebars};
+
+    #[derive(Reflect)]
+    struct Page {
+        title: &'static str,
+        items: Vec<Item>,
+        note: Option<&'static str>,
+    }
+
+    #[derive(Reflect)]
+    struct Item {
+        name: &'static str,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(
+        source = "<ul>\n  {{~#each_ref items~}}\n    <li> {{~name~}} </li>\n  {{~/each_ref~}}\n</ul> {{{~title~}}} .",
+        minify = false
+    )]
+    struct Trimmed {
+        title: &'static str,
+        items: Vec<Item>,
+    }
+
+    fn page() -> Page {
+        Page {
+            title: "<Tools>",
+            items: vec![Item { name: "saw" }, Item { name: "plane" }],
+            note: None,
+        }
+    }
+
+    /// Writes `files` to a fresh directory and returns its path.
+    fn root(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("rusty-handlebars-{test}-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for (name, source) in files {
+            fs::write(root.join(name), source).unwrap();
+        }
+        root
+    }
+
+    fn render_file(root: &Path, name: &str) -> Option<String> {
+        render(
+            root.join(name).to_str().unwrap(),
+            root.to_str().unwrap(),
+            true,
+            false,
+            &page(),
+        )
+    }
+
+    #[test]
+    fn rerenders_edited_files() {
+        let root = root(
+            "edits",
+            &[
+                (
+                    "page.rhbs",
+                    "<h1>{{title}}</h1>{{#each items}}{{#unless @first}}, {{/unless}}{{> \"item.rhbs\"}}{{/each}}",
+                ),
+                ("item.rhbs", "{{@index}}:{{name}}"),
+            ],
+        );
+        assert_eq!(
+            render_file(&root, "page.rhbs").unwrap(),
+            "<h1>&lt;Tools&gt;</h1>0:saw, 1:plane"
+        );
+        fs::write(
+            root.join("page.rhbs"),
+            "{{{title}}} ({{items.length}}){{#if_some note as |n|}}{{n}}{{else}} none{{/if_some}}",
+        )
+        .unwrap();
+        assert_eq!(
+            render_file(&root, "page.rhbs"),
+            None,
+            "items has no length field"
+        );
+        fs::write(
+            root.join("page.rhbs"),
+            "{{{title}}}{{#if_some note as |n|}}{{n}}{{else}} none{{/if_some}}",
+        )
+        .unwrap();
+        assert_eq!(render_file(&root, "page.rhbs").unwrap(), "<Tools> none");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn trims_whitespace_like_the_compiled_template() {
+        let root = root(
+            "trim",
+            &[(
+                "trim.rhbs",
+                "<ul>\n  {{~#each_ref items~}}\n    <li> {{~name~}} </li>\n  {{~/each_ref~}}\n</ul> {{{~title~}}} .",
+            )],
+        );
+        let trimmed = Trimmed {
+            title: "<Tools>",
+            items: page().items,
+        };
+        let rendered = render(
+            root.join("trim.rhbs").to_str().unwrap(),
+            root.to_str().unwrap(),
+            true,
+            false,
+            &trimmed,
+        );
+        assert_eq!(rendered.unwrap(), trimmed.to_string());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn falls_back_for_compiled_features() {
+        let root = root(
+            "fallback",
+            &[
+                ("helper.rhbs", "{{upper title}}"),
+                ("broken.rhbs", "{{#if title}}"),
+            ],
+        );
+        assert_eq!(render_file(&root, "helper.rhbs"), None);
+        assert_eq!(render_file(&root, "broken.rhbs"), None);
+        assert_eq!(render_file(&root, "missing.rhbs"), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
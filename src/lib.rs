@@ -21,8 +21,10 @@
 //!
 //! `{{value}}` uses [`AsDisplayHtml`]. `{{{value}}}` uses [`AsDisplay`] and
 //! writes its result unchanged. The string implementation of [`AsDisplayHtml`]
-//! escapes `&`, `<`, `>`, and `"`, but is not contextual escaping for URLs,
-//! JavaScript, CSS, or unquoted attributes.
+//! escapes `&`, `<`, `>`, and `"`. For URLs, JavaScript, unquoted attributes,
+//! and CSS, `{{url value}}`, `{{js value}}`, `{{attr value}}`, and
+//! `{{css value}}` use [`AsDisplayUrl`], [`AsDisplayJs`], [`AsDisplayAttr`],
+//! and [`AsDisplayCss`].
 //!
 //! ```rust
 //! use rusty_handlebars::AsDisplayHtml;
@@ -33,8 +35,11 @@
 //! );
 //! ```
 //!
-//! This is a Rust-oriented source generator, not a runtime Handlebars
-//! interpreter. See the project README for the supported template syntax.
+//! This is a Rust-oriented source generator rather than a runtime Handlebars
+//! interpreter. The exception is the `hot-reload` feature, which interprets
+//! template files in debug builds so edits show up without recompiling; see
+//! the `hot_reload` module. See the project README for the supported template
+//! syntax.
 
 extern crate self as rusty_handlebars;
 
@@ -43,17 +48,65 @@ use std::fmt::Display;
 pub mod as_bool;
 pub use as_bool::AsBool;
 
+pub mod helpers;
+
+#[cfg(feature = "async")]
+mod async_render;
+#[cfg(feature = "async")]
+pub use async_render::{AsyncRender, AsyncWriter};
+/// Re-exported for the bounds of [`AsyncRender::render_async`].
+#[cfg(feature = "async")]
+pub use tokio::io::AsyncWrite;
+
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+#[cfg(feature = "hot-reload")]
+pub mod reflect;
+#[cfg(feature = "hot-reload")]
+pub use reflect::Reflect;
+/// Derives [`Reflect`], exposing a struct's fields to hot-reloaded templates.
+#[cfg(feature = "hot-reload")]
+pub use rusty_handlebars_derive::Reflect;
+
+#[cfg(any(feature = "axum", feature = "actix-web", feature = "warp"))]
+pub mod web;
+
 /// Derives a template-backed [`Display`] implementation.
 ///
-/// The required `template` attribute accepts `path`, plus optional `minify`
-/// and `helpers` arguments. See the derive crate documentation for details.
+/// The required `template` attribute accepts either `path` or an inline
+/// `source`, plus optional `minify`, `escape`, `helpers`, and `blocks`
+/// arguments. See the derive crate documentation for details.
 pub use rusty_handlebars_derive::WithRustyHandlebars;
 
 #[cfg(feature = "parser")]
 pub use rusty_handlebars_parser::{Compiler, Options};
 
-/// Marker implemented by [`WithRustyHandlebars`] for generated renderers.
-pub trait WithRustyHandlebars: Display {}
+/// Rendering methods implemented by [`WithRustyHandlebars`].
+///
+/// The [`Display`] implementation writes through a [`std::fmt::Formatter`].
+/// The methods here write the same output straight to other targets. The
+/// trait is object safe; through `&dyn WithRustyHandlebars`, every method but
+/// the generic `render_to` is available.
+pub trait WithRustyHandlebars: Display {
+    /// Writes the rendered template to `w`.
+    fn render_to<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()>
+    where
+        Self: Sized;
+
+    /// Appends the rendered template to `buf`.
+    ///
+    /// Returns the error of a value whose `Display` implementation fails,
+    /// leaving the output written before it in `buf`.
+    fn render_into(&self, buf: &mut String) -> std::fmt::Result;
+
+    /// Returns an estimate of the rendered length in bytes, for preallocating
+    /// buffers.
+    ///
+    /// The estimate is the length of the template's static text. Interpolated
+    /// values are not counted, loop bodies are counted once, and every branch
+    /// of a conditional is counted, so the output may be shorter or longer.
+    fn size_hint(&self) -> usize;
+}
 
 macro_rules! impl_as_display {
     ($($t:ty),*) => {
@@ -209,6 +262,229 @@ impl<T: AsDisplayHtml> AsDisplayHtml for Box<T> {
     }
 }
 
+/// Supplies the value written by `{{url value}}`.
+///
+/// String implementations percent-encode every byte except ASCII letters,
+/// digits, `-`, `_`, `.`, `~`, and `/`. Use it for URL paths and query values.
+pub trait AsDisplayUrl {
+    /// Returns the value to write for `{{url value}}`.
+    fn as_display_url(&self) -> impl Display;
+}
+
+/// Supplies the value written by `{{js value}}`.
+///
+/// String implementations escape everything except ASCII letters, digits,
+/// spaces, `_`, `.`, `,`, `-`, and non-ASCII text as `\uXXXX`, so the result is
+/// safe inside a quoted JavaScript string in a `<script>` element or event
+/// handler attribute.
+pub trait AsDisplayJs {
+    /// Returns the value to write for `{{js value}}`.
+    fn as_display_js(&self) -> impl Display;
+}
+
+/// Supplies the value written by `{{attr value}}`.
+///
+/// String implementations encode every ASCII character except letters,
+/// digits, `-`, `_`, `.`, and `,` as `&#xHH;`, so the result is safe in quoted
+/// and unquoted HTML attribute values.
+pub trait AsDisplayAttr {
+    /// Returns the value to write for `{{attr value}}`.
+    fn as_display_attr(&self) -> impl Display;
+}
+
+/// Supplies the value written by `{{css value}}`.
+///
+/// String implementations escape every ASCII character except letters and
+/// digits as `\HH `, so the result is safe in CSS strings and property values.
+pub trait AsDisplayCss {
+    /// Returns the value to write for `{{css value}}`.
+    fn as_display_css(&self) -> impl Display;
+}
+
+fn write_escaped(
+    f: &mut std::fmt::Formatter<'_>,
+    string: &str,
+    keep: impl Fn(char) -> bool,
+    escape: impl Fn(&mut std::fmt::Formatter<'_>, char) -> std::fmt::Result,
+) -> std::fmt::Result {
+    let mut start = 0;
+    for (index, c) in string.char_indices() {
+        if keep(c) {
+            continue;
+        }
+        if start < index {
+            f.write_str(&string[start..index])?;
+        }
+        escape(f, c)?;
+        start = index + c.len_utf8();
+    }
+    if start < string.len() {
+        f.write_str(&string[start..])?;
+    }
+    Ok(())
+}
+
+struct DisplayUrl<'a> {
+    string: &'a str,
+}
+
+impl Display for DisplayUrl<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_escaped(
+            f,
+            self.string,
+            |c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~' | '/'),
+            |f, c| {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    write!(f, "%{byte:02X}")?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+struct DisplayJs<'a> {
+    string: &'a str,
+}
+
+impl Display for DisplayJs<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_escaped(
+            f,
+            self.string,
+            |c| {
+                c.is_ascii_alphanumeric()
+                    || matches!(c, ' ' | '_' | '.' | ',' | '-')
+                    || (!c.is_ascii() && !matches!(c, '\u{2028}' | '\u{2029}'))
+            },
+            |f, c| write!(f, "\\u{:04X}", c as u32),
+        )
+    }
+}
+
+struct DisplayAttr<'a> {
+    string: &'a str,
+}
+
+impl Display for DisplayAttr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_escaped(
+            f,
+            self.string,
+            |c| !c.is_ascii() || c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ','),
+            |f, c| write!(f, "&#x{:X};", c as u32),
+        )
+    }
+}
+
+struct DisplayCss<'a> {
+    string: &'a str,
+}
+
+impl Display for DisplayCss<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_escaped(
+            f,
+            self.string,
+            |c| !c.is_ascii() || c.is_ascii_alphanumeric(),
+            |f, c| write!(f, "\\{:X} ", c as u32),
+        )
+    }
+}
+
+struct DisplayOptionWith<D> {
+    value: Option<D>,
+}
+
+impl<D: Display> Display for DisplayOptionWith<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => Display::fmt(value, f),
+            None => Ok(()),
+        }
+    }
+}
+
+macro_rules! impl_contextual {
+    ($trait:ident, $method:ident, $display:ident) => {
+        impl $trait for &str {
+            fn $method(&self) -> impl Display {
+                $display { string: self }
+            }
+        }
+
+        impl $trait for String {
+            fn $method(&self) -> impl Display {
+                $display {
+                    string: self.as_str(),
+                }
+            }
+        }
+
+        impl<T: $trait + ?Sized> $trait for &T {
+            fn $method(&self) -> impl Display {
+                $trait::$method(*self)
+            }
+        }
+
+        impl<T: $trait> $trait for Option<T> {
+            fn $method(&self) -> impl Display {
+                DisplayOptionWith {
+                    value: self.as_ref().map($trait::$method),
+                }
+            }
+        }
+
+        impl<T: $trait> $trait for Box<T> {
+            fn $method(&self) -> impl Display {
+                self.as_ref().$method()
+            }
+        }
+    };
+}
+
+// Numbers and booleans never contain characters that need escaping.
+macro_rules! impl_contextual_unescaped {
+    ($($t:ty),*) => {
+        $(
+            impl AsDisplayUrl for $t {
+                fn as_display_url(&self) -> impl Display {
+                    self
+                }
+            }
+
+            impl AsDisplayJs for $t {
+                fn as_display_js(&self) -> impl Display {
+                    self
+                }
+            }
+
+            impl AsDisplayAttr for $t {
+                fn as_display_attr(&self) -> impl Display {
+                    self
+                }
+            }
+
+            impl AsDisplayCss for $t {
+                fn as_display_css(&self) -> impl Display {
+                    self
+                }
+            }
+        )*
+    }
+}
+
+impl_contextual!(AsDisplayUrl, as_display_url, DisplayUrl);
+impl_contextual!(AsDisplayJs, as_display_js, DisplayJs);
+impl_contextual!(AsDisplayAttr, as_display_attr, DisplayAttr);
+impl_contextual!(AsDisplayCss, as_display_css, DisplayCss);
+
+impl_contextual_unescaped!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,6 +511,102 @@ mod tests {
         values: Vec<&'a str>,
     }
 
+    #[derive(WithRustyHandlebars)]
+    #[template(source = "<a href=\"{{url}}\">{{label}}</a>", minify = false)]
+    struct LinkTemplate<'a> {
+        url: &'a str,
+        label: &'a str,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(path = "examples/templates/conditions.rhbs", minify = false)]
+    struct ConditionsTemplate<'a> {
+        status: &'a str,
+        total: u32,
+        overdue: bool,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(path = "examples/templates/contexts.rhbs", minify = false)]
+    struct ContextsTemplate<'a> {
+        query: &'a str,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(path = "examples/templates/plain.txt.rhbs")]
+    struct PlainTemplate<'a> {
+        name: &'a str,
+        link: &'a str,
+    }
+
+    fn shout(value: &impl Display) -> impl Display {
+        value.to_string().to_uppercase()
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(source = "{{name}}!", escape = "self::shout")]
+    struct ShoutTemplate<'a> {
+        name: &'a str,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(
+        source = "{{#permission \"admin\"}}Admin{{else}}Guest{{/permission}}",
+        blocks = ["permission: if self.roles.contains(&{arg}) { {body} } else { {else} }"]
+    )]
+    struct PermissionTemplate<'a> {
+        roles: Vec<&'a str>,
+    }
+
+    fn repeat(text: &str, times: usize, separator: &str) -> String {
+        vec![text; times].join(separator)
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(
+        source = "{{repeat word}}|{{repeat word times=3 separator=\"-\"}}",
+        helpers = ["self::repeat(text, times = 2, separator = \"\")"]
+    )]
+    struct RepeatTemplate<'a> {
+        word: &'a str,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(
+        source = "{{#each_ref names}}{{#if @first}}{{@length}}: {{/if}}{{this}}{{#unless @last}}, {{/unless}}{{/each_ref}}"
+    )]
+    struct ListTemplate<'a> {
+        names: Vec<&'a str>,
+    }
+
+    #[allow(dead_code)]
+    enum OrderStatus {
+        Pending,
+        Paid,
+        Shipped { tracking: String },
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(path = "examples/templates/order.rhbs", minify = false)]
+    struct OrderTemplate {
+        status: OrderStatus,
+        total: u32,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(path = "examples/templates/partials.rhbs", minify = false)]
+    struct PartialsTemplate<'a> {
+        title: &'a str,
+        items: Vec<&'a str>,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(path = "examples/templates/layouts/page.rhbs", minify = false)]
+    struct LayoutTemplate<'a> {
+        title: &'a str,
+        body: &'a str,
+    }
+
     #[test]
     fn test_with_rusty_handlebars() {
         assert!(!TestTemplate {
@@ -255,6 +627,83 @@ mod tests {
         assert_eq!(None::<&str>.as_display_html().to_string(), "");
     }
 
+    #[test]
+    fn contextual_escapes() {
+        let value = "a b/\"'<é>\u{2028}";
+        assert_eq!(
+            value.as_display_url().to_string(),
+            "a%20b/%22%27%3C%C3%A9%3E%E2%80%A8"
+        );
+        assert_eq!(
+            value.as_display_js().to_string(),
+            "a b\\u002F\\u0022\\u0027\\u003Cé\\u003E\\u2028"
+        );
+        assert_eq!(
+            value.as_display_attr().to_string(),
+            "a&#x20;b&#x2F;&#x22;&#x27;&#x3C;é&#x3E;\u{2028}"
+        );
+        assert_eq!(
+            value.as_display_css().to_string(),
+            "a\\20 b\\2F \\22 \\27 \\3C é\\3E \u{2028}"
+        );
+        assert_eq!(Some(-1.5).as_display_attr().to_string(), "-1.5");
+        assert_eq!(None::<&str>.as_display_js().to_string(), "");
+    }
+
+    #[test]
+    fn contextual_helpers_select_escaping() {
+        assert_eq!(
+            ContextsTemplate { query: "x&y" }.to_string(),
+            "<a href=\"/search?q=x%26y\" title=x&#x26;y style=\"content: 'x\\26 y'\" onclick=\"go('x\\u0026y')\">x&amp;y</a>\n"
+        );
+    }
+
+    #[test]
+    fn declared_blocks_render() {
+        let render = |roles| PermissionTemplate { roles }.to_string();
+        assert_eq!(render(vec!["admin"]), "Admin");
+        assert_eq!(render(vec!["user"]), "Guest");
+    }
+
+    #[test]
+    fn helpers_take_named_arguments() {
+        assert_eq!(RepeatTemplate { word: "ab" }.to_string(), "abab|ab-ab-ab");
+    }
+
+    #[test]
+    fn each_exposes_first_last_and_length() {
+        let render = |names| ListTemplate { names }.to_string();
+        assert_eq!(render(vec!["a", "b", "c"]), "3: a, b, c");
+        assert_eq!(render(vec!["a"]), "1: a");
+        assert_eq!(render(vec![]), "");
+    }
+
+    #[test]
+    fn match_renders_enum_variants() {
+        let render = |status| OrderTemplate { status, total: 12 }.to_string();
+        assert_eq!(
+            render(OrderStatus::Shipped {
+                tracking: "<1Z>".to_string()
+            }),
+            "shipped: &lt;1Z&gt;\n"
+        );
+        assert_eq!(render(OrderStatus::Paid), "paid 12\n");
+        assert_eq!(render(OrderStatus::Pending), "pending\n");
+    }
+
+    #[test]
+    fn escape_modes() {
+        assert_eq!(
+            PlainTemplate {
+                name: "A&B",
+                link: "https://x.test/?a=1&b=2",
+            }
+            .to_string(),
+            "Hi A&B & co, see <https://x.test/?a=1&b=2>\n"
+        );
+        assert_eq!(ShoutTemplate { name: "<hey>" }.to_string(), "<HEY>!");
+    }
+
     #[test]
     fn derive_supports_bounded_and_const_generics() {
         let value = "generic";
@@ -266,6 +715,92 @@ mod tests {
         assert!(template.marker.is_empty());
     }
 
+    #[test]
+    fn partials_share_the_including_scope() {
+        assert_eq!(
+            PartialsTemplate {
+                title: "list",
+                items: vec!["a", "<b>"],
+            }
+            .to_string(),
+            "0:a of list;1:&lt;b&gt; of list;\n"
+        );
+    }
+
+    #[test]
+    fn layouts_render_child_blocks() {
+        assert_eq!(
+            LayoutTemplate {
+                title: "Home",
+                body: "Welcome",
+            }
+            .to_string(),
+            "<title>Home | Rusty Handlebars</title><main>Welcome</main>"
+        );
+    }
+
+    #[test]
+    fn condition_helpers_choose_the_first_matching_branch() {
+        let render = |status, total, overdue| {
+            ConditionsTemplate {
+                status,
+                total,
+                overdue,
+            }
+            .to_string()
+        };
+        assert_eq!(render("paid", 500, true), "paid\n");
+        assert_eq!(render("open", 500, false), "large\n");
+        assert_eq!(render("open", 5, false), "open\n");
+        assert_eq!(render("open", 500, true), "overdue\n");
+    }
+
+    #[test]
+    fn inline_sources_render_like_files() {
+        assert_eq!(
+            LinkTemplate {
+                url: "/home",
+                label: "<Home>",
+            }
+            .to_string(),
+            "<a href=\"/home\">&lt;Home&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn renders_to_writers_and_strings() {
+        let template = EachElseTemplate {
+            values: vec!["one", "two"],
+        };
+        let mut bytes = Vec::new();
+        template.render_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"onetwo\n");
+        let mut buf = String::from(">");
+        template.render_into(&mut buf).unwrap();
+        assert_eq!(buf, ">onetwo\n");
+        assert_eq!(template.size_hint(), "empty\n".len());
+    }
+
+    #[test]
+    fn templates_are_object_safe() {
+        let template = EachElseTemplate {
+            values: vec!["one"],
+        };
+        let templates: [&dyn WithRustyHandlebars; 2] = [
+            &template,
+            &LinkTemplate {
+                url: "/",
+                label: "Home",
+            },
+        ];
+        let mut buf = String::new();
+        for template in templates {
+            template.render_into(&mut buf).unwrap();
+        }
+        assert_eq!(buf, "one\n<a href=\"/\">Home</a>");
+        assert_eq!(templates[1].to_string(), "<a href=\"/\">Home</a>");
+    }
+
     #[test]
     fn each_else_renders_both_branches() {
         assert_eq!(EachElseTemplate { values: vec![] }.to_string(), "empty\n");
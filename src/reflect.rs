@@ -0,0 +1,338 @@
+//! Runtime access to template values, used by the `hot-reload` interpreter.
+//!
+//! ```rust
+//! use rusty_handlebars::Reflect;
+//!
+//! #[derive(Reflect)]
+//! struct User {
+//!     name: String,
+//!     admin: bool,
+//! }
+//!
+//! let user = User { name: "Ada".to_string(), admin: false };
+//! let mut out = String::new();
+//! user.field("name").unwrap().write(&mut out, true).unwrap().unwrap();
+//! assert_eq!(out, "Ada");
+//! assert!(!user.field("admin").unwrap().truthy());
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, LinkedList, VecDeque},
+    fmt::{self, Write},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::{AsBool, DisplayHtml};
+
+/// A value that templates can read without compiled code.
+///
+/// `#[derive(Reflect)]` implements this for structs, looking fields up by
+/// name. Fields whose types do not implement `Reflect` are left out, and a
+/// template that reads them falls back to its compiled rendering.
+/// `#[derive(WithRustyHandlebars)]` implements it for the template itself when
+/// the `hot-reload` feature is enabled.
+pub trait Reflect {
+    /// Returns the field called `name`, if it exists and implements `Reflect`.
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        let _ = name;
+        None
+    }
+
+    /// Returns the items iterated by `{{#each}}`, or `None` if this is not a
+    /// collection.
+    fn items(&self) -> Option<Vec<Item<'_>>> {
+        None
+    }
+
+    /// Returns the contents of an `Option`, or `None` if this is not one.
+    fn option(&self) -> Option<Option<&dyn Reflect>> {
+        None
+    }
+
+    /// Returns whether `{{#if}}` treats the value as true, as [`AsBool`] does.
+    fn truthy(&self) -> bool {
+        true
+    }
+
+    /// Writes the value, escaped as by `{{value}}` when `escape` is set.
+    ///
+    /// Returns `None` if the value cannot be written.
+    fn write(&self, out: &mut dyn Write, escape: bool) -> Option<fmt::Result> {
+        let _ = (out, escape);
+        None
+    }
+}
+
+/// One iteration of `{{#each}}`.
+pub struct Item<'a> {
+    /// The map key read by `@key`, or `None` for lists.
+    pub key: Option<&'a dyn Reflect>,
+    /// The value bound to `this` or the block's alias.
+    pub value: &'a dyn Reflect,
+}
+
+impl<'a> Item<'a> {
+    fn value(value: &'a dyn Reflect) -> Self {
+        Self { key: None, value }
+    }
+}
+
+impl Reflect for str {
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn write(&self, out: &mut dyn Write, escape: bool) -> Option<fmt::Result> {
+        Some(if escape {
+            write!(out, "{}", DisplayHtml { string: self })
+        } else {
+            out.write_str(self)
+        })
+    }
+}
+
+impl Reflect for String {
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn write(&self, out: &mut dyn Write, escape: bool) -> Option<fmt::Result> {
+        self.as_str().write(out, escape)
+    }
+}
+
+impl Reflect for char {
+    fn truthy(&self) -> bool {
+        AsBool::as_bool(self)
+    }
+
+    fn write(&self, out: &mut dyn Write, escape: bool) -> Option<fmt::Result> {
+        self.encode_utf8(&mut [0; 4]).write(out, escape)
+    }
+}
+
+macro_rules! impl_scalar {
+    ($($t:ty),*) => {
+        $(
+            impl Reflect for $t {
+                fn truthy(&self) -> bool {
+                    AsBool::as_bool(self)
+                }
+
+                fn write(&self, out: &mut dyn Write, _escape: bool) -> Option<fmt::Result> {
+                    Some(write!(out, "{self}"))
+                }
+            }
+        )*
+    }
+}
+
+impl_scalar!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool);
+
+macro_rules! impl_pointer {
+    ($($t:ty),*) => {
+        $(
+            impl<T: Reflect + ?Sized> Reflect for $t {
+                fn field(&self, name: &str) -> Option<&dyn Reflect> {
+                    (**self).field(name)
+                }
+
+                fn items(&self) -> Option<Vec<Item<'_>>> {
+                    (**self).items()
+                }
+
+                fn option(&self) -> Option<Option<&dyn Reflect>> {
+                    (**self).option()
+                }
+
+                fn truthy(&self) -> bool {
+                    (**self).truthy()
+                }
+
+                fn write(&self, out: &mut dyn Write, escape: bool) -> Option<fmt::Result> {
+                    (**self).write(out, escape)
+                }
+            }
+        )*
+    }
+}
+
+impl_pointer!(&T, Box<T>, Rc<T>, Arc<T>);
+
+impl<T: Reflect> Reflect for Option<T> {
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        self.as_ref()?.field(name)
+    }
+
+    fn items(&self) -> Option<Vec<Item<'_>>> {
+        self.as_ref().map_or(Some(Vec::new()), Reflect::items)
+    }
+
+    fn option(&self) -> Option<Option<&dyn Reflect>> {
+        Some(self.as_ref().map(|value| value as &dyn Reflect))
+    }
+
+    fn truthy(&self) -> bool {
+        self.as_ref().is_some_and(Reflect::truthy)
+    }
+
+    fn write(&self, out: &mut dyn Write, escape: bool) -> Option<fmt::Result> {
+        match self {
+            Some(value) => value.write(out, escape),
+            None => Some(Ok(())),
+        }
+    }
+}
+
+impl<T: Reflect> Reflect for [T] {
+    fn items(&self) -> Option<Vec<Item<'_>>> {
+        Some(self.iter().map(|value| Item::value(value)).collect())
+    }
+
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T: Reflect, const N: usize> Reflect for [T; N] {
+    fn items(&self) -> Option<Vec<Item<'_>>> {
+        self.as_slice().items()
+    }
+
+    fn truthy(&self) -> bool {
+        N > 0
+    }
+}
+
+macro_rules! impl_list {
+    ($($t:ty),*) => {
+        $(
+            impl<T: Reflect> Reflect for $t {
+                fn items(&self) -> Option<Vec<Item<'_>>> {
+                    Some(self.iter().map(|value| Item::value(value)).collect())
+                }
+
+                fn truthy(&self) -> bool {
+                    !self.is_empty()
+                }
+            }
+        )*
+    }
+}
+
+impl_list!(Vec<T>, VecDeque<T>, LinkedList<T>, BTreeSet<T>, HashSet<T>);
+
+macro_rules! impl_map {
+    ($($t:ty),*) => {
+        $(
+            impl<K: Reflect, V: Reflect> Reflect for $t {
+                fn items(&self) -> Option<Vec<Item<'_>>> {
+                    Some(
+                        self.iter()
+                            .map(|(key, value)| Item {
+                                key: Some(key),
+                                value,
+                            })
+                            .collect(),
+                    )
+                }
+
+                fn truthy(&self) -> bool {
+                    !self.is_empty()
+                }
+            }
+        )*
+    }
+}
+
+impl_map!(HashMap<K, V>, BTreeMap<K, V>);
+
+/// Wraps a field for `#[derive(Reflect)]`.
+///
+/// The derive calls `(&Probe(&self.field)).reflect()`. Method resolution picks
+/// [`ReflectProbe`] when the field implements [`Reflect`] and
+/// [`FallbackProbe`] otherwise, so fields of other types read as missing
+/// instead of failing to compile.
+#[doc(hidden)]
+pub struct Probe<'a, T: ?Sized>(pub &'a T);
+
+#[doc(hidden)]
+pub trait ReflectProbe<'a> {
+    fn reflect(&self) -> Option<&'a dyn Reflect>;
+}
+
+impl<'a, T: Reflect + 'a> ReflectProbe<'a> for Probe<'a, T> {
+    fn reflect(&self) -> Option<&'a dyn Reflect> {
+        Some(self.0)
+    }
+}
+
+#[doc(hidden)]
+pub trait FallbackProbe<'a> {
+    fn reflect(&self) -> Option<&'a dyn Reflect>;
+}
+
+impl<'a, T: ?Sized> FallbackProbe<'a> for &Probe<'a, T> {
+    fn reflect(&self) -> Option<&'a dyn Reflect> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::Reflect;
+
+    struct Opaque;
+
+    #[derive(Reflect)]
+    struct Order<'a> {
+        id: u32,
+        customer: &'a str,
+        lines: Vec<Line>,
+        note: Option<String>,
+        opaque: Opaque,
+    }
+
+    #[derive(Reflect)]
+    struct Line(&'static str, u8);
+
+    fn written(value: &dyn Reflect, escape: bool) -> String {
+        let mut out = String::new();
+        value.write(&mut out, escape).unwrap().unwrap();
+        out
+    }
+
+    #[test]
+    fn reads_derived_fields() {
+        let order = Order {
+            id: 7,
+            customer: "<Ada>",
+            lines: vec![Line("pen", 2)],
+            note: None,
+            opaque: Opaque,
+        };
+        assert_eq!(written(order.field("id").unwrap(), true), "7");
+        assert_eq!(
+            written(order.field("customer").unwrap(), true),
+            "&lt;Ada&gt;"
+        );
+        assert_eq!(written(order.field("customer").unwrap(), false), "<Ada>");
+        let lines = order.field("lines").unwrap().items().unwrap();
+        assert_eq!(written(lines[0].value.field("0").unwrap(), true), "pen");
+        assert!(!order.field("note").unwrap().truthy());
+        assert!(order.field("note").unwrap().option().unwrap().is_none());
+        assert!(order.field("opaque").is_none());
+        assert!(order.field("missing").is_none());
+    }
+
+    #[test]
+    fn iterates_maps() {
+        let map = BTreeMap::from([("a", 1), ("b", 0)]);
+        let items = map.items().unwrap();
+        assert_eq!(written(items[1].key.unwrap(), true), "b");
+        assert!(!items[1].value.truthy());
+    }
+}
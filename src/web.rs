@@ -0,0 +1,190 @@
+//! Web framework responses for derived templates.
+//!
+//! With the `axum`, `actix-web`, or `warp` feature enabled,
+//! `#[derive(WithRustyHandlebars)]` also implements that framework's response
+//! trait, so a handler can return the template itself:
+//!
+//! ```rust,ignore
+//! async fn profile() -> ProfileTemplate<'static> {
+//!     ProfileTemplate { name: "Ada" }
+//! }
+//! ```
+//!
+//! The `Content-Type` comes from the template's file name: `page.rhbs` and
+//! `page.html.rhbs` are `text/html`, `email.txt.rhbs` is `text/plain`, and so
+//! on. A template whose `Display` implementation fails is logged through the
+//! `log` crate and answered with an empty `500 Internal Server Error`.
+
+use std::fmt::{self, Display, Write};
+
+#[cfg(feature = "actix-web")]
+pub use actix_web;
+#[cfg(feature = "axum")]
+pub use axum_core;
+#[cfg(feature = "warp")]
+pub use warp;
+
+/// Renders `template` for a response body, logging the failure if it has one.
+fn render_body<T: Display + ?Sized>(template: &T, name: &str) -> Option<String> {
+    let mut body = String::new();
+    match write!(body, "{template}") {
+        Ok(()) => Some(body),
+        Err(fmt::Error) => {
+            log::error!("failed to render template {name}");
+            None
+        }
+    }
+}
+
+/// Builds the axum response for a derived template.
+#[cfg(feature = "axum")]
+#[doc(hidden)]
+pub fn axum_response<T: Display + ?Sized>(
+    template: &T,
+    name: &str,
+    content_type: &'static str,
+) -> axum_core::response::Response {
+    use axum_core::{body::Body, response::Response};
+    use http::{header::CONTENT_TYPE, HeaderValue, StatusCode};
+
+    match render_body(template, name) {
+        Some(body) => {
+            let mut response = Response::new(Body::from(body));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            response
+        }
+        None => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+/// Builds the actix-web response for a derived template.
+#[cfg(feature = "actix-web")]
+#[doc(hidden)]
+pub fn actix_response<T: Display + ?Sized>(
+    template: &T,
+    name: &str,
+    content_type: &'static str,
+) -> actix_web::HttpResponse {
+    use actix_web::HttpResponse;
+
+    match render_body(template, name) {
+        Some(body) => HttpResponse::Ok().content_type(content_type).body(body),
+        None => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Builds the warp response for a derived template.
+#[cfg(feature = "warp")]
+#[doc(hidden)]
+pub fn warp_response<T: Display + ?Sized>(
+    template: &T,
+    name: &str,
+    content_type: &'static str,
+) -> warp::reply::Response {
+    use warp::{
+        http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+        hyper::Body,
+        reply::Response,
+    };
+
+    match render_body(template, name) {
+        Some(body) => {
+            let mut response = Response::new(Body::from(body));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            response
+        }
+        None => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::render_body;
+    use crate::{AsDisplay, WithRustyHandlebars};
+
+    struct Failing;
+
+    impl fmt::Display for Failing {
+        fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+            Err(fmt::Error)
+        }
+    }
+
+    impl AsDisplay for Failing {
+        fn as_display(&self) -> impl fmt::Display {
+            self
+        }
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(source = "<p>{{name}}</p>")]
+    struct Page<'a> {
+        name: &'a str,
+    }
+
+    #[derive(WithRustyHandlebars)]
+    #[template(source = "{{{value}}}")]
+    struct Broken {
+        value: Failing,
+    }
+
+    #[test]
+    fn renders_or_reports_failure() {
+        assert_eq!(render_body("ok", "Ok").as_deref(), Some("ok"));
+        assert_eq!(render_body(&Failing, "Failing"), None);
+    }
+
+    #[cfg(feature = "axum")]
+    #[test]
+    fn axum_responses() {
+        use axum_core::response::IntoResponse;
+        use http::{header::CONTENT_TYPE, StatusCode};
+
+        let response = Page { name: "Ada" }.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        let response = Broken { value: Failing }.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[cfg(feature = "actix-web")]
+    #[test]
+    fn actix_responses() {
+        use actix_web::{http::StatusCode, Responder};
+
+        let request = actix_web::test::TestRequest::default().to_http_request();
+        let response = Page { name: "Ada" }.respond_to(&request);
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = Broken { value: Failing }.respond_to(&request);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[cfg(feature = "warp")]
+    #[test]
+    fn warp_responses() {
+        use warp::{
+            http::{header::CONTENT_TYPE, StatusCode},
+            Reply,
+        };
+
+        let response = Page { name: "Ada" }.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        let response = Broken { value: Failing }.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}